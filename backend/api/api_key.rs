@@ -0,0 +1,14 @@
+use ntex::web;
+use crate::controllers::api_key_controller;
+use crate::middlewares::access_middleware::Access;
+use crate::models::user::UserRole;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api-key")
+            .wrap(Access::min_role(UserRole::Admin).no_impersonation())
+            .route("", web::get().to(api_key_controller::list_api_keys))
+            .route("", web::post().to(api_key_controller::create_api_key))
+            .route("/{id}", web::delete().to(api_key_controller::revoke_api_key))
+    );
+}
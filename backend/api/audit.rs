@@ -0,0 +1,12 @@
+use ntex::web;
+use crate::controllers::audit_controller;
+use crate::middlewares::access_middleware::Access;
+use crate::models::permission;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/audit")
+            .wrap(Access::permission(permission::AUDIT_VIEW))
+            .route("", web::get().to(audit_controller::list_events))
+    );
+}
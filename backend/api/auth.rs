@@ -1,12 +1,31 @@
 use ntex::web;
-use crate::controllers::auth_controller;
+use crate::controllers::{auth_controller, mfa_controller, oidc_controller, override_controller};
+use crate::middlewares::access_middleware::{resource, Access};
+use crate::models::user::UserRole;
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
-            .route("/login", web::post().to(auth_controller::login))
-            .route("/register", web::post().to(auth_controller::register))
-            .route("/refresh", web::post().to(auth_controller::refresh_token))
-            .route("/logout", web::post().to(auth_controller::logout))
+            .service(resource("/login").wrap(Access::public()).route(web::post().to(auth_controller::login)))
+            .service(resource("/login/mfa").wrap(Access::public()).route(web::post().to(mfa_controller::login_mfa)))
+            .service(resource("/login/mfa/setup").wrap(Access::public()).route(web::post().to(mfa_controller::login_mfa_setup)))
+            .service(resource("/oidc/{provider}").wrap(Access::public()).route(web::get().to(oidc_controller::start_login)))
+            .service(resource("/oidc/{provider}/callback").wrap(Access::public()).route(web::post().to(oidc_controller::callback)))
+            .service(resource("/register").wrap(Access::public()).route(web::post().to(auth_controller::register)))
+            .service(resource("/verify-whatsapp").wrap(Access::public()).route(web::post().to(auth_controller::verify_whatsapp)))
+            .service(resource("/verify-whatsapp/resend").wrap(Access::public()).route(web::post().to(auth_controller::resend_whatsapp_verification)))
+            .service(resource("/refresh").wrap(Access::public()).route(web::post().to(auth_controller::refresh_token)))
+            .service(resource("/forgot").wrap(Access::public()).route(web::post().to(auth_controller::forgot_password)))
+            .service(resource("/reset").wrap(Access::public()).route(web::post().to(auth_controller::reset_password)))
+            .service(resource("/logout").wrap(Access::authenticated()).route(web::post().to(auth_controller::logout)))
+            .service(resource("/mfa/setup").wrap(Access::authenticated().no_impersonation()).route(web::post().to(mfa_controller::setup_mfa)))
+            .service(resource("/mfa/confirm").wrap(Access::authenticated().no_impersonation()).route(web::post().to(mfa_controller::confirm_mfa)))
+            .service(resource("/mfa/disable").wrap(Access::authenticated().no_impersonation()).route(web::post().to(mfa_controller::disable_mfa)))
+            .service(resource("/mfa/recovery-codes").wrap(Access::authenticated().no_impersonation()).route(web::post().to(mfa_controller::regenerate_recovery_codes)))
+            .service(resource("/pin").wrap(Access::authenticated()).route(web::post().to(auth_controller::pin_login)))
+            .service(resource("/pin/setup").wrap(Access::min_role(UserRole::Employee).no_impersonation()).route(web::post().to(auth_controller::setup_pin)))
+            .service(resource("/override").wrap(Access::authenticated().no_impersonation()).route(web::post().to(override_controller::approve)))
+            .service(resource("/impersonate").wrap(Access::authenticated()).route(web::delete().to(auth_controller::stop_impersonation)))
+            .service(resource("/impersonate/{id}").wrap(Access::min_role(UserRole::SuperAdmin).no_impersonation()).route(web::post().to(auth_controller::impersonate)))
     );
 }
\ No newline at end of file
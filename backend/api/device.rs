@@ -0,0 +1,14 @@
+use ntex::web;
+use crate::controllers::device_controller;
+use crate::middlewares::access_middleware::Access;
+use crate::models::user::UserRole;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/device")
+            .wrap(Access::min_role(UserRole::Admin).no_impersonation())
+            .route("", web::get().to(device_controller::list_devices))
+            .route("", web::post().to(device_controller::enroll_device))
+            .route("/{id}", web::delete().to(device_controller::revoke_device))
+    );
+}
\ No newline at end of file
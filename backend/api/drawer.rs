@@ -0,0 +1,12 @@
+use ntex::web;
+use crate::controllers::drawer_controller;
+use crate::middlewares::access_middleware::Access;
+use crate::models::user::UserRole;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/drawer")
+            .wrap(Access::min_role(UserRole::Employee).no_impersonation())
+            .route("/open", web::post().to(drawer_controller::open_drawer))
+    );
+}
\ No newline at end of file
@@ -0,0 +1,24 @@
+use ntex::web;
+use crate::controllers::employee_controller;
+use crate::middlewares::access_middleware::{resource, Access};
+use crate::models::permission;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/employee")
+            // Own profile
+            .service(resource("/me")
+                .route(web::get().to(employee_controller::get_my_profile)))
+
+            // Manager endpoints
+            .service(resource("")
+                .wrap(Access::permission(permission::EMPLOYEE_MANAGE).no_impersonation())
+                .route(web::get().to(employee_controller::list_employees))
+                .route(web::post().to(employee_controller::create_employee)))
+            .service(resource("/{id}")
+                .wrap(Access::permission(permission::EMPLOYEE_MANAGE).no_impersonation())
+                .route(web::get().to(employee_controller::get_employee))
+                .route(web::put().to(employee_controller::update_employee))
+                .route(web::delete().to(employee_controller::delete_employee)))
+    );
+}
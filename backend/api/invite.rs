@@ -0,0 +1,23 @@
+use ntex::web;
+use crate::controllers::invite_controller;
+use crate::middlewares::access_middleware::{resource, Access};
+use crate::models::permission;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/invite")
+            // Invitee endpoints
+            .service(resource("/accept")
+                .wrap(Access::public())
+                .route(web::post().to(invite_controller::accept_invite)))
+
+            // Admin-only endpoints
+            .service(resource("")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::get().to(invite_controller::list_invites))
+                .route(web::post().to(invite_controller::create_invite)))
+            .service(resource("/{id}")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::delete().to(invite_controller::revoke_invite)))
+    );
+}
@@ -1,2 +1,10 @@
 pub mod auth;
-pub mod user;
\ No newline at end of file
+pub mod user;
+pub mod well_known;
+pub mod role;
+pub mod device;
+pub mod audit;
+pub mod api_key;
+pub mod invite;
+pub mod employee;
+pub mod drawer;
\ No newline at end of file
@@ -0,0 +1,19 @@
+use ntex::web;
+use crate::controllers::role_controller;
+use crate::middlewares::access_middleware::Access;
+use crate::models::permission;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/role")
+            .wrap(Access::permission(permission::ROLE_MANAGE).no_impersonation())
+            .route("", web::get().to(role_controller::list_roles))
+            .route("", web::post().to(role_controller::create_role))
+            .route("/permissions", web::get().to(role_controller::list_permissions))
+            .route("/user/{user_id}", web::get().to(role_controller::get_user_roles))
+            .route("/user/{user_id}", web::put().to(role_controller::set_user_roles))
+            .route("/{id}", web::put().to(role_controller::update_role))
+            .route("/{id}", web::delete().to(role_controller::delete_role))
+            .route("/{id}/permissions", web::put().to(role_controller::set_role_permissions))
+    );
+}
\ No newline at end of file
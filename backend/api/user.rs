@@ -1,18 +1,51 @@
 use ntex::web;
-use crate::controllers::user_controller;
+use crate::controllers::{session_controller, user_controller, user_csv_controller};
+use crate::middlewares::access_middleware::{resource, Access};
+use crate::models::permission;
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
-    // cfg.service(
-    //     web::scope("/user")
-    //         // Current user endpoints
-    //         .route("/me", web::get().to(user_controller::get_current_user))
-    //         .route("/me/details", web::get().to(user_controller::get_user_details))
-    //         .route("/me/update", web::put().to(user_controller::update_user))
-    //         .route("/me/password", web::put().to(user_controller::update_password))
-            
-    //         // Admin-only endpoints
-    //         .route("", web::get().to(user_controller::list_users))
-    //         .route("/{id}", web::get().to(user_controller::admin_get_user))
-    //         .route("/{id}", web::put().to(user_controller::admin_update_user))
-    // );
+    cfg.service(
+        web::scope("/user")
+            // Current user endpoints
+            .service(resource("/me")
+                .route(web::get().to(user_controller::get_current_user))
+                .route(web::put().to(user_controller::update_current_user)))
+            .service(resource("/me/password")
+                .wrap(Access::authenticated().no_impersonation())
+                .route(web::put().to(user_controller::update_password)))
+            .service(resource("/me/sessions")
+                .wrap(Access::authenticated().no_impersonation())
+                .route(web::get().to(session_controller::list_my_sessions))
+                .route(web::delete().to(session_controller::logout_everywhere)))
+            .service(resource("/me/sessions/{session_id}")
+                .wrap(Access::authenticated().no_impersonation())
+                .route(web::delete().to(session_controller::revoke_my_session)))
+
+            // Admin-only endpoints
+            .service(resource("")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::get().to(user_controller::list_users))
+                .route(web::post().to(user_controller::create_user)))
+            .service(resource("/import")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::post().to(user_csv_controller::import_users)))
+            .service(resource("/export")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::get().to(user_csv_controller::export_users)))
+            .service(resource("/{id}")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::get().to(user_controller::get_user))
+                .route(web::put().to(user_controller::update_user))
+                .route(web::delete().to(user_controller::deactivate_user)))
+            .service(resource("/{id}/status")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::put().to(user_controller::update_user_status)))
+            .service(resource("/{id}/sessions")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::get().to(session_controller::list_user_sessions))
+                .route(web::delete().to(session_controller::logout_user_everywhere)))
+            .service(resource("/{id}/sessions/{session_id}")
+                .wrap(Access::permission(permission::USER_MANAGE).no_impersonation())
+                .route(web::delete().to(session_controller::revoke_user_session)))
+    );
 }
\ No newline at end of file
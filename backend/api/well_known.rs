@@ -0,0 +1,11 @@
+use ntex::web;
+use crate::controllers::auth_controller;
+use crate::middlewares::access_middleware::Access;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/.well-known")
+            .wrap(Access::public())
+            .route("/jwks.json", web::get().to(auth_controller::jwks))
+    );
+}
\ No newline at end of file
@@ -1,15 +1,38 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use ntex::web::{self, HttpServer};
 
+use crate::services::audit_service::AuditService;
+use crate::services::message_service::{message_sender_from_config, MessageSender};
+use crate::services::mfa_service::MfaService;
+use crate::services::oidc_service::OidcService;
+use crate::services::otp_service::OtpService;
+use crate::services::override_service::OverrideService;
+use crate::services::permission_service::PermissionService;
+use crate::services::pin_service::PinService;
+use crate::services::rate_limit_service::RateLimitService;
 use crate::services::redis_service::RedisService;
 use crate::services::session_service::SessionService;
 use crate::services::token_service::TokenService;
+use crate::services::user_csv_service::{self, ImportReport};
+use crate::services::user_state_service::UserStateService;
 use crate::{config::config::Config, database::DbPool};
-use crate::error::{Error, Result};
-use crate::api::{auth, user};
+use crate::error::{log_error, Error, Result};
+use crate::api::{api_key, audit, auth, device, drawer, employee, invite, role, user, well_known};
+use crate::models::audit_event::{self as audit_event, NewAuditEvent};
+use crate::models::user::{User, UserFilter, UserRole};
 use crate::seeds;
+use crate::utils::validation;
+
+const AUDIT_PURGE_INTERVAL: u64 = 3600; // in sec
+
+async fn purge_audit_events(state: &AppState) -> Result<usize> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    state.audit_service.purge_expired(&mut conn).await
+}
 
 async fn not_found() -> Result<web::HttpResponse> {
     Err(Error::ApiError(anyhow!("However, those stuffs aren't available.")))
@@ -23,6 +46,16 @@ pub struct AppState {
     pub token_service: TokenService,
     pub redis_service: Arc<RedisService>,
     pub session_service: SessionService,
+    pub permission_service: PermissionService,
+    pub pin_service: PinService,
+    pub rate_limit_service: RateLimitService,
+    pub mfa_service: MfaService,
+    pub otp_service: OtpService,
+    pub message_sender: Box<dyn MessageSender>,
+    pub audit_service: AuditService,
+    pub oidc_service: OidcService,
+    pub override_service: OverrideService,
+    pub user_state_service: UserStateService,
 }
 
 pub struct App {
@@ -32,13 +65,30 @@ pub struct App {
 impl App {
     pub async fn new() -> Result<Self> {
         let config = Config::from_env()?;
+        validation::init_password_policy(&config);
+        User::init_password_hashing(&config)?;
+
         let db_pool = DbPool::new(&config.database_url, config.database_pool_size)?;
 
         let redis_service = Arc::new(RedisService::new(&config).await?);
-        let token_service = TokenService::new(&config);
+        let token_service = TokenService::new(&config)?;
         let session_service = SessionService::new(redis_service.clone(), &config);
+        let permission_service = PermissionService::new(redis_service.clone(), &config);
+        let pin_service = PinService::new(redis_service.clone(), &config);
+        let rate_limit_service = RateLimitService::new(redis_service.clone(), &config);
+        let mfa_service = MfaService::new(redis_service.clone(), &config);
+        let otp_service = OtpService::new(redis_service.clone(), &config);
+        let message_sender = message_sender_from_config(&config)?;
+        let audit_service = AuditService::new(&config);
+        let oidc_service = OidcService::new(redis_service.clone(), &config)?;
+        let override_service = OverrideService::new(redis_service.clone());
+        let user_state_service = UserStateService::new(redis_service.clone(), &config);
 
-        let state = Arc::new(AppState { config, db_pool, token_service, redis_service, session_service });
+        let state = Arc::new(AppState {
+            config, db_pool, token_service, redis_service, session_service, permission_service,
+            pin_service, rate_limit_service, mfa_service, otp_service, message_sender, audit_service,
+            oidc_service, override_service, user_state_service
+        });
 
         Ok(App { state })
     }
@@ -62,11 +112,53 @@ impl App {
         Ok(())
     }
 
+    /// Imports users from a CSV file for `--import-users`. The operator has access to
+    /// the server, so they're treated like a SuperAdmin.
+    pub async fn import_users(&self, path: &str, dry_run: bool) -> Result<ImportReport> {
+        let input = std::fs::read_to_string(path).map_err(|e| Error::IoError(e.into()))?;
+
+        let mut conn = self.state.db_pool.get_connection().await?;
+
+        let event = |event_type: &str| NewAuditEvent::new(event_type, "cli".to_string(), "teapos --import-users".to_string());
+        user_csv_service::import_csv(&self.state, &input, UserRole::SuperAdmin, None, dry_run, &event, &mut conn).await
+    }
+
+    /// Exports every user as CSV for `--export-users`.
+    pub async fn export_users(&self) -> Result<String> {
+        let mut conn = self.state.db_pool.get_connection().await?;
+        let users = User::find_matching(&UserFilter::default(), &mut conn).await?;
+
+        let event = NewAuditEvent::new(audit_event::USER_EXPORT, "cli".to_string(), "teapos --export-users".to_string())
+            .details(serde_json::json!({ "users": users.len() }));
+        self.state.audit_service.record(event, &mut conn).await;
+
+        Ok(user_csv_service::export_csv(&users))
+    }
+
+    /// Purges expired audit events in the background, once now and then every hour.
+    fn spawn_audit_purge(&self) {
+        let state = self.state.clone();
+
+        ntex::rt::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(AUDIT_PURGE_INTERVAL));
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = purge_audit_events(&state).await {
+                    log_error(&e);
+                }
+            }
+        });
+    }
+
     pub async fn run(self) -> Result<()> {
         println!("TeaPOS backend is running at http://{}:{}", 
                  self.state.config.server_address, 
                  self.state.config.server_port);
         
+        self.spawn_audit_purge();
+
         let state = self.state.clone();
         
         let result = HttpServer::new(move || {
@@ -74,9 +166,18 @@ impl App {
             web::App::new()
                 .state(state.clone())
                 .wrap(crate::middlewares::auth_middleware::Auth)
+                .wrap(crate::middlewares::csrf_middleware::Csrf)
                 .wrap(crate::middlewares::response_middleware::Response)
                 .configure(auth::configure)
                 .configure(user::configure)
+                .configure(invite::configure)
+                .configure(employee::configure)
+                .configure(role::configure)
+                .configure(device::configure)
+                .configure(well_known::configure)
+                .configure(audit::configure)
+                .configure(api_key::configure)
+                .configure(drawer::configure)
                 .default_service(web::to(not_found))
         })
         .bind((self.state.config.server_address.clone(), self.state.config.server_port)).map_err(|e| Error::IoError(e.into()))?
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Path, State};
+use serde::Deserialize;
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::api_key::ApiKey;
+use crate::models::audit_event as audit;
+use crate::models::permission::Permission;
+use crate::models::user::UserRole;
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, message = "must grant at least one permission"))]
+    pub permissions: Vec<String>,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub store: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+pub async fn list_api_keys(state: State<Arc<AppState>>) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let api_keys = ApiKey::get_all(&mut conn).await?;
+
+    Ok(HttpResponse::Ok().json(&api_keys))
+}
+
+pub async fn create_api_key(state: State<Arc<AppState>>, req: ValidatedJson<CreateApiKeyRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+    let role = http_req.user_role()
+        .and_then(|role| role.parse::<UserRole>().ok())
+        .ok_or(Error::UnauthorizedError)?;
+
+    if req.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Err(Error::ApiError(anyhow!("'expires_at' must be in the future")));
+    }
+
+    let mut conn = state.db_pool.get_connection().await?;
+    Permission::find_by_codes(&req.permissions, &mut conn).await?;
+
+    // a key can't be handed more than its creator is allowed to do
+    for permission in &req.permissions {
+        if !state.permission_service.has_permission(user_id, role, permission, &mut conn).await? {
+            return Err(Error::ApiError(anyhow!("You can't grant the permission '{}'", permission)));
+        }
+    }
+
+    let (api_key, key) = ApiKey::create(
+        req.name.clone(),
+        req.permissions.clone(),
+        req.store.clone(),
+        req.expires_at.map(|expires_at| expires_at.naive_utc()),
+        user_id,
+        &mut conn
+    ).await?;
+
+    let event = audit_event(&http_req, audit::API_KEY_CREATE)
+        .details(json!({ "api_key_id": api_key.id, "name": api_key.name, "permissions": api_key.permissions, "store": api_key.store }));
+    state.audit_service.record(event, &mut conn).await;
+
+    // the key can't be recovered later, the integration has to store it now
+    let response = json!({
+        "api_key": api_key,
+        "key": key,
+    });
+
+    Ok(HttpResponse::Created().json(&response))
+}
+
+pub async fn revoke_api_key(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let api_key = ApiKey::find_by_id(path.0, &mut conn).await?;
+    api_key.revoke(&mut conn).await?;
+
+    let event = audit_event(&http_req, audit::API_KEY_REVOKE)
+        .details(json!({ "api_key_id": api_key.id, "name": api_key.name }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "API key revoked successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use ntex::web::HttpResponse;
+use ntex::web::types::{Query, State};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::app::AppState;
+use crate::error::Result;
+use crate::models::audit_event::{AuditEvent, AuditFilter};
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    pub event_type: Option<String>,
+    pub actor_id: Option<i64>,
+    pub target_id: Option<i64>,
+    pub from: Option<DateTime<Utc>>, // RFC 3339, inclusive
+    pub to: Option<DateTime<Utc>>, // RFC 3339, exclusive
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+pub async fn list_events(state: State<Arc<AppState>>, query: Query<AuditQuery>) -> Result<HttpResponse> {
+    let query = query.into_inner();
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    let filter = AuditFilter {
+        event_type: query.event_type,
+        actor_id: query.actor_id,
+        target_id: query.target_id,
+        from: query.from.map(|from| from.naive_utc()),
+        to: query.to.map(|to| to.naive_utc()),
+    };
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let (events, total) = AuditEvent::search(&filter, per_page, (page - 1).saturating_mul(per_page), &mut conn).await?;
+
+    let response = json!({
+        "events": events,
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+    });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
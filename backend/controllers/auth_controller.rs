@@ -1,156 +1,717 @@
 use chrono::Utc;
-use ntex::web::{self, HttpResponse};
-use ntex::web::types::{Json, State};
+use diesel_async::AsyncPgConnection;
+use ntex::http::header;
+use ntex::web::{self, HttpResponse, HttpResponseBuilder};
+use ntex::web::types::{Path, State};
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
+use validator::Validate;
 use std::sync::Arc;
 use anyhow::anyhow;
 
 use crate::app::AppState;
-use crate::error::{Result, Error};
+use crate::error::{log_error, Result, Error};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event::{self, NewAuditEvent};
+use crate::models::device::{Device, DeviceType};
+use crate::models::employee::{Employee, EmployeeError};
 use crate::models::user::{NewUser, User, UserRole};
-use crate::services::session_service::DeviceInfo;
+use crate::services::session_service::{DeviceInfo, EnrolledDevice};
+use crate::utils::{cookie, crypto, phone, validation};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct LoginRequest {
+    #[validate(length(min = 1, max = 255, message = "is required"))]
     pub username: String,
+    #[validate(length(min = 1, message = "is required"))]
     pub password: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct RefreshTokenRequest {
-    pub refresh_token: String,
-    pub device_id: String,
+    #[validate(length(min = 1, message = "is required"))]
+    pub refresh_token: Option<String>, // browsers in cookie mode send it as a cookie instead
+    #[validate(length(min = 1, max = 36, message = "must be 1 to 36 characters"))]
+    pub device_id: Option<String>, // only needed by clients that aren't enrolled devices
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct LogoutRequest {
-    pub refresh_token: String,
+    #[validate(length(min = 1, message = "is required"))]
+    pub refresh_token: Option<String>, // without it, the session of the access token is ended
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
+pub struct PinLoginRequest {
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub username: String,
+    #[validate(custom(function = "validation::validate_pin"))]
+    pub pin: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct PinSetupRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    pub password: String,
+    #[validate(custom(function = "validation::validate_pin"))]
+    pub pin: String,
+    #[validate(must_match(other = "pin", message = "doesn't match with 'pin'"))]
+    pub pin_confirm: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub username: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ResendVerificationRequest {
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub username: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct VerifyWhatsappRequest {
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub username: String,
+    #[validate(length(equal = 6, message = "must be 6 digits"))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub username: String,
+    #[validate(length(equal = 6, message = "must be 6 digits"))]
+    pub code: String,
+    #[validate(custom(function = "validation::validate_password"))]
+    pub password: String,
+    #[validate(must_match(other = "password", message = "doesn't match with 'password'"))]
+    pub password_confirm: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ImpersonateRequest {
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub reason: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct RegisterRequest {
+    #[validate(length(min = 3, max = 32, message = "must be 3 to 32 characters"), custom(function = "validation::validate_username"))]
     pub username: String,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
     pub fullname: String,
+    #[validate(custom(function = "validation::validate_password"))]
     pub password: String,
+    #[validate(must_match(other = "password", message = "doesn't match with 'password'"))]
     pub password_confirm: String,
+    #[validate(length(min = 1, max = 32, message = "is required"))]
     pub whatsapp: String
 }
 
-pub async fn login(state: State<Arc<AppState>>, req: Json<LoginRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+/// Authenticates the calling device when it presents an enrolled device credential
+/// (`X-Device-Id` + `X-Device-Secret`), browsers and other unenrolled clients get `None`.
+async fn authenticate_device(http_req: &web::HttpRequest, conn: &mut AsyncPgConnection) -> Result<Option<Device>> {
+    let header = |name: &str| http_req.headers().get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+
+    match (header("X-Device-Id"), header("X-Device-Secret")) {
+        (Some(device_uid), Some(device_secret)) => Ok(Some(Device::authenticate(&device_uid, &device_secret, conn).await?)),
+        _ => Ok(None)
+    }
+}
+
+pub(crate) fn client_ip(http_req: &web::HttpRequest) -> String {
+    http_req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or("Unknown".to_string())
+}
+
+fn user_agent(http_req: &web::HttpRequest) -> String {
+    http_req.headers().get("User-Agent")
+        .map(|v| v.to_str().unwrap_or("Unknown").to_string())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+/// Starts an audit event for the request, with its IP address and user agent filled in.
+/// Authenticated requests are attributed to the caller, impersonated ones to the
+/// SuperAdmin behind them.
+pub(crate) fn audit_event(http_req: &web::HttpRequest, event_type: &str) -> NewAuditEvent {
+    let event = NewAuditEvent::new(event_type, client_ip(http_req), user_agent(http_req));
+
+    match http_req.impersonator_id().or(http_req.user_id()) {
+        Some(actor_id) => event.actor(actor_id),
+        None => event
+    }
+}
+
+fn device_info(http_req: &web::HttpRequest, device_id: String, device: Option<&Device>) -> DeviceInfo {
+    DeviceInfo {
+        user_agent: user_agent(http_req),
+        ip_address: client_ip(http_req),
+        device_id,
+        enrolled_device: device.map(|d| EnrolledDevice {
+            id: d.id,
+            name: d.name.clone(),
+            store: d.store.clone(),
+            device_type: d.device_type.to_string(),
+        }),
+        last_active: Utc::now()
+    }
+}
+
+pub async fn login(state: State<Arc<AppState>>, req: ValidatedJson<LoginRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("login", &client_ip(&http_req)).await?;
+    state.rate_limit_service.limit_username("login", &req.username).await?;
+    state.rate_limit_service.ensure_login_allowed(&req.username).await?;
+
     let mut conn = state.db_pool.get_connection().await?;
 
-    let user = match User::find_by_username(&req.username, &mut conn).await {
+    // unknown usernames count as failures too, so they can't be told apart from locked accounts
+    let mut user = match User::find_by_username(&req.username, &mut conn).await {
         Ok(user) => user,
-        Err(_) => return Err(Error::ApiError(anyhow!("Invalid credentials")))
+        Err(_) => {
+            let event = audit_event(&http_req, audit_event::LOGIN_FAILURE)
+                .details(json!({ "method": "password", "username": req.username, "reason": "unknown_user" }));
+            state.audit_service.record(event, &mut conn).await;
+
+            state.rate_limit_service.register_login_failure(&req.username).await?;
+            return Err(Error::ApiError(anyhow!("Invalid credentials")));
+        }
     };
 
     if !User::verify_password(&user.password, &req.password)? {
+        let event = audit_event(&http_req, audit_event::LOGIN_FAILURE)
+            .target(user.id)
+            .details(json!({ "method": "password", "username": user.username, "reason": "invalid_password" }));
+        state.audit_service.record(event, &mut conn).await;
+
+        state.rate_limit_service.register_login_failure(&req.username).await?;
         return Err(Error::ApiError(anyhow!("Invalid credentials")));
     }
-    
-    let (access_token, refresh_token) = state.token_service.generate_tokens(&user)?;
 
-    let refresh_claims = state.token_service.verify_refresh_token(&refresh_token)?;
+    // only told once the password is right, so the status doesn't leak to anyone guessing
+    if let Err(e) = user.ensure_active() {
+        let event = audit_event(&http_req, audit_event::LOGIN_FAILURE)
+            .target(user.id)
+            .details(json!({ "method": "password", "username": user.username, "reason": user.status.to_string() }));
+        state.audit_service.record(event, &mut conn).await;
 
-    let device_info = DeviceInfo { 
-        user_agent: http_req.headers().get("User-Agent")
-            .map(|v| v.to_str().unwrap_or("Unknown").to_string())
-            .unwrap_or_else(|| "Unknown".to_string()),
-        ip_address: http_req.peer_addr()
-            .map(|addr| addr.ip().to_string())
-            .unwrap_or("Unknown".to_string()),
-        device_id: Uuid::new_v4().to_string(),
-        last_active: Utc::now()
+        return Err(e);
+    }
+
+    // staff who have left keep their account and history, but can't sign in anymore
+    if Employee::is_terminated(user.id, &mut conn).await? {
+        let event = audit_event(&http_req, audit_event::LOGIN_FAILURE)
+            .target(user.id)
+            .details(json!({ "method": "password", "username": user.username, "reason": "terminated" }));
+        state.audit_service.record(event, &mut conn).await;
+
+        return Err(EmployeeError::Terminated.into());
+    }
+
+    // upgrade hashes made with older Argon2 parameters while we have the plain text
+    if User::needs_rehash(&user.password) {
+        user.password = User::hash_password(&req.password)?;
+        user = rehash_or_keep(user, &mut conn).await;
+    }
+
+    // the password alone isn't enough, hand out a challenge for the second step instead of tokens.
+    // failures are only reset once that step succeeded, so it shares the lockout with the password
+    if state.mfa_service.is_challenged(&user) {
+        let mfa_token = state.token_service.generate_mfa_token(&user)?;
+
+        let response = json!({
+            "message": "Two-factor authentication required",
+            "mfa_required": true,
+            "mfa_setup_required": !user.has_mfa(),
+        });
+
+        return Ok(HttpResponse::Ok()
+            .set_header("X-Mfa-Token", mfa_token)
+            .json(&response));
+    }
+
+    state.rate_limit_service.reset_login_failures(&req.username).await;
+
+    let mut response = start_session(&state, &http_req, &user, &mut conn).await?;
+
+    let event = audit_event(&http_req, audit_event::LOGIN_SUCCESS)
+        .actor(user.id)
+        .target(user.id)
+        .details(json!({ "method": "password" }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(response.json(&json!({ "message": "Login successful" })))
+}
+
+/// Persists a rehashed password/PIN. Failing to do so must not fail the login, the
+/// old hash is still valid and the next login will try again.
+async fn rehash_or_keep(user: User, conn: &mut AsyncPgConnection) -> User {
+    match user.update(conn).await {
+        Ok(updated) => updated,
+        Err(e) => {
+            log_error(&Error::GeneralError(anyhow!("Failed to store rehashed credentials of user {}: {}", user.id, e)));
+            user
+        }
+    }
+}
+
+// browsers opt into cookie mode when logging in, by sending `X-Auth-Mode: cookie`
+fn wants_cookie_mode(state: &AppState, http_req: &web::HttpRequest) -> bool {
+    state.config.cookie_auth
+        && http_req.headers().get("X-Auth-Mode").and_then(|v| v.to_str().ok()) == Some("cookie")
+}
+
+/// Hands the refresh token to a browser in cookie mode, along with the CSRF token it
+/// has to send back in `X-CSRF-Token` (see `csrf_middleware`).
+fn set_session_cookies(state: &AppState, response: &mut HttpResponseBuilder, refresh_token: &str, csrf_token: &str) {
+    let (max_age, secure) = (state.config.ref_token_expiry, state.config.cookie_secure);
+
+    response
+        .header(header::SET_COOKIE, cookie::build(cookie::REFRESH_COOKIE, refresh_token, cookie::REFRESH_COOKIE_PATH, max_age, true, secure))
+        .header(header::SET_COOKIE, cookie::build(cookie::CSRF_COOKIE, csrf_token, "/", max_age, false, secure));
+}
+
+fn clear_session_cookies(state: &AppState, response: &mut HttpResponseBuilder) {
+    let secure = state.config.cookie_secure;
+
+    response
+        .header(header::SET_COOKIE, cookie::build(cookie::REFRESH_COOKIE, "", cookie::REFRESH_COOKIE_PATH, 0, true, secure))
+        .header(header::SET_COOKIE, cookie::build(cookie::CSRF_COOKIE, "", "/", 0, false, secure));
+}
+
+/// Opens a new session for the user, responding with its tokens and device id.
+pub(crate) async fn start_session(state: &AppState, http_req: &web::HttpRequest, user: &User, conn: &mut AsyncPgConnection) -> Result<HttpResponseBuilder> {
+    let session_id = Uuid::new_v4().to_string();
+    let cookie_mode = wants_cookie_mode(state, http_req);
+
+    let (access_token, refresh_token) = if cookie_mode {
+        state.token_service.generate_cookie_tokens(user, &session_id)?
+    } else {
+        state.token_service.generate_tokens(user, &session_id)?
     };
 
-    state.session_service.create_session(user.id, device_info, &refresh_claims.jti).await?;
+    let refresh_claims = state.token_service.verify_refresh_token(&refresh_token)?;
 
-    let response = json!({ "message": "Login successful" });
-    
-    Ok(HttpResponse::Ok()
+    // unenrolled clients get a fresh device id, they have to send it back when refreshing
+    let device = authenticate_device(http_req, conn).await?;
+    let device_id = device.as_ref()
+        .map(|d| d.device_uid.clone())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let device_info = device_info(http_req, device_id.clone(), device.as_ref());
+
+    state.session_service.create_session(user.id, device_info, &refresh_claims.jti, &session_id).await?;
+
+    let mut response = HttpResponse::Ok();
+    response
         .set_header("X-Access-Token", access_token)
-        .set_header("X-Refresh-Token", refresh_token)
-        .json(&response))
+        .set_header("X-Device-Id", device_id);
+
+    // a new login always gets a new CSRF token
+    if cookie_mode {
+        set_session_cookies(state, &mut response, &refresh_token, &crypto::random_token(24));
+    } else {
+        response.set_header("X-Refresh-Token", refresh_token);
+    }
+
+    Ok(response)
 }
 
-pub async fn refresh_token(state: State<Arc<AppState>>, req: Json<RefreshTokenRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
-    let refresh_claims = state.token_service.verify_refresh_token(&req.refresh_token)?;
-    
-    let current_device_info = DeviceInfo {
-        user_agent: http_req.headers().get("User-Agent")
-            .map(|v| v.to_str().unwrap_or("Unknown").to_string())
-            .unwrap_or_else(|| "Unknown".to_string()),
-        ip_address: http_req.peer_addr()
-            .map(|addr| addr.ip().to_string())
-            .unwrap_or("Unknown".to_string()),
-        device_id: req.device_id.clone(),
-        last_active: Utc::now()
-    };
-    
-    let session = match state.session_service.validate_session(&refresh_claims.jti, current_device_info).await {
-        Ok(session) => session,
-        Err(e) => { 
-            return Err(Error::ApiError(anyhow!("Failed to invalidate session: {}", e)))
-        }
+pub async fn refresh_token(state: State<Arc<AppState>>, req: ValidatedJson<RefreshTokenRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("refresh", &client_ip(&http_req)).await?;
+
+    let cookie_token = cookie::get(http_req.headers(), cookie::REFRESH_COOKIE).filter(|_| state.config.cookie_auth);
+
+    let (presented_token, cookie_mode) = match (&req.refresh_token, cookie_token) {
+        (Some(token), _) => (token.clone(), false),
+        (None, Some(token)) => (token, true),
+        (None, None) => return Err(Error::ApiError(anyhow!("'refresh_token' is required")))
     };
 
+    let refresh_claims = state.token_service.verify_refresh_token(&presented_token)?;
+
+    state.rate_limit_service.limit_username("refresh", &refresh_claims.sub.to_string()).await?;
+
     let mut conn = state.db_pool.get_connection().await?;
-    let user = User::find_by_id(session.user_id, &mut conn).await?;
-
-    let access_token = state.token_service.generate_access_token(&user)?;
-
-    let device_info = DeviceInfo {
-        user_agent: http_req.headers().get("User-Agent")
-            .map(|v| v.to_str().unwrap_or("Unknown").to_string())
-            .unwrap_or_else(|| "Unknown".to_string()),
-        ip_address: http_req.peer_addr()
-            .map(|addr| addr.ip().to_string())
-            .unwrap_or("Unknown".to_string()),
-        device_id: session.device_info.device_id,
-        last_active: Utc::now()
-    };
+    let user = User::find_by_id(refresh_claims.sub, &mut conn).await?;
+
+    // a role or status change since the login ends the session, the user has to sign in again.
+    // so does an employee's termination date passing
+    if user.ensure_active().is_err() || refresh_claims.ver != user.token_version || Employee::is_terminated(user.id, &mut conn).await? {
+        state.session_service.invalidate_session(&refresh_claims.jti).await?;
+        return Err(Error::ApiError(anyhow!("Session is no longer valid, please sign in again")));
+    }
 
-    state.session_service.update_session_activity(&refresh_claims.jti, device_info).await?;
+    // every refresh hands out a brand new refresh token, the presented one is retired
+    let (access_token, refresh_token) = if cookie_mode {
+        state.token_service.generate_cookie_tokens(&user, &refresh_claims.sid)?
+    } else {
+        state.token_service.generate_tokens(&user, &refresh_claims.sid)?
+    };
 
-    let _user_id = user.id;
+    let new_refresh_claims = state.token_service.verify_refresh_token(&refresh_token)?;
 
-    let response = json!({ "message": "Token refreshed successfully" });
+    let device = authenticate_device(&http_req, &mut conn).await?;
+    let device_id = match (&device, &req.device_id) {
+        (Some(device), _) => device.device_uid.clone(),
+        (None, Some(device_id)) => device_id.clone(),
+        (None, None) => return Err(Error::ApiError(anyhow!("'device_id' is required")))
+    };
     
-    Ok(HttpResponse::Ok()
-        .set_header("X-Access-Token", access_token)
-        .json(&response))
+    let current_device_info = device_info(&http_req, device_id, device.as_ref());
+    
+    if let Err(e) = state.session_service.rotate_session(&refresh_claims.jti, &new_refresh_claims.jti, current_device_info).await {
+        return Err(Error::ApiError(anyhow!("Failed to refresh session: {}", e)));
+    }
+
+    let event = audit_event(&http_req, audit_event::TOKEN_REFRESH)
+        .actor(user.id)
+        .target(user.id)
+        .details(json!({ "session_id": refresh_claims.sid }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let mut response = HttpResponse::Ok();
+    response.set_header("X-Access-Token", access_token);
+
+    // the CSRF token stays the same, other tabs of the browser keep using it
+    if cookie_mode {
+        let csrf_token = cookie::get(http_req.headers(), cookie::CSRF_COOKIE).unwrap_or_else(|| crypto::random_token(24));
+        set_session_cookies(&state, &mut response, &refresh_token, &csrf_token);
+    } else {
+        response.set_header("X-Refresh-Token", refresh_token);
+    }
+
+    Ok(response.json(&json!({ "message": "Token refreshed successfully" })))
 }
 
-pub async fn register(state: State<Arc<AppState>>, req: Json<RegisterRequest>) -> Result<HttpResponse> {
+pub async fn register(state: State<Arc<AppState>>, req: ValidatedJson<RegisterRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("register", &client_ip(&http_req)).await?;
+    state.rate_limit_service.limit_username("register", &req.username).await?;
+
     let mut conn = state.db_pool.get_connection().await?;
     
-    if req.password != req.password_confirm {
-        return Err(Error::ApiError(anyhow!("'password_confirm' doesn't match with 'password'")));
+    let whatsapp = phone::normalize_e164(&req.whatsapp, &state.config.default_country_code)
+        .ok_or_else(|| Error::ValidationError(validation::field_error("whatsapp", "phone_invalid", "is not a valid phone number")))?;
+
+    if User::is_whatsapp_taken(&whatsapp, &mut conn).await? {
+        return Err(Error::ApiError(anyhow!("WhatsApp number '{}' is already in use", whatsapp)));
     }
 
     let new_user = NewUser {
         username: req.username.clone(),
         fullname: req.fullname.clone(),
         password: req.password.clone(),
-        whatsapp: req.whatsapp.clone(),
-        role: UserRole::User
+        whatsapp,
+        role: UserRole::User,
+        whatsapp_verified_at: None
     };
     
-    let _user = User::create_and_return(new_user, &mut conn).await?;
+    let user = User::create_and_return(new_user, &mut conn).await?;
+
+    send_whatsapp_verification(&state, &user).await?;
     
-    let response = json!({ "message": "User registered successfully" });
+    let response = json!({ "message": "User registered successfully, enter the code sent to your WhatsApp to verify your number" });
     
     Ok(HttpResponse::Created().json(&response))
 }
 
-pub async fn logout(state: State<Arc<AppState>>, req: Json<LogoutRequest>) -> Result<HttpResponse> {
-    let refresh_claims = state.token_service.verify_refresh_token(&req.refresh_token)?;
-    
-    state.session_service.invalidate_session(&refresh_claims.jti).await?;
+pub(crate) async fn send_whatsapp_verification(state: &AppState, user: &User) -> Result<()> {
+    state.otp_service.send(state.message_sender.as_ref(), "whatsapp_verify", user.id, &user.whatsapp, |code, minutes| format!(
+        "Your TeaPOS verification code is {}. It expires in {} minutes.",
+        code, minutes
+    )).await
+}
+
+pub async fn verify_whatsapp(state: State<Arc<AppState>>, req: ValidatedJson<VerifyWhatsappRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("verify_whatsapp", &client_ip(&http_req)).await?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let user = match User::find_by_username(&req.username, &mut conn).await {
+        Ok(user) if !user.is_whatsapp_verified() => user,
+        _ => return Err(Error::ApiError(anyhow!("Invalid or expired code")))
+    };
+
+    if !state.otp_service.verify("whatsapp_verify", user.id, &req.code).await? {
+        return Err(Error::ApiError(anyhow!("Invalid or expired code")));
+    }
+
+    user.mark_whatsapp_verified(&mut conn).await?;
+
+    let response = json!({ "message": "WhatsApp number verified successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn resend_whatsapp_verification(state: State<Arc<AppState>>, req: ValidatedJson<ResendVerificationRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("verify_whatsapp_resend", &client_ip(&http_req)).await?;
+    state.rate_limit_service.limit_username("verify_whatsapp_resend", &req.username).await?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    if let Ok(user) = User::find_by_username(&req.username, &mut conn).await && !user.is_whatsapp_verified() {
+        send_whatsapp_verification(&state, &user).await?;
+    }
+
+    let response = json!({ "message": "If the number still needs verifying, a new code has been sent" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn logout(state: State<Arc<AppState>>, req: ValidatedJson<LogoutRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let access_claims = http_req.token_claims().ok_or(Error::UnauthorizedError)?;
+
+    // browsers in cookie mode only send their refresh token to `/auth/refresh`
+    let (token_id, session_id, user_id) = match &req.refresh_token {
+        Some(refresh_token) => {
+            let refresh_claims = state.token_service.verify_refresh_token(refresh_token)?;
+            (refresh_claims.jti, refresh_claims.sid, refresh_claims.sub)
+        },
+        None => {
+            let (token_id, session) = state.session_service.get_session(&access_claims.sid).await?;
+            (token_id, access_claims.sid.clone(), session.user_id)
+        }
+    };
+
+    if user_id != access_claims.sub {
+        return Err(Error::ForbiddenError);
+    }
     
-    let response = json!({ "message": "Logout successful" });
+    state.session_service.invalidate_session(&token_id).await?;
+    state.session_service.revoke_access_token(&access_claims).await?;
+
+    let event = audit_event(&http_req, audit_event::LOGOUT)
+        .target(access_claims.sub)
+        .details(json!({ "session_id": session_id }));
+
+    let mut conn = state.db_pool.get_connection().await?;
+    state.audit_service.record(event, &mut conn).await;
     
+    let mut response = HttpResponse::Ok();
+
+    if state.config.cookie_auth && cookie::get(http_req.headers(), cookie::CSRF_COOKIE).is_some() {
+        clear_session_cookies(&state, &mut response);
+    }
+
+    Ok(response.json(&json!({ "message": "Logout successful" })))
+}
+
+pub async fn forgot_password(state: State<Arc<AppState>>, req: ValidatedJson<ForgotPasswordRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("forgot", &client_ip(&http_req)).await?;
+    state.rate_limit_service.limit_username("forgot", &req.username).await?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    // the response is the same whether or not the account exists. codes only go to verified
+    // numbers, a mistyped one must not let a stranger take the account over
+    if let Ok(user) = User::find_by_username(&req.username, &mut conn).await
+        && user.is_whatsapp_verified() {
+        state.otp_service.send(state.message_sender.as_ref(), "password_reset", user.id, &user.whatsapp, |code, minutes| format!(
+            "Your TeaPOS password reset code is {}. It expires in {} minutes, don't share it with anyone.",
+            code, minutes
+        )).await?;
+    }
+
+    let response = json!({ "message": "If the account exists, a reset code has been sent to its WhatsApp number" });
+
     Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn reset_password(state: State<Arc<AppState>>, req: ValidatedJson<ResetPasswordRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("reset", &client_ip(&http_req)).await?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let mut user = match User::find_by_username(&req.username, &mut conn).await {
+        Ok(user) => user,
+        Err(_) => return Err(Error::ApiError(anyhow!("Invalid or expired code")))
+    };
+
+    if !state.otp_service.verify("password_reset", user.id, &req.code).await? {
+        return Err(Error::ApiError(anyhow!("Invalid or expired code")));
+    }
+
+    user.password = User::hash_password(&req.password)?;
+    user.update(&mut conn).await?;
+
+    // whoever had the old password shouldn't stay signed in
+    state.session_service.logout_all_sessions(user.id).await?;
+    state.rate_limit_service.reset_login_failures(&user.username).await;
+
+    let event = audit_event(&http_req, audit_event::PASSWORD_RESET)
+        .actor(user.id)
+        .target(user.id)
+        .details(json!({ "method": "whatsapp_otp" }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Password has been reset, please sign in again" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Lets a SuperAdmin see the app exactly as another user does. The token carries the
+/// SuperAdmin in its `act` claim, can't be refreshed and is kept away from sensitive
+/// routes (see `Access::no_impersonation`).
+pub async fn impersonate(state: State<Arc<AppState>>, path: Path<(i64,)>, req: ValidatedJson<ImpersonateRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let claims = http_req.token_claims().ok_or(Error::UnauthorizedError)?;
+
+    if claims.act.is_some() {
+        return Err(Error::ApiError(anyhow!("Stop the current impersonation first")));
+    }
+
+    if claims.sub == path.0 {
+        return Err(Error::ApiError(anyhow!("You can't impersonate yourself")));
+    }
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(path.0, &mut conn).await?;
+
+    if user.role == UserRole::SuperAdmin {
+        return Err(Error::ForbiddenError);
+    }
+
+    user.ensure_active()?;
+
+    let (access_token, impersonation_claims) = state.token_service.generate_impersonation_token(&user, claims.sub, &claims.sid)?;
+
+    let event = audit_event(&http_req, audit_event::IMPERSONATION_START)
+        .target(user.id)
+        .details(json!({ "reason": req.reason, "token_id": impersonation_claims.jti }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({
+        "message": format!("You are now acting as '{}'", user.username),
+        "impersonating": user.id,
+        "expires_at": impersonation_claims.exp,
+    });
+
+    Ok(HttpResponse::Ok()
+        .set_header("X-Access-Token", access_token)
+        .json(&response))
+}
+
+pub async fn stop_impersonation(state: State<Arc<AppState>>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let claims = http_req.token_claims().ok_or(Error::UnauthorizedError)?;
+
+    if claims.act.is_none() {
+        return Err(Error::ApiError(anyhow!("You are not impersonating anyone")));
+    }
+
+    state.session_service.revoke_access_token(&claims).await?;
+
+    let event = audit_event(&http_req, audit_event::IMPERSONATION_STOP)
+        .target(claims.sub)
+        .details(json!({ "token_id": claims.jti }));
+
+    let mut conn = state.db_pool.get_connection().await?;
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Impersonation stopped" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn pin_login(state: State<Arc<AppState>>, req: ValidatedJson<PinLoginRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let terminal_claims = http_req.token_claims().ok_or(Error::UnauthorizedError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    // PIN login only works on an enrolled POS terminal, with the terminal's own session
+    let device = authenticate_device(&http_req, &mut conn).await?
+        .filter(|device| device.device_type == DeviceType::Pos)
+        .ok_or_else(|| Error::ApiError(anyhow!("This device is not an enrolled terminal")))?;
+
+    let (_, terminal_session) = state.session_service.get_session(&terminal_claims.sid).await
+        .map_err(|_| Error::ForbiddenError)?;
+
+    if terminal_session.device_info.enrolled_device.as_ref().map(|d| d.id) != Some(device.id) {
+        return Err(Error::ApiError(anyhow!("This device is not an enrolled terminal")));
+    }
+
+    let mut user = match User::find_by_username(&req.username, &mut conn).await {
+        Ok(user) if user.role == UserRole::Employee => user,
+        _ => return Err(Error::ApiError(anyhow!("Invalid credentials")))
+    };
+
+    state.pin_service.ensure_not_locked(user.id).await?;
+
+    let pin_hash = user.pin.as_deref().ok_or_else(|| Error::ApiError(anyhow!("Invalid credentials")))?;
+
+    if !User::verify_password(pin_hash, &req.pin)? {
+        let event = audit_event(&http_req, audit_event::LOGIN_FAILURE)
+            .target(user.id)
+            .details(json!({ "method": "pin", "username": user.username, "device_id": device.id, "reason": "invalid_pin" }));
+        state.audit_service.record(event, &mut conn).await;
+
+        state.pin_service.register_failure(user.id).await?;
+        return Err(Error::ApiError(anyhow!("Invalid credentials")));
+    }
+
+    user.ensure_active()?;
+
+    if Employee::is_terminated(user.id, &mut conn).await? {
+        return Err(EmployeeError::Terminated.into());
+    }
+
+    if User::needs_rehash(pin_hash) {
+        user.pin = Some(User::hash_password(&req.pin)?);
+        user = rehash_or_keep(user, &mut conn).await;
+    }
+
+    state.pin_service.reset(user.id).await;
+
+    let access_token = state.token_service.generate_pin_access_token(&user, &terminal_claims.sid)?;
+
+    let event = audit_event(&http_req, audit_event::LOGIN_SUCCESS)
+        .actor(user.id)
+        .target(user.id)
+        .details(json!({ "method": "pin", "device_id": device.id }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Login successful" });
+
+    Ok(HttpResponse::Ok()
+        .set_header("X-Access-Token", access_token)
+        .json(&response))
+}
+
+pub async fn setup_pin(state: State<Arc<AppState>>, req: ValidatedJson<PinSetupRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let mut user = User::find_by_id(user_id, &mut conn).await?;
+
+    if user.role != UserRole::Employee {
+        return Err(Error::ApiError(anyhow!("Only employees can use a PIN")));
+    }
+
+    if !User::verify_password(&user.password, &req.password)? {
+        return Err(Error::ApiError(anyhow!("Invalid credentials")));
+    }
+
+    user.pin = Some(User::hash_password(&req.pin)?);
+    user.update(&mut conn).await?;
+
+    state.pin_service.reset(user.id).await;
+
+    let response = json!({ "message": "PIN updated successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn jwks(state: State<Arc<AppState>>) -> Result<HttpResponse> {
+    let body = serde_json::to_string(&state.token_service.jwks())
+        .map_err(|e| Error::ControllerError(anyhow!("Failed to serialize JWKS: {}", e)))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/jwk-set+json")
+        .set_header("Cache-Control", "public, max-age=300")
+        .body(body))
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Path, State};
+use serde::Deserialize;
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event as audit;
+use crate::models::device::{Device, DeviceType};
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct EnrollDeviceRequest {
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub store: String,
+    #[validate(length(min = 1, message = "is required"))]
+    pub device_type: String,
+}
+
+pub async fn list_devices(state: State<Arc<AppState>>) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let devices = Device::get_all(&mut conn).await?;
+
+    Ok(HttpResponse::Ok().json(&devices))
+}
+
+pub async fn enroll_device(state: State<Arc<AppState>>, req: ValidatedJson<EnrollDeviceRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let device_type = req.device_type.parse::<DeviceType>()
+        .map_err(|e| Error::ApiError(anyhow!(e)))?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let (device, device_secret) = Device::enroll(req.name.clone(), req.store.clone(), device_type, user_id, &mut conn).await?;
+
+    let event = audit_event(&http_req, audit::DEVICE_ENROLL)
+        .details(json!({ "device_id": device.id, "name": device.name, "store": device.store, "device_type": device.device_type.to_string() }));
+    state.audit_service.record(event, &mut conn).await;
+
+    // the secret can't be recovered later, the terminal has to store it now
+    let response = json!({
+        "device": device,
+        "device_id": device.device_uid,
+        "device_secret": device_secret,
+    });
+
+    Ok(HttpResponse::Created().json(&response))
+}
+
+/// Revokes a terminal's credential and ends every session opened on it.
+pub async fn revoke_device(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let device = Device::find_by_id(path.0, &mut conn).await?;
+    device.revoke(&mut conn).await?;
+
+    let sessions = state.session_service.revoke_device_sessions(device.id).await?;
+
+    let event = audit_event(&http_req, audit::DEVICE_REVOKE)
+        .details(json!({ "device_id": device.id, "name": device.name, "store": device.store, "sessions": sessions }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Device revoked successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::State;
+use serde_json::json;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::error::{Error, Result};
+use crate::middlewares::access_middleware::{require_override, require_permission};
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event as audit;
+use crate::models::permission;
+
+/// Lets the terminal open its cash drawer outside a sale. Staff with `drawer.open` do
+/// it themselves, everyone else needs a manager's approval in `X-Override-Token`.
+pub async fn open_drawer(state: State<Arc<AppState>>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let approved_by = match require_permission(&http_req, &state, permission::DRAWER_OPEN).await {
+        Ok(()) => None,
+        Err(Error::ForbiddenError) => Some(require_override(&http_req, &state, permission::DRAWER_OPEN).await?.sub),
+        Err(e) => return Err(e),
+    };
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let event = audit_event(&http_req, audit::DRAWER_OPEN)
+        .target(user_id)
+        .details(json!({ "approved_by": approved_by }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({
+        "message": "Drawer can be opened",
+        "approved_by": approved_by,
+    });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Path, Query, State};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::controllers::user_controller::managed_user;
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event as audit;
+use crate::models::employee::{Employee, EmployeeFilter, NewEmployee};
+use crate::models::user::{User, UserRole};
+use crate::utils::{phone, validation};
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+// tells a field left out of an update (`None`) apart from one cleared with `null` (`Some(None)`)
+fn nullable<'de, D, T>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct EmployeeCreateRequest {
+    pub user_id: i64,
+    #[validate(length(min = 1, max = 32, message = "must be 1 to 32 characters"))]
+    pub employee_code: String,
+    #[validate(length(max = 255, message = "must be at most 255 characters"))]
+    pub job_title: Option<String>,
+    #[validate(range(min = 0, message = "can't be negative"))]
+    pub hourly_rate: Option<i64>,
+    pub hire_date: NaiveDate,
+    pub termination_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub stores: Vec<String>,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub emergency_contact_name: Option<String>,
+    #[validate(length(min = 1, max = 32, message = "is required"))]
+    pub emergency_contact_phone: Option<String>,
+    #[validate(custom(function = "validation::validate_pin"))]
+    pub pin: Option<String>, // the terminal PIN, can also be set by the employee later
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct EmployeeUpdateRequest {
+    #[validate(length(min = 1, max = 32, message = "must be 1 to 32 characters"))]
+    pub employee_code: Option<String>,
+    #[validate(length(max = 255, message = "must be at most 255 characters"))]
+    pub job_title: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub hourly_rate: Option<Option<i64>>,
+    pub hire_date: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub termination_date: Option<Option<NaiveDate>>, // `null` for a rehire
+    pub stores: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub emergency_contact_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub emergency_contact_phone: Option<Option<String>>,
+    #[validate(custom(function = "validation::validate_pin"))]
+    pub pin: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmployeeListQuery {
+    pub search: Option<String>, // part of the employee code, job title or full name
+    pub store: Option<String>,
+    pub active: Option<bool>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EmployeeResponse {
+    pub id: i64,
+    pub user_id: i64,
+    pub username: Option<String>,
+    pub fullname: Option<String>,
+    pub employee_code: String,
+    pub job_title: String,
+    pub hourly_rate: Option<i64>,
+    pub hire_date: NaiveDate,
+    pub termination_date: Option<NaiveDate>,
+    pub active: bool,
+    pub stores: Vec<String>,
+    pub emergency_contact_name: Option<String>,
+    pub emergency_contact_phone: Option<String>,
+    pub has_pin: bool,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl EmployeeResponse {
+    fn new(employee: Employee, user: Option<&User>) -> Self {
+        Self {
+            active: employee.is_active(),
+            id: employee.id,
+            user_id: employee.user_id,
+            username: user.map(|user| user.username.clone()),
+            fullname: user.map(|user| user.fullname.clone()),
+            employee_code: employee.employee_code,
+            job_title: employee.job_title,
+            hourly_rate: employee.hourly_rate,
+            hire_date: employee.hire_date,
+            termination_date: employee.termination_date,
+            stores: employee.stores,
+            emergency_contact_name: employee.emergency_contact_name,
+            emergency_contact_phone: employee.emergency_contact_phone,
+            has_pin: user.is_some_and(|user| user.pin.is_some()),
+            created_at: employee.created_at,
+            updated_at: employee.updated_at,
+        }
+    }
+}
+
+fn normalize_contact_phone(state: &AppState, phone: &str) -> Result<String> {
+    phone::normalize_e164(phone, &state.config.default_country_code)
+        .ok_or_else(|| Error::ValidationError(validation::field_error("emergency_contact_phone", "phone_invalid", "is not a valid phone number")))
+}
+
+fn check_dates(hire_date: NaiveDate, termination_date: Option<NaiveDate>) -> Result<()> {
+    if termination_date.is_some_and(|termination_date| termination_date < hire_date) {
+        return Err(Error::ValidationError(validation::field_error("termination_date", "date_invalid", "can't be before 'hire_date'")));
+    }
+
+    Ok(())
+}
+
+// blank entries are dropped and repeats collapsed, the order the manager gave is kept
+fn clean_stores(stores: &[String]) -> Vec<String> {
+    let mut cleaned: Vec<String> = Vec::new();
+
+    for store in stores.iter().map(|store| store.trim()).filter(|store| !store.is_empty()) {
+        if !cleaned.iter().any(|s| s == store) {
+            cleaned.push(store.to_string());
+        }
+    }
+
+    cleaned
+}
+
+/// Hashes the terminal PIN used by PIN login, which only employees can use.
+fn hash_pin(user: &User, pin: &str) -> Result<String> {
+    if user.role != UserRole::Employee {
+        return Err(Error::ApiError(anyhow!("Only employees can use a PIN")));
+    }
+
+    User::hash_password(pin)
+}
+
+pub async fn get_my_profile(state: State<Arc<AppState>>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let employee = Employee::find_by_user_id(user_id, &mut conn).await?;
+    let user = User::find_by_id(user_id, &mut conn).await?;
+
+    Ok(HttpResponse::Ok().json(&EmployeeResponse::new(employee, Some(&user))))
+}
+
+/// Keeps an API key tied to a store away from the staff of other stores.
+fn check_key_store(http_req: &web::HttpRequest, employee: &Employee) -> Result<()> {
+    match http_req.api_key_store() {
+        Some(store) if !employee.stores.contains(&store) => Err(Error::ForbiddenError),
+        _ => Ok(()),
+    }
+}
+
+pub async fn list_employees(state: State<Arc<AppState>>, query: Query<EmployeeListQuery>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let query = query.into_inner();
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    let mut store = query.store.map(|store| store.trim().to_string()).filter(|store| !store.is_empty());
+
+    if let Some(key_store) = http_req.api_key_store() {
+        if store.as_ref().is_some_and(|store| *store != key_store) {
+            return Err(Error::ForbiddenError);
+        }
+
+        store = Some(key_store);
+    }
+
+    let filter = EmployeeFilter {
+        search: query.search.map(|search| search.trim().to_string()).filter(|search| !search.is_empty()),
+        store,
+        active: query.active,
+    };
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let (employees, total) = Employee::search(&filter, per_page, (page - 1).saturating_mul(per_page), &mut conn).await?;
+
+    let user_ids: Vec<i64> = employees.iter().map(|employee| employee.user_id).collect();
+    let users: HashMap<i64, User> = User::find_by_ids(&user_ids, &mut conn).await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+
+    let employees: Vec<EmployeeResponse> = employees.into_iter()
+        .map(|employee| {
+            let user = users.get(&employee.user_id);
+            EmployeeResponse::new(employee, user)
+        })
+        .collect();
+
+    let response = json!({
+        "employees": employees,
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+    });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn get_employee(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let employee = Employee::find_by_id(path.0, &mut conn).await?;
+    check_key_store(&http_req, &employee)?;
+
+    let user = User::find_by_id(employee.user_id, &mut conn).await?;
+
+    Ok(HttpResponse::Ok().json(&EmployeeResponse::new(employee, Some(&user))))
+}
+
+/// Creates the employee profile of a staff account. Like the user admin endpoints,
+/// managers can't set up their own profile or one ranked above them.
+pub async fn create_employee(state: State<Arc<AppState>>, req: ValidatedJson<EmployeeCreateRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    check_dates(req.hire_date, req.termination_date)?;
+
+    let emergency_contact_phone = req.emergency_contact_phone.as_deref()
+        .map(|phone| normalize_contact_phone(&state, phone))
+        .transpose()?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let mut user = managed_user(&http_req, req.user_id, &mut conn).await?;
+
+    if !user.role.has_at_least(UserRole::Employee) {
+        return Err(Error::ApiError(anyhow!("Only staff accounts can have an employee profile")));
+    }
+
+    let pin_hash = req.pin.as_deref().map(|pin| hash_pin(&user, pin)).transpose()?;
+
+    let new_employee = NewEmployee {
+        user_id: user.id,
+        employee_code: req.employee_code.trim().to_string(),
+        job_title: req.job_title.clone().unwrap_or_default(),
+        hourly_rate: req.hourly_rate,
+        hire_date: req.hire_date,
+        termination_date: req.termination_date,
+        stores: clean_stores(&req.stores),
+        emergency_contact_name: req.emergency_contact_name.clone(),
+        emergency_contact_phone,
+    };
+
+    let employee = Employee::create(new_employee, pin_hash.as_deref(), &mut conn).await?;
+
+    if pin_hash.is_some() {
+        user.pin = pin_hash;
+        state.pin_service.reset(user.id).await;
+    }
+
+    let event = audit_event(&http_req, audit::EMPLOYEE_CREATE)
+        .target(user.id)
+        .details(json!({ "employee_id": employee.id, "employee_code": employee.employee_code, "stores": employee.stores }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(HttpResponse::Created().json(&EmployeeResponse::new(employee, Some(&user))))
+}
+
+pub async fn update_employee(state: State<Arc<AppState>>, path: Path<(i64,)>, req: ValidatedJson<EmployeeUpdateRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let mut employee = Employee::find_by_id(path.0, &mut conn).await?;
+    let mut user = managed_user(&http_req, employee.user_id, &mut conn).await?;
+
+    let pin_hash = req.pin.as_deref().map(|pin| hash_pin(&user, pin)).transpose()?;
+    let mut changes = Vec::new();
+
+    if let Some(employee_code) = &req.employee_code {
+        employee.employee_code = employee_code.trim().to_string();
+        changes.push("employee_code");
+    }
+
+    if let Some(job_title) = &req.job_title {
+        employee.job_title = job_title.clone();
+        changes.push("job_title");
+    }
+
+    if let Some(hourly_rate) = req.hourly_rate {
+        if hourly_rate.is_some_and(|rate| rate < 0) {
+            return Err(Error::ValidationError(validation::field_error("hourly_rate", "range", "can't be negative")));
+        }
+
+        employee.hourly_rate = hourly_rate;
+        changes.push("hourly_rate");
+    }
+
+    if let Some(hire_date) = req.hire_date {
+        employee.hire_date = hire_date;
+        changes.push("hire_date");
+    }
+
+    if let Some(termination_date) = req.termination_date {
+        employee.termination_date = termination_date;
+        changes.push("termination_date");
+    }
+
+    check_dates(employee.hire_date, employee.termination_date)?;
+
+    if let Some(stores) = &req.stores {
+        employee.stores = clean_stores(stores);
+        changes.push("stores");
+    }
+
+    if let Some(name) = &req.emergency_contact_name {
+        if name.as_ref().is_some_and(|name| name.is_empty() || name.chars().count() > 255) {
+            return Err(Error::ValidationError(validation::field_error("emergency_contact_name", "length", "must be 1 to 255 characters")));
+        }
+
+        employee.emergency_contact_name = name.clone();
+        changes.push("emergency_contact_name");
+    }
+
+    if let Some(phone) = &req.emergency_contact_phone {
+        employee.emergency_contact_phone = phone.as_deref()
+            .map(|phone| normalize_contact_phone(&state, phone))
+            .transpose()?;
+        changes.push("emergency_contact_phone");
+    }
+
+    if pin_hash.is_some() {
+        changes.push("pin");
+    }
+
+    let employee = employee.update(pin_hash.as_deref(), &mut conn).await?;
+
+    if pin_hash.is_some() {
+        user.pin = pin_hash;
+        state.pin_service.reset(user.id).await;
+    }
+
+    // setting a termination date of today or earlier ends the employee's tokens right away
+    let terminated = employee.termination_date.is_some_and(|date| date <= Utc::now().date_naive());
+
+    if req.termination_date.is_some() && terminated {
+        user = user.revoke_tokens(&mut conn).await?;
+        state.user_state_service.invalidate(user.id).await?;
+    }
+
+    let event = audit_event(&http_req, audit::EMPLOYEE_UPDATE)
+        .target(user.id)
+        .details(json!({ "employee_id": employee.id, "changes": changes }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(HttpResponse::Ok().json(&EmployeeResponse::new(employee, Some(&user))))
+}
+
+/// Removes the profile only, the login account is left as it is.
+pub async fn delete_employee(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let employee = Employee::find_by_id(path.0, &mut conn).await?;
+    let user = managed_user(&http_req, employee.user_id, &mut conn).await?;
+
+    employee.delete(&mut conn).await?;
+
+    let event = audit_event(&http_req, audit::EMPLOYEE_DELETE)
+        .target(user.id)
+        .details(json!({ "employee_id": employee.id, "employee_code": employee.employee_code }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Employee profile deleted successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
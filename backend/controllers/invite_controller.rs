@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use chrono::{NaiveDateTime, Utc};
+use diesel_async::AsyncConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Path, Query, State};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::{audit_event, client_ip};
+use crate::controllers::user_controller::{caller_role, normalize_whatsapp, parse_role};
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event as audit;
+use crate::models::employee::{Employee, NewEmployee};
+use crate::models::staff_invite::{InviteStatus, StaffInvite};
+use crate::models::user::{NewUser, User, UserRole};
+use crate::services::message_service::send_invite;
+use crate::utils::validation;
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct CreateInviteRequest {
+    #[validate(length(min = 1, max = 32, message = "is required"))]
+    pub whatsapp: String,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub fullname: String,
+    #[validate(length(min = 1, message = "is required"))]
+    pub role: String,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub store: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct AcceptInviteRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    pub code: String,
+    #[validate(length(min = 3, max = 32, message = "must be 3 to 32 characters"), custom(function = "validation::validate_username"))]
+    pub username: String,
+    #[validate(custom(function = "validation::validate_password"))]
+    pub password: String,
+    #[validate(must_match(other = "password", message = "doesn't match with 'password'"))]
+    pub password_confirm: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InviteListQuery {
+    pub status: Option<String>, // "pending" / "accepted" / "revoked" / "expired", everything by default
+}
+
+#[derive(Serialize, Debug)]
+pub struct InviteResponse {
+    pub id: i64,
+    pub whatsapp: String,
+    pub fullname: String,
+    pub role: String,
+    pub store: Option<String>,
+    pub status: String,
+    pub invited_by: Option<i64>,
+    pub user_id: Option<i64>,
+    pub expires_at: NaiveDateTime,
+    pub accepted_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<StaffInvite> for InviteResponse {
+    fn from(invite: StaffInvite) -> Self {
+        Self {
+            status: invite.status().to_string(),
+            id: invite.id,
+            whatsapp: invite.whatsapp,
+            fullname: invite.fullname,
+            role: invite.role.to_string(),
+            store: invite.store,
+            invited_by: invite.invited_by,
+            user_id: invite.user_id,
+            expires_at: invite.expires_at,
+            accepted_at: invite.accepted_at,
+            revoked_at: invite.revoked_at,
+            created_at: invite.created_at,
+        }
+    }
+}
+
+pub async fn list_invites(state: State<Arc<AppState>>, query: Query<InviteListQuery>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let status = query.status.as_deref()
+        .map(|status| status.parse::<InviteStatus>().map_err(|e| Error::ApiError(anyhow!(e))))
+        .transpose()?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let mut invites = StaffInvite::list(status, &mut conn).await?;
+
+    // a key tied to a store only sees the invites for that store
+    if let Some(store) = http_req.api_key_store() {
+        invites.retain(|invite| invite.store.as_ref() == Some(&store));
+    }
+
+    let response: Vec<InviteResponse> = invites.into_iter().map(InviteResponse::from).collect();
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Invites someone to join as staff and sends them the link to set up their
+/// account. Like user creation, nobody can invite someone ranked above them.
+pub async fn create_invite(state: State<Arc<AppState>>, req: ValidatedJson<CreateInviteRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+    let role = parse_role(&req.role)?;
+
+    if !role.has_at_least(UserRole::Employee) {
+        return Err(Error::ApiError(anyhow!("Invites are for staff, customers sign up on their own")));
+    }
+
+    if !caller_role(&http_req)?.has_at_least(role) {
+        return Err(Error::ForbiddenError);
+    }
+
+    let whatsapp = normalize_whatsapp(&state, &req.whatsapp)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    if User::is_whatsapp_taken(&whatsapp, &mut conn).await? {
+        return Err(Error::ApiError(anyhow!("WhatsApp number '{}' is already in use", whatsapp)));
+    }
+
+    let (invite, code) = StaffInvite::create(
+        whatsapp,
+        req.fullname.clone(),
+        role,
+        req.store.clone(),
+        state.config.invite_expiry,
+        Some(user_id),
+        &mut conn
+    ).await?;
+
+    let link = send_invite(state.message_sender.as_ref(), &state.config, &invite, &code).await?;
+
+    let event = audit_event(&http_req, audit::INVITE_CREATE)
+        .details(json!({ "invite_id": invite.id, "whatsapp": invite.whatsapp, "role": invite.role.to_string(), "store": invite.store }));
+    state.audit_service.record(event, &mut conn).await;
+
+    // handed back once as well, for when the invite is passed on in person
+    let response = json!({
+        "invite": InviteResponse::from(invite),
+        "link": link,
+    });
+
+    Ok(HttpResponse::Created().json(&response))
+}
+
+pub async fn revoke_invite(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let invite = StaffInvite::find_by_id(path.0, &mut conn).await?;
+
+    if !caller_role(&http_req)?.has_at_least(invite.role) {
+        return Err(Error::ForbiddenError);
+    }
+
+    let invite = invite.revoke(&mut conn).await?;
+
+    let event = audit_event(&http_req, audit::INVITE_REVOKE)
+        .details(json!({ "invite_id": invite.id, "whatsapp": invite.whatsapp }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Invite revoked successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Activates an invite: the invitee picks their username and password and gets
+/// an account with the invited role. The code reached them over WhatsApp, so the
+/// number counts as verified.
+pub async fn accept_invite(state: State<Arc<AppState>>, req: ValidatedJson<AcceptInviteRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("invite_accept", &client_ip(&http_req)).await?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let invite = StaffInvite::find_pending_by_code(&req.code, &mut conn).await?;
+
+    // the number may have been verified by someone else since the invite went out
+    if User::is_whatsapp_taken(&invite.whatsapp, &mut conn).await? {
+        return Err(Error::ApiError(anyhow!("WhatsApp number '{}' is already in use", invite.whatsapp)));
+    }
+
+    let new_user = NewUser {
+        username: req.username.clone(),
+        fullname: invite.fullname.clone(),
+        password: User::hash_password(&req.password)?, // hashed up front to keep the transaction short
+        whatsapp: invite.whatsapp.clone(),
+        role: invite.role,
+        whatsapp_verified_at: Some(Utc::now().naive_utc())
+    };
+
+    // a failure (e.g. a taken username) leaves the invite pending, so the invitee can try again
+    let (invite, user) = conn.transaction::<_, Error, _>(|conn| async move {
+        let invite = invite.claim(conn).await?;
+        let user = User::create_and_return(new_user, conn).await?;
+
+        // the store the invite was for goes on the new employee profile
+        if let Some(store) = &invite.store {
+            let new_employee = NewEmployee {
+                user_id: user.id,
+                employee_code: format!("INV-{}", invite.id),
+                job_title: String::new(),
+                hourly_rate: None,
+                hire_date: Utc::now().date_naive(),
+                termination_date: None,
+                stores: vec![store.clone()],
+                emergency_contact_name: None,
+                emergency_contact_phone: None,
+            };
+
+            Employee::create(new_employee, None, conn).await?;
+        }
+
+        let invite = invite.set_user(user.id, conn).await?;
+
+        Ok((invite, user))
+    }.scope_boxed())
+    .await?;
+
+    let event = audit_event(&http_req, audit::INVITE_ACCEPT)
+        .actor(user.id)
+        .target(user.id)
+        .details(json!({ "invite_id": invite.id, "invited_by": invite.invited_by, "role": user.role.to_string(), "store": invite.store }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Account activated successfully, you can now log in" });
+
+    Ok(HttpResponse::Created().json(&response))
+}
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use chrono::Utc;
+use diesel_async::AsyncPgConnection;
+use ntex::http::header::{HeaderName, HeaderValue};
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::State;
+use serde::Deserialize;
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::{audit_event, client_ip, start_session};
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event;
+use crate::models::recovery_code::RecoveryCode;
+use crate::models::user::User;
+use crate::utils::totp;
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct MfaLoginRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    pub mfa_token: String,
+    #[validate(length(min = 6, max = 16, message = "must be a 6 digit code or a recovery code"))]
+    pub code: String, // TOTP code or a recovery code
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct MfaLoginSetupRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    pub mfa_token: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct MfaSetupRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct MfaCodeRequest {
+    #[validate(length(equal = 6, message = "must be 6 digits"))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct MfaDisableRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    pub password: String,
+    #[validate(length(min = 6, max = 16, message = "must be a 6 digit code or a recovery code"))]
+    pub code: String,
+}
+
+/// Stores a fresh, not yet confirmed secret for the user and returns what the
+/// authenticator app needs to add the account.
+async fn begin_enrollment(state: &AppState, user: &User, conn: &mut AsyncPgConnection) -> Result<HttpResponse> {
+    if user.has_mfa() {
+        return Err(Error::ApiError(anyhow!("Two-factor authentication is already enabled")));
+    }
+
+    let secret = totp::generate_secret();
+    user.set_totp(Some(&secret), None, conn).await?;
+
+    let response = json!({
+        "secret": secret,
+        "provisioning_uri": state.mfa_service.provisioning_uri(&secret, user),
+    });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Second login step. For a user whose role requires MFA but who hasn't enrolled yet,
+/// the first valid code also confirms the enrollment. The MFA token only works once,
+/// a wrong code means starting over from the password.
+pub async fn login_mfa(state: State<Arc<AppState>>, req: ValidatedJson<MfaLoginRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("login_mfa", &client_ip(&http_req)).await?;
+
+    let claims = state.token_service.verify_mfa_token(&req.mfa_token)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(claims.sub, &mut conn).await?;
+
+    // the account may have been suspended since the password step
+    user.ensure_active()?;
+    if claims.ver != user.token_version {
+        return Err(Error::ApiError(anyhow!("Invalid token")));
+    }
+
+    state.rate_limit_service.ensure_login_allowed(&user.username).await?;
+    state.mfa_service.consume_challenge(&claims).await?;
+
+    if !state.mfa_service.verify_code(&user, &req.code, &mut conn).await? {
+        let event = audit_event(&http_req, audit_event::LOGIN_FAILURE)
+            .target(user.id)
+            .details(json!({ "method": "mfa", "username": user.username, "reason": "invalid_code" }));
+        state.audit_service.record(event, &mut conn).await;
+
+        state.rate_limit_service.register_login_failure(&user.username).await?;
+        return Err(Error::ApiError(anyhow!("Invalid verification code")));
+    }
+
+    state.rate_limit_service.reset_login_failures(&user.username).await;
+
+    let response = if user.has_mfa() {
+        json!({ "message": "Login successful" })
+    } else {
+        user.set_totp(user.totp_secret.as_deref(), Some(Utc::now().naive_utc()), &mut conn).await?;
+        let recovery_codes = RecoveryCode::regenerate(user.id, &mut conn).await?;
+
+        json!({
+            "message": "Login successful, two-factor authentication enabled",
+            "recovery_codes": recovery_codes,
+        })
+    };
+
+    let mut session = start_session(&state, &http_req, &user, &mut conn).await?;
+
+    let event = audit_event(&http_req, audit_event::LOGIN_SUCCESS)
+        .actor(user.id)
+        .target(user.id)
+        .details(json!({ "method": "mfa" }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(session.json(&response))
+}
+
+/// Enrollment for users who are forced into MFA by their role and can't sign in
+/// without it, so they only have the challenge token to prove who they are. That
+/// token is used up here, a fresh one for `login_mfa` comes back in `X-Mfa-Token`.
+pub async fn login_mfa_setup(state: State<Arc<AppState>>, req: ValidatedJson<MfaLoginSetupRequest>) -> Result<HttpResponse> {
+    let claims = state.token_service.verify_mfa_token(&req.mfa_token)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(claims.sub, &mut conn).await?;
+
+    user.ensure_active()?;
+    if claims.ver != user.token_version {
+        return Err(Error::ApiError(anyhow!("Invalid token")));
+    }
+
+    state.mfa_service.consume_challenge(&claims).await?;
+
+    let mfa_token = state.token_service.generate_mfa_token(&user)?;
+    let mut response = begin_enrollment(&state, &user, &mut conn).await?;
+
+    let mfa_token = HeaderValue::from_str(&mfa_token)
+        .map_err(|_| Error::ServiceError(anyhow!("Failed to generate MFA token")))?;
+    response.headers_mut().insert(HeaderName::from_static("x-mfa-token"), mfa_token);
+
+    Ok(response)
+}
+
+pub async fn setup_mfa(state: State<Arc<AppState>>, req: ValidatedJson<MfaSetupRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(user_id, &mut conn).await?;
+
+    if !User::verify_password(&user.password, &req.password)? {
+        return Err(Error::ApiError(anyhow!("Invalid credentials")));
+    }
+
+    begin_enrollment(&state, &user, &mut conn).await
+}
+
+pub async fn confirm_mfa(state: State<Arc<AppState>>, req: ValidatedJson<MfaCodeRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(user_id, &mut conn).await?;
+
+    if user.has_mfa() {
+        return Err(Error::ApiError(anyhow!("Two-factor authentication is already enabled")));
+    }
+
+    if !state.mfa_service.verify_totp(&user, &req.code).await? {
+        return Err(Error::ApiError(anyhow!("Invalid verification code")));
+    }
+
+    user.set_totp(user.totp_secret.as_deref(), Some(Utc::now().naive_utc()), &mut conn).await?;
+    let recovery_codes = RecoveryCode::regenerate(user.id, &mut conn).await?;
+
+    let response = json!({
+        "message": "Two-factor authentication enabled",
+        "recovery_codes": recovery_codes,
+    });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn disable_mfa(state: State<Arc<AppState>>, req: ValidatedJson<MfaDisableRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(user_id, &mut conn).await?;
+
+    if !user.has_mfa() {
+        return Err(Error::ApiError(anyhow!("Two-factor authentication is not enabled")));
+    }
+
+    if state.mfa_service.is_required(user.role) {
+        return Err(Error::ApiError(anyhow!("Two-factor authentication is required for your role")));
+    }
+
+    if !User::verify_password(&user.password, &req.password)? || !state.mfa_service.verify_code(&user, &req.code, &mut conn).await? {
+        return Err(Error::ApiError(anyhow!("Invalid credentials")));
+    }
+
+    user.set_totp(None, None, &mut conn).await?;
+    RecoveryCode::delete_for_user(user.id, &mut conn).await?;
+
+    let response = json!({ "message": "Two-factor authentication disabled" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn regenerate_recovery_codes(state: State<Arc<AppState>>, req: ValidatedJson<MfaCodeRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(user_id, &mut conn).await?;
+
+    if !user.has_mfa() {
+        return Err(Error::ApiError(anyhow!("Two-factor authentication is not enabled")));
+    }
+
+    // a recovery code can't be used to mint new ones
+    if !state.mfa_service.verify_totp(&user, &req.code).await? {
+        return Err(Error::ApiError(anyhow!("Invalid verification code")));
+    }
+
+    let recovery_codes = RecoveryCode::regenerate(user.id, &mut conn).await?;
+
+    let response = json!({ "recovery_codes": recovery_codes });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
@@ -1,2 +1,14 @@
 pub mod auth_controller;
-pub mod user_controller;
\ No newline at end of file
+pub mod user_controller;
+pub mod role_controller;
+pub mod device_controller;
+pub mod session_controller;
+pub mod mfa_controller;
+pub mod audit_controller;
+pub mod api_key_controller;
+pub mod oidc_controller;
+pub mod override_controller;
+pub mod invite_controller;
+pub mod employee_controller;
+pub mod user_csv_controller;
+pub mod drawer_controller;
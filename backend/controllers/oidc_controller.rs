@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Path, State};
+use serde::Deserialize;
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::{audit_event, client_ip, start_session};
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event as audit;
+use crate::models::employee::{Employee, EmployeeError};
+use crate::models::user::{NewUser, User, UserRole};
+use crate::models::user_identity::UserIdentity;
+use crate::services::oidc_service::OidcIdentity;
+use crate::utils::{crypto, validation};
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct OidcCallbackRequest {
+    #[validate(length(min = 1, max = 2048, message = "is required"))]
+    pub code: String,
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub state: String,
+}
+
+/// Sends the user to the identity provider. Signed in users (not impersonated) link
+/// the provider account to themselves instead of signing in with it.
+pub async fn start_login(state: State<Arc<AppState>>, path: Path<(String,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("oidc", &client_ip(&http_req)).await?;
+
+    let link_user_id = http_req.user_id().filter(|_| http_req.impersonator_id().is_none());
+    let authorization_url = state.oidc_service.authorization_url(&path.0, link_user_id).await?;
+
+    let response = json!({ "authorization_url": authorization_url });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Creates a TeaPOS account for a provider account that signs in for the first time.
+async fn provision_user(identity: &OidcIdentity, conn: &mut AsyncPgConnection) -> Result<User> {
+    // fall back to a generated username when the provider's one doesn't fit or is taken
+    let preferred = identity.username.as_deref()
+        .filter(|username| (3..=32).contains(&username.len()) && validation::validate_username(username).is_ok());
+
+    let username = match preferred {
+        Some(username) if User::find_by_username(username, conn).await.is_err() => username.to_string(),
+        _ => format!("{}-{}", identity.provider, &crypto::sha256_hex(&identity.subject)[..12])
+    };
+
+    let new_user = NewUser {
+        fullname: identity.fullname.clone().unwrap_or_else(|| username.clone()),
+        username,
+        password: crypto::random_token(32), // unusable, the account signs in through the provider
+        whatsapp: String::new(),
+        role: identity.role,
+        whatsapp_verified_at: None
+    };
+
+    // an account without its identity could never sign in, so both are written or neither
+    conn.transaction::<_, Error, _>(|conn| async move {
+        let user = User::create_and_return(new_user, conn).await?;
+        UserIdentity::link(user.id, &identity.provider, &identity.subject, conn).await?;
+
+        Ok(user)
+    }.scope_boxed())
+    .await
+}
+
+pub async fn callback(state: State<Arc<AppState>>, path: Path<(String,)>, req: ValidatedJson<OidcCallbackRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    state.rate_limit_service.limit_ip("oidc", &client_ip(&http_req)).await?;
+
+    let identity = state.oidc_service.complete(&path.0, &req.code, &req.state).await?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    if let Some(user_id) = identity.link_user_id {
+        UserIdentity::link(user_id, &identity.provider, &identity.subject, &mut conn).await?;
+
+        let event = audit_event(&http_req, audit::IDENTITY_LINK)
+            .actor(user_id)
+            .target(user_id)
+            .details(json!({ "provider": identity.provider, "subject": identity.subject }));
+        state.audit_service.record(event, &mut conn).await;
+
+        let response = json!({ "message": format!("Your {} account has been linked", identity.provider) });
+
+        return Ok(HttpResponse::Ok().json(&response));
+    }
+
+    let mut user = match UserIdentity::find(&identity.provider, &identity.subject, &mut conn).await? {
+        Some(linked) => {
+            linked.touch(&mut conn).await?;
+            User::find_by_id(linked.user_id, &mut conn).await?
+        },
+        None => provision_user(&identity, &mut conn).await?
+    };
+
+    user.ensure_active()?;
+
+    if Employee::is_terminated(user.id, &mut conn).await? {
+        return Err(EmployeeError::Terminated.into());
+    }
+
+    // the provider's groups are the source of truth for the role of linked accounts,
+    // except SuperAdmins, who are only ever appointed inside TeaPOS
+    let previous_role = user.role;
+    if previous_role != UserRole::SuperAdmin && previous_role != identity.role {
+        user.role = identity.role;
+        user = user.update_profile(None, true, &mut conn).await?;
+        state.permission_service.invalidate_users(&[user.id]).await?;
+        state.user_state_service.invalidate(user.id).await?;
+    }
+
+    let mut response = start_session(&state, &http_req, &user, &mut conn).await?;
+
+    let event = audit_event(&http_req, audit::LOGIN_SUCCESS)
+        .actor(user.id)
+        .target(user.id)
+        .details(json!({
+            "method": "oidc",
+            "provider": identity.provider,
+            "previous_role": previous_role.to_string(),
+            "role": user.role.to_string(),
+        }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(response.json(&json!({ "message": "Login successful" })))
+}
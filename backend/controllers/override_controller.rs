@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::State;
+use serde::Deserialize;
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::{audit_event, client_ip};
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event;
+use crate::models::employee::{Employee, EmployeeError};
+use crate::models::permission;
+use crate::models::user::{User, UserRole};
+use crate::utils::validation;
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct OverrideRequest {
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub username: String, // the approving manager
+    #[validate(custom(function = "validation::validate_pin"))]
+    pub pin: Option<String>,
+    #[validate(length(min = 1, message = "is required"))]
+    pub password: Option<String>, // for managers without a PIN
+    #[validate(length(min = 1, max = 255, message = "is required"))]
+    pub action: String,
+}
+
+/// A manager approves a sensitive action on the cashier's terminal with their PIN or
+/// password. The cashier gets a single-use override token for that action only, to
+/// send in `X-Override-Token` with the protected request.
+pub async fn approve(state: State<Arc<AppState>>, req: ValidatedJson<OverrideRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let cashier_claims = http_req.token_claims().ok_or(Error::UnauthorizedError)?;
+
+    state.rate_limit_service.limit_ip("override", &client_ip(&http_req)).await?;
+    state.rate_limit_service.limit_username("override", &req.username).await?;
+
+    if !permission::OVERRIDABLE.contains(&req.action.as_str()) {
+        return Err(Error::ApiError(anyhow!("'{}' can't be approved with an override", req.action)));
+    }
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let manager = match User::find_by_username(&req.username, &mut conn).await {
+        Ok(manager) if manager.id != cashier_claims.sub => manager,
+        _ => return Err(Error::ApiError(anyhow!("Invalid credentials")))
+    };
+
+    // shares the PIN lockout, so overrides can't be used to guess a manager's PIN or password
+    state.pin_service.ensure_not_locked(manager.id).await?;
+
+    let verified = match (&req.pin, &req.password, &manager.pin) {
+        (Some(pin), None, Some(pin_hash)) => User::verify_password(pin_hash, pin)?,
+        (None, Some(password), _) => User::verify_password(&manager.password, password)?,
+        (Some(_), Some(_), _) => return Err(Error::ApiError(anyhow!("Send either 'pin' or 'password', not both"))),
+        _ => false
+    };
+
+    if !verified {
+        let event = audit_event(&http_req, audit_event::OVERRIDE_FAILURE)
+            .target(manager.id)
+            .details(json!({ "action": req.action, "username": manager.username, "reason": "invalid_credentials" }));
+        state.audit_service.record(event, &mut conn).await;
+
+        state.pin_service.register_failure(manager.id).await?;
+        return Err(Error::ApiError(anyhow!("Invalid credentials")));
+    }
+
+    state.pin_service.reset(manager.id).await;
+
+    manager.ensure_active()?;
+
+    if Employee::is_terminated(manager.id, &mut conn).await? {
+        let event = audit_event(&http_req, audit_event::OVERRIDE_FAILURE)
+            .target(manager.id)
+            .details(json!({ "action": req.action, "username": manager.username, "reason": "terminated" }));
+        state.audit_service.record(event, &mut conn).await;
+
+        return Err(EmployeeError::Terminated.into());
+    }
+
+    let allowed = manager.role.has_at_least(UserRole::Employee)
+        && state.permission_service.has_permission(manager.id, manager.role, &req.action, &mut conn).await?;
+
+    if !allowed {
+        let event = audit_event(&http_req, audit_event::OVERRIDE_FAILURE)
+            .target(manager.id)
+            .details(json!({ "action": req.action, "username": manager.username, "reason": "not_permitted" }));
+        state.audit_service.record(event, &mut conn).await;
+
+        return Err(Error::ApiError(anyhow!("{} isn't allowed to approve this action", manager.username)));
+    }
+
+    let (override_token, override_claims) = state.token_service.generate_override_token(&manager, &cashier_claims, &req.action)?;
+
+    let event = audit_event(&http_req, audit_event::OVERRIDE_APPROVE)
+        .actor(manager.id)
+        .target(cashier_claims.sub)
+        .details(json!({ "action": req.action, "override_id": override_claims.jti }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({
+        "message": "Action approved",
+        "action": req.action,
+        "approved_by": manager.id,
+        "expires_at": override_claims.exp,
+    });
+
+    Ok(HttpResponse::Ok()
+        .set_header("X-Override-Token", override_token)
+        .json(&response))
+}
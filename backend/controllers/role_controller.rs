@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use diesel_async::AsyncPgConnection;
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Path, State};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::controllers::user_controller::caller_role;
+use crate::error::{Error, Result};
+use crate::extractors::validated_json::ValidatedJson;
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event as audit;
+use crate::models::permission::Permission;
+use crate::models::role::{NewRole, Role, RoleError};
+use crate::models::user::User;
+use crate::utils::validation;
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct RoleRequest {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"), custom(function = "validation::validate_role_name"))]
+    pub name: String,
+    #[validate(length(max = 255, message = "must be at most 255 characters"))]
+    pub description: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct RolePermissionsRequest {
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct UserRolesRequest {
+    pub role_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RoleResponse {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+pub async fn list_roles(state: State<Arc<AppState>>) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let roles = Role::get_all(&mut conn).await?;
+
+    let mut response = Vec::with_capacity(roles.len());
+    for role in roles {
+        let permissions = role.permissions(&mut conn).await?;
+        response.push(RoleResponse {
+            id: role.id,
+            name: role.name,
+            description: role.description,
+            permissions,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Nobody can hand out a permission they don't have themselves, neither by editing a
+/// role nor by assigning one, so managing roles can't be used to gain more access.
+async fn check_grantable(state: &AppState, http_req: &web::HttpRequest, permissions: &[String], conn: &mut AsyncPgConnection) -> Result<()> {
+    let user_id = http_req.user_id();
+    let api_key = http_req.api_key();
+
+    for permission in permissions {
+        let allowed = match (user_id, &api_key) {
+            (Some(user_id), _) => state.permission_service.has_permission(user_id, caller_role(http_req)?, permission, conn).await?,
+            (None, Some(api_key)) => api_key.has_permission(permission),
+            (None, None) => return Err(Error::UnauthorizedError),
+        };
+
+        if !allowed {
+            return Err(Error::ApiError(anyhow!("You can't grant the permission '{}'", permission)));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn list_permissions(state: State<Arc<AppState>>) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let permissions = Permission::get_all(&mut conn).await?;
+
+    Ok(HttpResponse::Ok().json(&permissions))
+}
+
+pub async fn create_role(state: State<Arc<AppState>>, req: ValidatedJson<RoleRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    check_grantable(&state, &http_req, &req.permissions, &mut conn).await?;
+
+    let new_role = NewRole {
+        name: req.name.clone(),
+        description: req.description.clone(),
+    };
+
+    let role = Role::create(new_role, &req.permissions, &mut conn).await?;
+
+    let event = audit_event(&http_req, audit::ROLE_CREATE)
+        .details(json!({ "role_id": role.id, "name": role.name, "permissions": req.permissions }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = RoleResponse {
+        id: role.id,
+        name: role.name,
+        description: role.description,
+        permissions: req.permissions.clone(),
+    };
+
+    Ok(HttpResponse::Created().json(&response))
+}
+
+pub async fn update_role(state: State<Arc<AppState>>, path: Path<(i64,)>, req: ValidatedJson<RoleRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let mut role = Role::find_by_id(path.0, &mut conn).await?;
+
+    if role.is_built_in() && role.name != req.name {
+        return Err(RoleError::BuiltInRole(role.name).into());
+    }
+
+    let changed = changed_permissions(&role, &req.permissions, &mut conn).await?;
+    check_grantable(&state, &http_req, &changed, &mut conn).await?;
+
+    role.name = req.name.clone();
+    role.description = req.description.clone();
+
+    let role = role.update(&req.permissions, &mut conn).await?;
+
+    let user_ids = role.user_ids(&mut conn).await?;
+    state.permission_service.invalidate_users(&user_ids).await?;
+
+    let event = audit_event(&http_req, audit::ROLE_UPDATE)
+        .details(json!({ "role_id": role.id, "name": role.name, "permissions": req.permissions }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = RoleResponse {
+        id: role.id,
+        name: role.name,
+        description: role.description,
+        permissions: req.permissions.clone(),
+    };
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+// taking a permission away from a role is handing it out as much as adding it is
+async fn changed_permissions(role: &Role, permissions: &[String], conn: &mut AsyncPgConnection) -> Result<Vec<String>> {
+    let current: HashSet<String> = role.permissions(conn).await?.into_iter().collect();
+    let requested: HashSet<String> = permissions.iter().cloned().collect();
+
+    Ok(current.symmetric_difference(&requested).cloned().collect())
+}
+
+pub async fn set_role_permissions(state: State<Arc<AppState>>, path: Path<(i64,)>, req: ValidatedJson<RolePermissionsRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let role = Role::find_by_id(path.0, &mut conn).await?;
+    let changed = changed_permissions(&role, &req.permissions, &mut conn).await?;
+    check_grantable(&state, &http_req, &changed, &mut conn).await?;
+
+    role.set_permissions(&req.permissions, &mut conn).await?;
+
+    let user_ids = role.user_ids(&mut conn).await?;
+    state.permission_service.invalidate_users(&user_ids).await?;
+
+    let event = audit_event(&http_req, audit::ROLE_PERMISSIONS_CHANGE)
+        .details(json!({ "role_id": role.id, "name": role.name, "permissions": req.permissions }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Role permissions updated successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn delete_role(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let role = Role::find_by_id(path.0, &mut conn).await?;
+
+    // deleting a role takes its permissions away from everyone holding it
+    let permissions = role.permissions(&mut conn).await?;
+    check_grantable(&state, &http_req, &permissions, &mut conn).await?;
+
+    let user_ids = role.user_ids(&mut conn).await?;
+
+    role.delete(&mut conn).await?;
+    state.permission_service.invalidate_users(&user_ids).await?;
+
+    let event = audit_event(&http_req, audit::ROLE_DELETE)
+        .details(json!({ "role_id": role.id, "name": role.name, "affected_users": user_ids }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Role deleted successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn get_user_roles(state: State<Arc<AppState>>, path: Path<(i64,)>) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let user = User::find_by_id(path.0, &mut conn).await?;
+    let roles = Role::find_by_user(user.id, &mut conn).await?;
+    let permissions = state.permission_service.get_user_permissions(user.id, &mut conn).await?;
+
+    let response = json!({ "roles": roles, "permissions": permissions });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Replaces the roles of a user. Only roles whose permissions the caller has can be
+/// given or taken away, so e.g. an admin can't make someone (or themselves) an owner.
+pub async fn set_user_roles(state: State<Arc<AppState>>, path: Path<(i64,)>, req: ValidatedJson<UserRolesRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let user = User::find_by_id(path.0, &mut conn).await?;
+
+    let current: HashSet<i64> = Role::find_by_user(user.id, &mut conn).await?.into_iter().map(|role| role.id).collect();
+    let requested: HashSet<i64> = req.role_ids.iter().copied().collect();
+
+    for role_id in current.symmetric_difference(&requested) {
+        let role = Role::find_by_id(*role_id, &mut conn).await?;
+        let permissions = role.permissions(&mut conn).await?;
+        check_grantable(&state, &http_req, &permissions, &mut conn).await?;
+    }
+
+    Role::assign_to_user(user.id, &req.role_ids, &mut conn).await?;
+
+    state.permission_service.invalidate_users(&[user.id]).await?;
+
+    let event = audit_event(&http_req, audit::USER_ROLES_CHANGE)
+        .target(user.id)
+        .details(json!({ "role_ids": req.role_ids }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "User roles updated successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
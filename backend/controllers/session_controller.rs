@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Path, State};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::controllers::user_controller::managed_user;
+use crate::error::{Error, Result};
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event::{self as audit, NewAuditEvent};
+use crate::services::session_service::{EnrolledDevice, SessionData};
+
+#[derive(Serialize, Debug)]
+pub struct SessionResponse {
+    pub id: String, // the session (family) id, never the refresh token's jti
+    pub user_agent: String,
+    pub ip_address: String,
+    pub device: Option<EnrolledDevice>,
+    pub current: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_active: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SessionResponse {
+    fn from_session(session: SessionData, current_session_id: Option<&str>) -> Self {
+        Self {
+            current: current_session_id == Some(session.family_id.as_str()),
+            id: session.family_id,
+            user_agent: session.device_info.user_agent,
+            ip_address: session.device_info.ip_address,
+            device: session.device_info.enrolled_device,
+            created_at: session.created_at,
+            last_active: session.device_info.last_active,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
+async fn session_list(state: &AppState, user_id: i64, current_session_id: Option<&str>) -> Result<Vec<SessionResponse>> {
+    let mut sessions: Vec<SessionResponse> = state.session_service.get_user_sessions(user_id).await?
+        .into_iter()
+        .map(|session| SessionResponse::from_session(session, current_session_id))
+        .collect();
+
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.last_active));
+
+    Ok(sessions)
+}
+
+async fn record(state: &AppState, event: NewAuditEvent) -> Result<()> {
+    let mut conn = state.db_pool.get_connection().await?;
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(())
+}
+
+pub async fn list_my_sessions(state: State<Arc<AppState>>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let claims = http_req.token_claims().ok_or(Error::UnauthorizedError)?;
+
+    let sessions = session_list(&state, claims.sub, Some(&claims.sid)).await?;
+
+    Ok(HttpResponse::Ok().json(&sessions))
+}
+
+pub async fn revoke_my_session(state: State<Arc<AppState>>, path: Path<(String,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    state.session_service.revoke_user_session(user_id, &path.0).await?;
+
+    let event = audit_event(&http_req, audit::SESSION_REVOKE)
+        .target(user_id)
+        .details(json!({ "session_id": path.0 }));
+    record(&state, event).await?;
+
+    let response = json!({ "message": "Session revoked successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn logout_everywhere(state: State<Arc<AppState>>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+
+    state.session_service.logout_all_sessions(user_id).await?;
+
+    record(&state, audit_event(&http_req, audit::SESSION_REVOKE_ALL).target(user_id)).await?;
+
+    let response = json!({ "message": "Logged out from every session" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn list_user_sessions(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = managed_user(&http_req, path.0, &mut conn).await?;
+
+    let sessions = session_list(&state, user.id, None).await?;
+
+    Ok(HttpResponse::Ok().json(&sessions))
+}
+
+pub async fn revoke_user_session(state: State<Arc<AppState>>, path: Path<(i64, String)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = managed_user(&http_req, path.0, &mut conn).await?;
+
+    state.session_service.revoke_user_session(user.id, &path.1).await?;
+
+    let event = audit_event(&http_req, audit::SESSION_REVOKE)
+        .target(user.id)
+        .details(json!({ "session_id": path.1 }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Session revoked successfully" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn logout_user_everywhere(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = managed_user(&http_req, path.0, &mut conn).await?;
+
+    state.session_service.logout_all_sessions(user.id).await?;
+
+    state.audit_service.record(audit_event(&http_req, audit::SESSION_REVOKE_ALL).target(user.id), &mut conn).await;
+
+    let response = json!({ "message": "User logged out from every session" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
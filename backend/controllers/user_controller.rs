@@ -1,286 +1,439 @@
 use std::sync::Arc;
 
+use anyhow::anyhow;
+use diesel_async::AsyncPgConnection;
 use ntex::web;
 use ntex::web::HttpResponse;
-use ntex::web::types::State;
+use ntex::web::types::{Path, Query, State};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
 
+use crate::controllers::auth_controller::{audit_event, send_whatsapp_verification};
+use crate::extractors::validated_json::ValidatedJson;
 use crate::middlewares::auth_middleware::UserInfo;
-use crate::models::user::User;
+use crate::models::audit_event as audit;
+use crate::models::user::{NewUser, User, UserFilter, UserRole, UserSort, UserStatus};
 
 use crate::app::AppState;
 use crate::error::{Error, Result};
+use crate::utils::{phone, validation};
 
-#[derive(Deserialize, Debug)]
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct UserUpdateRequest {
-    pub id: u64,
-    pub username: String,
-    pub fullname: String,
+    #[validate(length(min = 3, max = 32, message = "must be 3 to 32 characters"), custom(function = "validation::validate_username"))]
+    pub username: Option<String>,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub fullname: Option<String>,
+    #[validate(length(min = 1, max = 32, message = "is required"))]
+    pub whatsapp: Option<String>, // has to be verified again once changed
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct PasswordUpdateRequest {
+    #[validate(length(min = 1, message = "is required"))]
     pub current_password: String,
+    #[validate(custom(function = "validation::validate_password"))]
     pub new_password: String,
+    #[validate(must_match(other = "new_password", message = "doesn't match with 'new_password'"))]
     pub confirm_password: String,
 }
 
+#[derive(Deserialize, Debug, Validate)]
+pub struct AdminUserCreateRequest {
+    #[validate(length(min = 3, max = 32, message = "must be 3 to 32 characters"), custom(function = "validation::validate_username"))]
+    pub username: String,
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub fullname: String,
+    #[validate(custom(function = "validation::validate_password"))]
+    pub password: String,
+    #[validate(length(min = 1, max = 32, message = "is required"))]
+    pub whatsapp: String,
+    #[validate(length(min = 1, message = "is required"))]
+    pub role: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct AdminUserUpdateRequest {
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
+    pub fullname: Option<String>,
+    #[validate(length(min = 1, max = 32, message = "is required"))]
+    pub whatsapp: Option<String>,
+    #[validate(length(min = 1, message = "is required"))]
+    pub role: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct UserStatusRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    pub status: String, // "active" / "suspended" / "deactivated"
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserListQuery {
+    pub search: Option<String>, // part of the username, full name or WhatsApp number
+    pub role: Option<String>,
+    pub status: Option<String>,
+    pub sort: Option<String>, // "created_at" (default) / "username" / "fullname"
+    pub order: Option<String>, // "asc" / "desc", newest first by default
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct UserDetailResponse {
     pub id: u64,
     pub username: String,
     pub fullname: String,
     pub whatsapp: String,
+    pub whatsapp_verified: bool,
     pub role: String,
+    pub status: String,
+    pub mfa_enabled: bool,
     pub created_at: Option<String>,
     pub updated_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<i64>, // the SuperAdmin acting as this user
+}
+
+impl From<User> for UserDetailResponse {
+    fn from(user: User) -> Self {
+        Self {
+            whatsapp_verified: user.is_whatsapp_verified(),
+            mfa_enabled: user.has_mfa(),
+            id: user.id as u64,
+            username: user.username,
+            fullname: user.fullname,
+            whatsapp: user.whatsapp,
+            role: user.role.to_string(),
+            status: user.status.to_string(),
+            created_at: Some(user.created_at.to_string()),
+            updated_at: Some(user.updated_at.to_string()),
+            impersonated_by: None,
+        }
+    }
+}
+
+pub(crate) fn caller_role(http_req: &web::HttpRequest) -> Result<UserRole> {
+    http_req.user_role()
+        .and_then(|role| role.parse::<UserRole>().ok())
+        .ok_or(Error::UnauthorizedError)
+}
+
+pub(crate) fn parse_role(role: &str) -> Result<UserRole> {
+    role.parse::<UserRole>()
+        .map_err(|_| Error::ValidationError(validation::field_error("role", "role_invalid", "must be superadmin, admin, employee or user")))
+}
+
+pub(crate) fn normalize_whatsapp(state: &AppState, whatsapp: &str) -> Result<String> {
+    phone::normalize_e164(whatsapp, &state.config.default_country_code)
+        .ok_or_else(|| Error::ValidationError(validation::field_error("whatsapp", "phone_invalid", "is not a valid phone number")))
+}
+
+/// Loads a user an admin wants to change. Nobody changes their own account through
+/// the admin endpoints or touches someone ranked above them, so only a SuperAdmin
+/// can manage another SuperAdmin.
+pub(crate) async fn managed_user(http_req: &web::HttpRequest, user_id: i64, conn: &mut AsyncPgConnection) -> Result<User> {
+    let caller_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+    let role = caller_role(http_req)?;
+
+    if user_id == caller_id {
+        return Err(Error::ApiError(anyhow!("Use your own profile endpoints to change your account")));
+    }
+
+    let user = User::find_by_id(user_id, conn).await?;
+
+    if !role.has_at_least(user.role) {
+        return Err(Error::ForbiddenError);
+    }
+
+    Ok(user)
 }
 
 pub async fn get_current_user(req: web::HttpRequest, state: State<Arc<AppState>>) -> Result<HttpResponse> {
-    let user_id = req.user_id().ok_or(Error::ForbiddenError)?;
-    
+    let user_id = req.user_id().ok_or(Error::UnauthorizedError)?;
+
     let mut conn = state.db_pool.get_connection().await?;
     let user = User::find_by_id(user_id, &mut conn).await?;
-    
+
     let response = UserDetailResponse {
-        id: user.id as u64,
-        username: user.username,
-        fullname: user.fullname,
-        whatsapp: user.whatsapp,
-        role: user.role.to_string(),
-        created_at: Some(user.created_at.to_string()),
-        updated_at: Some(user.updated_at.to_string()),
+        impersonated_by: req.impersonator_id(),
+        ..user.into()
+    };
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn update_current_user(state: State<Arc<AppState>>, req: ValidatedJson<UserUpdateRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let user_id = http_req.user_id().ok_or(Error::UnauthorizedError)?;
+    let whatsapp = req.whatsapp.as_deref().map(|whatsapp| normalize_whatsapp(&state, whatsapp)).transpose()?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let mut user = User::find_by_id(user_id, &mut conn).await?;
+    let mut changes = Vec::new();
+
+    // everything is checked before anything is saved
+    if let Some(username) = &req.username && *username != user.username {
+        if User::find_by_username(username, &mut conn).await.is_ok() {
+            return Err(Error::ApiError(anyhow!("User with username '{}' already exists", username)));
+        }
+
+        user.username = username.clone();
+        changes.push("username");
+    }
+
+    if let Some(fullname) = &req.fullname {
+        user.fullname = fullname.clone();
+        changes.push("fullname");
+    }
+
+    let whatsapp = whatsapp.filter(|whatsapp| *whatsapp != user.whatsapp);
+
+    if let Some(whatsapp) = &whatsapp {
+        if User::is_whatsapp_taken(whatsapp, &mut conn).await? {
+            return Err(Error::ApiError(anyhow!("WhatsApp number '{}' is already in use", whatsapp)));
+        }
+
+        changes.push("whatsapp");
+    }
+
+    user = user.update_profile(whatsapp.as_deref(), false, &mut conn).await?;
+
+    if whatsapp.is_some() {
+        send_whatsapp_verification(&state, &user).await?;
+    }
+
+    let event = audit_event(&http_req, audit::USER_UPDATE)
+        .target(user.id)
+        .details(json!({ "fields": changes }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = UserDetailResponse {
+        impersonated_by: http_req.impersonator_id(),
+        ..user.into()
+    };
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+/// Changes the caller's password. Every other session of theirs is ended, the one
+/// the change was made from stays signed in.
+pub async fn update_password(state: State<Arc<AppState>>, req: ValidatedJson<PasswordUpdateRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let claims = http_req.token_claims().ok_or(Error::UnauthorizedError)?;
+
+    state.rate_limit_service.limit_username("password_change", &claims.sub.to_string()).await?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let mut user = User::find_by_id(claims.sub, &mut conn).await?;
+
+    if !User::verify_password(&user.password, &req.current_password)? {
+        return Err(Error::ApiError(anyhow!("Current password is incorrect")));
+    }
+
+    user.password = User::hash_password(&req.new_password)?;
+    let user = user.update(&mut conn).await?;
+
+    state.session_service.logout_other_sessions(user.id, &claims.sid).await?;
+
+    let event = audit_event(&http_req, audit::PASSWORD_CHANGE)
+        .target(user.id)
+        .details(json!({ "session_id": claims.sid }));
+    state.audit_service.record(event, &mut conn).await;
+
+    let response = json!({ "message": "Password updated successfully, your other sessions have been signed out" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn list_users(state: State<Arc<AppState>>, query: Query<UserListQuery>) -> Result<HttpResponse> {
+    let query = query.into_inner();
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    let sort = match &query.sort {
+        Some(sort) => sort.parse::<UserSort>().map_err(|e| Error::ApiError(anyhow!(e)))?,
+        None => UserSort::default()
+    };
+
+    let descending = match query.order.as_deref() {
+        Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => return Err(Error::ApiError(anyhow!("Unknown sort order: {}", order))),
+        None => matches!(sort, UserSort::CreatedAt)
     };
-    
+
+    let filter = UserFilter {
+        search: query.search.map(|search| search.trim().to_string()).filter(|search| !search.is_empty()),
+        role: query.role.as_deref().map(parse_role).transpose()?,
+        status: query.status.as_deref()
+            .map(|status| status.parse::<UserStatus>().map_err(|e| Error::ApiError(anyhow!(e))))
+            .transpose()?,
+    };
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let (users, total) = User::search(&filter, sort, descending, per_page, (page - 1).saturating_mul(per_page), &mut conn).await?;
+
+    let users: Vec<UserDetailResponse> = users.into_iter().map(UserDetailResponse::from).collect();
+
+    let response = json!({
+        "users": users,
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+    });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+pub async fn get_user(state: State<Arc<AppState>>, path: Path<(i64,)>) -> Result<HttpResponse> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = User::find_by_id(path.0, &mut conn).await?;
+
+    Ok(HttpResponse::Ok().json(&UserDetailResponse::from(user)))
+}
+
+/// Creates an account on someone's behalf. Admins can't hand out a role above their own.
+pub async fn create_user(state: State<Arc<AppState>>, req: ValidatedJson<AdminUserCreateRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let role = parse_role(&req.role)?;
+
+    if !caller_role(&http_req)?.has_at_least(role) {
+        return Err(Error::ForbiddenError);
+    }
+
+    let whatsapp = normalize_whatsapp(&state, &req.whatsapp)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    if User::is_whatsapp_taken(&whatsapp, &mut conn).await? {
+        return Err(Error::ApiError(anyhow!("WhatsApp number '{}' is already in use", whatsapp)));
+    }
+
+    let new_user = NewUser {
+        username: req.username.clone(),
+        fullname: req.fullname.clone(),
+        password: req.password.clone(),
+        whatsapp,
+        role,
+        whatsapp_verified_at: None
+    };
+
+    let user = User::create_and_return(new_user, &mut conn).await?;
+
+    let event = audit_event(&http_req, audit::USER_CREATE)
+        .target(user.id)
+        .details(json!({ "username": user.username, "role": user.role.to_string() }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(HttpResponse::Created().json(&UserDetailResponse::from(user)))
+}
+
+/// Updates someone else's profile and role. A role change takes effect right away,
+/// their current tokens stop working.
+pub async fn update_user(state: State<Arc<AppState>>, path: Path<(i64,)>, req: ValidatedJson<AdminUserUpdateRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let role = req.role.as_deref().map(parse_role).transpose()?;
+    let whatsapp = req.whatsapp.as_deref().map(|whatsapp| normalize_whatsapp(&state, whatsapp)).transpose()?;
+
+    if let Some(role) = role && !caller_role(&http_req)?.has_at_least(role) {
+        return Err(Error::ForbiddenError);
+    }
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let mut user = managed_user(&http_req, path.0, &mut conn).await?;
+
+    let previous_role = user.role;
+    let mut changes = Vec::new();
+
+    let whatsapp = whatsapp.filter(|whatsapp| *whatsapp != user.whatsapp);
+
+    if let Some(whatsapp) = &whatsapp && User::is_whatsapp_taken(whatsapp, &mut conn).await? {
+        return Err(Error::ApiError(anyhow!("WhatsApp number '{}' is already in use", whatsapp)));
+    }
+
+    if let Some(role) = role && role != user.role {
+        user.role = role;
+        changes.push("role");
+    }
+
+    if let Some(fullname) = &req.fullname {
+        user.fullname = fullname.clone();
+        changes.push("fullname");
+    }
+
+    if whatsapp.is_some() {
+        changes.push("whatsapp");
+    }
+
+    let role_changed = user.role != previous_role;
+    user = user.update_profile(whatsapp.as_deref(), role_changed, &mut conn).await?;
+
+    if role_changed {
+        // both caches have to go, even when clearing one of them fails
+        let state_cleared = state.user_state_service.invalidate(user.id).await;
+        let permissions_cleared = state.permission_service.invalidate_users(&[user.id]).await;
+
+        state_cleared.and(permissions_cleared)?;
+    }
+
+    if whatsapp.is_some() {
+        send_whatsapp_verification(&state, &user).await?;
+    }
+
+    let event = audit_event(&http_req, audit::USER_UPDATE)
+        .target(user.id)
+        .details(json!({
+            "fields": changes,
+            "previous_role": previous_role.to_string(),
+            "role": user.role.to_string(),
+        }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(HttpResponse::Ok().json(&UserDetailResponse::from(user)))
+}
+
+/// Sets the status of a user managed by the caller. Their tokens stop working right
+/// away and, unless reactivated, all their sessions are ended.
+async fn change_status(state: &AppState, http_req: &web::HttpRequest, user_id: i64, status: UserStatus) -> Result<User> {
+    let mut conn = state.db_pool.get_connection().await?;
+    let user = managed_user(http_req, user_id, &mut conn).await?;
+
+    let previous_status = user.status;
+    let user = user.set_status(status, &mut conn).await?;
+
+    state.user_state_service.invalidate(user.id).await?;
+
+    if status != UserStatus::Active {
+        state.session_service.logout_all_sessions(user.id).await?;
+    }
+
+    let event = audit_event(http_req, audit::USER_STATUS_CHANGE)
+        .target(user.id)
+        .details(json!({ "from": previous_status.to_string(), "to": status.to_string() }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(user)
+}
+
+/// Suspends, deactivates or reactivates a user.
+pub async fn update_user_status(state: State<Arc<AppState>>, path: Path<(i64,)>, req: ValidatedJson<UserStatusRequest>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let status = req.status.parse::<UserStatus>()
+        .map_err(|_| Error::ValidationError(validation::field_error("status", "status_invalid", "must be active, suspended or deactivated")))?;
+
+    change_status(&state, &http_req, path.0, status).await?;
+
+    let response = json!({ "message": format!("User is now {}", status), "status": status.to_string() });
+
     Ok(HttpResponse::Ok().json(&response))
 }
 
-// use ntex::web::{self, HttpResponse};
-// use ntex::web::types::{Json, State};
-// use serde::{Deserialize, Serialize};
-// use std::sync::Arc;
-// use anyhow::anyhow;
-
-// use crate::app::AppState;
-// use crate::error::{Result, Error};
-// use crate::models::user::{User, UserRole};
-// use crate::middlewares::auth_middleware::UserInfo;
-
-// #[derive(Deserialize, Debug)]
-// pub struct UserUpdateRequest {
-//     pub fullname: String,
-//     pub whatsapp: String,
-// }
-
-// #[derive(Deserialize, Debug)]
-// pub struct AdminUserUpdateRequest {
-//     pub fullname: String,
-//     pub whatsapp: String,
-//     pub role: String,
-// }
-
-// #[derive(Deserialize, Debug)]
-// pub struct PasswordUpdateRequest {
-//     pub current_password: String,
-//     pub new_password: String,
-//     pub confirm_password: String,
-// }
-
-// #[derive(Serialize, Debug)]
-// pub struct UserResponse {
-//     pub id: i32,
-//     pub username: String,
-//     pub fullname: String,
-//     pub role: String,
-// }
-
-// #[derive(Serialize, Debug)]
-// pub struct UserDetailResponse {
-//     pub id: i32,
-//     pub username: String,
-//     pub fullname: String,
-//     pub whatsapp: String,
-//     pub role: String,
-//     pub created_at: String,
-//     pub updated_at: String,
-// }
-
-// pub async fn get_current_user(req: web::HttpRequest, state: State<Arc<AppState>>) -> Result<HttpResponse> {
-//     let user_id = req.user_id().ok_or(Error::ForbiddenError)?;
-    
-//     let mut conn = state.db_pool.get_connection().await?;
-//     let user = User::find_by_id(user_id, &mut conn).await?;
-    
-//     let response = UserResponse {
-//         id: user.id,
-//         username: user.username,
-//         fullname: user.fullname,
-//         role: user.role.to_string(),
-//     };
-    
-//     Ok(HttpResponse::Ok().json(&response))
-// }
-
-// pub async fn update_current_user(req: web::HttpRequest, state: State<Arc<AppState>>) -> Result<HttpResponse> {
-//     let user_id = req.user_id().ok_or(Error::ForbiddenError)?;
-// }
-
-// pub async fn get_user_details(req: web::HttpRequest, state: State<Arc<AppState>>) -> Result<HttpResponse> {
-//     let user_id = req.user_id().ok_or(Error::ForbiddenError)?;
-    
-//     let mut conn = state.db_pool.get_connection().await?;
-//     let user = User::find_by_id(user_id, &mut conn).await?;
-    
-//     let response = UserDetailResponse {
-//         id: user.id,
-//         username: user.username,
-//         fullname: user.fullname,
-//         whatsapp: user.whatsapp,
-//         role: user.role.to_string(),
-//         created_at: user.created_at.to_string(),
-//         updated_at: user.updated_at.to_string(),
-//     };
-    
-//     Ok(HttpResponse::Ok().json(&response))
-// }
-
-// pub async fn update_user(req: web::HttpRequest, state: State<Arc<AppState>>, body: Json<UserUpdateRequest>) -> Result<HttpResponse> {
-//     let user_id = req.user_id().ok_or(Error::ForbiddenError)?;
-    
-//     let mut conn = state.db_pool.get_connection().await?;
-//     let mut user = User::find_by_id(user_id, &mut conn).await?;
-    
-//     user.fullname = body.fullname.clone();
-//     user.whatsapp = body.whatsapp.clone();
-    
-//     let updated_user = user.update(&mut conn).await?;
-    
-//     let response = UserResponse {
-//         id: updated_user.id,
-//         username: updated_user.username,
-//         fullname: updated_user.fullname,
-//         role: updated_user.role.to_string(),
-//     };
-    
-//     Ok(HttpResponse::Ok().json(&response))
-// }
-
-// pub async fn update_password(req: web::HttpRequest, state: State<Arc<AppState>>, body: Json<PasswordUpdateRequest>) -> Result<HttpResponse> {
-//     if body.new_password != body.confirm_password {
-//         return Err(Error::ApiError(anyhow!("New password and confirmation do not match")));
-//     }
-    
-//     if body.new_password.len() < 8 {
-//         return Err(Error::ApiError(anyhow!("Password must be at least 8 characters long")));
-//     }
-    
-//     let user_id = req.user_id().ok_or(Error::ForbiddenError)?;
-    
-//     let mut conn = state.db_pool.get_connection().await?;
-//     let user = User::find_by_id(user_id, &mut conn).await?;
-    
-//     if !User::verify_password(&user.password, &body.current_password)? {
-//         return Err(Error::ApiError(anyhow!("Current password is incorrect")));
-//     }
-    
-//     let mut user_to_update = user;
-//     user_to_update.password = User::hash_password(&body.new_password)?;
-    
-//     let _updated_user = user_to_update.update(&mut conn).await?;
-    
-//     Ok(HttpResponse::Ok().json(&serde_json::json!({ "success": true, "message": "Password updated successfully" })))
-// }
-
-// // Admin-only endpoint to get any user's details
-// pub async fn admin_get_user(req: web::HttpRequest, state: State<Arc<AppState>>, path: web::types::Path<(i32,)>) -> Result<HttpResponse> {
-//     let user_role = req.user_role().ok_or(Error::ForbiddenError)?;
-    
-//     if user_role != "admin" && user_role != "superadmin" {
-//         return Err(Error::ForbiddenError);
-//     }
-    
-//     let user_id = path.0;
-    
-//     let mut conn = state.db_pool.get_connection().await?;
-//     let user = User::find_by_id(user_id, &mut conn).await?;
-    
-//     let response = UserDetailResponse {
-//         id: user.id,
-//         username: user.username,
-//         fullname: user.fullname,
-//         whatsapp: user.whatsapp,
-//         role: user.role.to_string(),
-//         created_at: user.created_at.to_string(),
-//         updated_at: user.updated_at.to_string(),
-//     };
-    
-//     Ok(HttpResponse::Ok().json(&response))
-// }
-
-// // Admin-only endpoint to update any user
-// pub async fn admin_update_user(req: web::HttpRequest, state: State<Arc<AppState>>, path: web::types::Path<(i32,)>, body: Json<AdminUserUpdateRequest>) -> Result<HttpResponse> {
-//     let user_role = req.user_role().ok_or(Error::ForbiddenError)?;
-    
-//     if user_role != "admin" && user_role != "superadmin" {
-//         return Err(Error::ForbiddenError);
-//     }
-    
-//     let user_id = path.0;
-    
-//     let mut conn = state.db_pool.get_connection().await?;
-//     let mut user = User::find_by_id(user_id, &mut conn).await?;
-    
-//     user.fullname = body.fullname.clone();
-//     user.whatsapp = body.whatsapp.clone();
-    
-//     // Only allow role updates if the current user is an admin/superadmin
-//     if let Ok(new_role) = body.role.parse::<UserRole>() {
-//         // Prevent changing superadmin roles unless you're a superadmin
-//         if user.role == UserRole::SuperAdmin && user_role != "superadmin" {
-//             return Err(Error::ApiError(anyhow!("Only superadmins can modify other superadmins")));
-//         }
-        
-//         // Prevent setting someone to superadmin unless you're a superadmin
-//         if new_role == UserRole::SuperAdmin && user_role != "superadmin" {
-//             return Err(Error::ApiError(anyhow!("Only superadmins can create new superadmins")));
-//         }
-        
-//         user.role = new_role;
-//     } else {
-//         return Err(Error::ApiError(anyhow!("Invalid role specified")));
-//     }
-    
-//     let updated_user = user.update(&mut conn).await?;
-    
-//     let response = UserResponse {
-//         id: updated_user.id,
-//         username: updated_user.username,
-//         fullname: updated_user.fullname,
-//         role: updated_user.role.to_string(),
-//     };
-    
-//     Ok(HttpResponse::Ok().json(&response))
-// }
-
-// // Admin-only endpoint to list all users
-// pub async fn list_users(req: web::HttpRequest, state: State<Arc<AppState>>) -> Result<HttpResponse> {
-//     let user_role = req.user_role().ok_or(Error::ForbiddenError)?;
-    
-//     if user_role != "admin" && user_role != "superadmin" {
-//         return Err(Error::ForbiddenError);
-//     }
-    
-//     let mut conn = state.db_pool.get_connection().await?;
-//     let users = User::get_all(&mut conn).await?;
-    
-//     let response: Vec<UserResponse> = users.into_iter().map(|user| {
-//         UserResponse {
-//             id: user.id,
-//             username: user.username,
-//             fullname: user.fullname,
-//             role: user.role.to_string(),
-//         }
-//     }).collect();
-    
-//     Ok(HttpResponse::Ok().json(&response))
-// }
+/// Users are never deleted, their history (orders, audit log, ...) has to stay intact.
+pub async fn deactivate_user(state: State<Arc<AppState>>, path: Path<(i64,)>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    change_status(&state, &http_req, path.0, UserStatus::Deactivated).await?;
+
+    let response = json!({ "message": "User deactivated" });
+
+    Ok(HttpResponse::Ok().json(&response))
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use ntex::http::header;
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::{Query, State};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::controllers::user_controller::{caller_role, parse_role};
+use crate::error::{Error, Result};
+use crate::middlewares::auth_middleware::UserInfo;
+use crate::models::audit_event as audit;
+use crate::models::user::{User, UserFilter, UserStatus};
+use crate::services::user_csv_service::{export_csv, import_csv};
+
+#[derive(Deserialize, Debug)]
+pub struct ImportQuery {
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    pub search: Option<String>,
+    pub role: Option<String>,
+    pub status: Option<String>,
+}
+
+pub async fn import_users(state: State<Arc<AppState>>, query: Query<ImportQuery>, body: String, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let role = caller_role(&http_req)?;
+    let dry_run = query.dry_run.unwrap_or(false);
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    let event = |event_type: &str| audit_event(&http_req, event_type);
+    let report = import_csv(&state, &body, role, http_req.user_id(), dry_run, &event, &mut conn).await?;
+
+    Ok(HttpResponse::Ok().json(&report))
+}
+
+pub async fn export_users(state: State<Arc<AppState>>, query: Query<ExportQuery>, http_req: web::HttpRequest) -> Result<HttpResponse> {
+    let query = query.into_inner();
+
+    let filter = UserFilter {
+        search: query.search.map(|search| search.trim().to_string()).filter(|search| !search.is_empty()),
+        role: query.role.as_deref().map(parse_role).transpose()?,
+        status: query.status.as_deref()
+            .map(|status| status.parse::<UserStatus>().map_err(|e| Error::ApiError(anyhow!(e))))
+            .transpose()?,
+    };
+
+    let mut conn = state.db_pool.get_connection().await?;
+    let users = User::find_matching(&filter, &mut conn).await?;
+
+    let event = audit_event(&http_req, audit::USER_EXPORT)
+        .details(json!({ "users": users.len() }));
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"users.csv\"")
+        .body(export_csv(&users)))
+}
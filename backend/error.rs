@@ -1,4 +1,5 @@
 use ntex::web;
+use serde_json::{json, Map, Value};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -24,17 +25,55 @@ pub enum Error {
     #[error("Io Error: {0}")]
     IoError(anyhow::Error),
 
+    #[error("Unauthorized Error")]
+    UnauthorizedError,
+
     #[error("Forbidden Error")]
     ForbiddenError,
+
+    #[error("Validation Error: {0}")]
+    ValidationError(validator::ValidationErrors),
+
+    #[error("Too Many Requests Error")]
+    TooManyRequestsError(u64), // seconds until the client may retry
     
     #[error(transparent)]
     GeneralError(anyhow::Error),
 }
 
-fn log_error(error: &Error) {
+// lets controllers run several model calls in one `conn.transaction`
+impl From<diesel::result::Error> for Error {
+    fn from(error: diesel::result::Error) -> Self {
+        Error::DatabaseError(error.into())
+    }
+}
+
+pub(crate) fn log_error(error: &Error) {
     eprintln!("[ERROR] {}", error);
 }
 
+// `{"field": ["reason", ...]}` for every invalid field
+fn validation_errors_body(errors: &validator::ValidationErrors) -> Value {
+    let fields: Map<String, Value> = errors.field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let reasons: Vec<String> = errors.iter()
+                .map(|e| e.message.as_ref().map(|m| m.to_string()).unwrap_or_else(|| e.code.to_string()))
+                .collect();
+
+            (field.to_string(), json!(reasons))
+        })
+        .collect();
+
+    json!({
+        "success": false,
+        "message": VALIDATION_MESSAGE,
+        "errors": fields
+    })
+}
+
+const VALIDATION_MESSAGE: &str = "Some fields are invalid.";
+const UNAUTHORIZED_MESSAGE: &str = "You need to be signed in to access this resource.";
 const FORBIDDEN_MESSAGE: &str = "You don't have permission to access this resource.";
 const INTERNAL_ERROR_MESSAGE: &str = "An internal server error occurred while trying to process your request.";
 
@@ -83,6 +122,12 @@ impl web::error::WebResponseError for Error {
                     .body(INTERNAL_ERROR_MESSAGE)
             },
             
+            Error::UnauthorizedError => {
+                web::HttpResponse::Unauthorized()
+                    .content_type("text/plain")
+                    .body(UNAUTHORIZED_MESSAGE)
+            },
+            
             Error::ForbiddenError => {
                 log_error(self);
                 web::HttpResponse::Forbidden()
@@ -90,6 +135,18 @@ impl web::error::WebResponseError for Error {
                     .body(FORBIDDEN_MESSAGE)
             },
             
+            Error::ValidationError(errors) => {
+                web::HttpResponse::UnprocessableEntity()
+                    .json(&validation_errors_body(errors))
+            },
+            
+            Error::TooManyRequestsError(retry_after) => {
+                web::HttpResponse::TooManyRequests()
+                    .content_type("text/plain")
+                    .set_header("Retry-After", retry_after.to_string())
+                    .body(format!("Too many attempts, please try again in {} seconds.", retry_after))
+            },
+            
             Error::GeneralError(_) => { 
                 log_error(self);
                 web::HttpResponse::InternalServerError()
@@ -0,0 +1 @@
+pub mod validated_json;
\ No newline at end of file
@@ -0,0 +1,45 @@
+use std::ops::Deref;
+
+use anyhow::anyhow;
+use ntex::http::Payload;
+use ntex::web::{DefaultError, FromRequest, HttpRequest};
+use ntex::web::types::Json;
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::error::Error;
+
+/// Drop-in replacement for `Json<T>` that also runs the `Validate` rules of `T`,
+/// rejecting the request with a field-by-field `422` before the handler runs.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest<DefaultError> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate + 'static
+{
+    type Error = Error;
+
+    async fn from_request(req: &HttpRequest, payload: &mut Payload) -> Result<Self, Self::Error> {
+        let value = <Json<T> as FromRequest<DefaultError>>::from_request(req, payload).await
+            .map_err(|e| Error::ApiError(anyhow!("Invalid request body: {}", e)))?
+            .into_inner();
+
+        value.validate().map_err(Error::ValidationError)?;
+
+        Ok(ValidatedJson(value))
+    }
+}
@@ -8,4 +8,6 @@ pub mod middlewares;
 pub mod schema;
 pub mod database;
 pub mod error;
-pub mod seeds;
\ No newline at end of file
+pub mod extractors;
+pub mod seeds;
+pub mod utils;
\ No newline at end of file
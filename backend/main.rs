@@ -1,3 +1,6 @@
+// the stacked app middlewares make for deeply nested service types
+#![recursion_limit = "256"]
+
 use std::env;
 use teapos::app::App;
 
@@ -20,6 +23,58 @@ async fn main() {
         return;
     }
 
+    // --import-users <file.csv> [--dry-run]
+    if args.len() > 1 && args[1] == "--import-users" {
+        let Some(path) = args.get(2) else {
+            eprintln!("Usage: teapos --import-users <file.csv> [--dry-run]");
+            std::process::exit(-1);
+        };
+        let dry_run = args.iter().skip(3).any(|arg| arg == "--dry-run");
+
+        let report = app.import_users(path, dry_run).await.unwrap_or_else(|e| {
+            eprintln!("Error importing users: {}", e);
+            std::process::exit(-1);
+        });
+
+        for error in &report.errors {
+            match &error.field {
+                Some(field) => eprintln!("Line {}: '{}' {}", error.line, field, error.message),
+                None => eprintln!("Line {}: {}", error.line, error.message),
+            }
+        }
+
+        if dry_run {
+            println!("Checked {} rows, {} with errors (dry run, nothing was imported)", report.rows, report.errors.len());
+        } else {
+            println!("Checked {} rows: {} users created, {} invites sent", report.rows, report.created, report.invited);
+        }
+
+        if !report.errors.is_empty() {
+            std::process::exit(-1);
+        }
+        return;
+    }
+
+    // --export-users [file.csv], to stdout without a file
+    if args.len() > 1 && args[1] == "--export-users" {
+        let csv = app.export_users().await.unwrap_or_else(|e| {
+            eprintln!("Error exporting users: {}", e);
+            std::process::exit(-1);
+        });
+
+        match args.get(2) {
+            Some(path) => {
+                if let Err(e) = std::fs::write(path, csv) {
+                    eprintln!("Error writing {}: {}", path, e);
+                    std::process::exit(-1);
+                }
+                println!("Users exported to {}", path);
+            },
+            None => print!("{}", csv)
+        }
+        return;
+    }
+
     if let Err(e) = app.run().await {
         eprintln!("{}", e);
         std::process::exit(-1);
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use ntex::service::{Identity, Middleware, Service, ServiceCtx};
+use ntex::web;
+use ntex::web::stack::WebStack;
+use serde_json::json;
+
+use crate::app::AppState;
+use crate::controllers::auth_controller::audit_event;
+use crate::error::Error;
+use crate::models::api_key::ApiKey;
+use crate::models::audit_event as audit;
+use crate::models::user::UserRole;
+use crate::services::override_service::OverrideError;
+use crate::services::token_service::{OverrideClaims, TokenClaims};
+
+#[derive(Debug, Clone)]
+pub enum AccessPolicy {
+    Public,
+    Authenticated,
+    MinRole(UserRole),
+    Permission(&'static str),
+}
+
+/// Route/scope level authorization, declared next to the routes it protects:
+///
+/// ```ignore
+/// web::scope("/role").wrap(Access::permission(permission::ROLE_MANAGE))
+/// resource("/login").wrap(Access::public())
+/// ```
+///
+/// Requests without valid credentials get a 401, authenticated requests that
+/// don't satisfy the policy get a 403. Policies of a scope and of a route in it
+/// both apply. Routes registered with `resource` that have no policy at all are
+/// for signed in users, so public routes always opt in explicitly.
+///
+/// Sensitive routes (credentials, sessions, access management) additionally call
+/// `no_impersonation()`, so a SuperAdmin acting as someone else can't use them.
+#[derive(Debug, Clone)]
+pub struct Access {
+    policy: AccessPolicy,
+    allow_impersonation: bool,
+    fallback: bool, // only applies when no other policy has checked the request
+}
+
+// left on requests by every declared policy, the fallback stands aside for them
+struct PolicyApplied;
+
+/// `web::resource` for API routes: unless it declares its own `Access`, the route
+/// is only for signed in users.
+pub fn resource<Err: web::ErrorRenderer>(path: &str) -> web::Resource<Err, WebStack<Identity, Access, Err>> {
+    web::resource(path).wrap(Access { fallback: true, ..Access::authenticated() })
+}
+
+impl Access {
+    fn new(policy: AccessPolicy) -> Self {
+        Self { policy, allow_impersonation: true, fallback: false }
+    }
+
+    pub fn public() -> Self {
+        Self::new(AccessPolicy::Public)
+    }
+
+    pub fn authenticated() -> Self {
+        Self::new(AccessPolicy::Authenticated)
+    }
+
+    pub fn min_role(role: UserRole) -> Self {
+        Self::new(AccessPolicy::MinRole(role))
+    }
+
+    pub fn permission(permission: &'static str) -> Self {
+        Self::new(AccessPolicy::Permission(permission))
+    }
+
+    pub fn no_impersonation(mut self) -> Self {
+        self.allow_impersonation = false;
+        self
+    }
+}
+
+impl<S> Middleware<S> for Access {
+    type Service = AccessMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        AccessMiddleware { service, policy: self.policy.clone(), allow_impersonation: self.allow_impersonation, fallback: self.fallback }
+    }
+}
+
+pub struct AccessMiddleware<S> {
+    service: S,
+    policy: AccessPolicy,
+    allow_impersonation: bool,
+    fallback: bool,
+}
+
+impl<S> AccessMiddleware<S> {
+    async fn authorize(&self, state: &AppState, claims: Option<&TokenClaims>, api_key: Option<&ApiKey>) -> Result<(), Error> {
+        if !self.allow_impersonation && claims.is_some_and(|claims| claims.act.is_some()) {
+            return Err(Error::ForbiddenError);
+        }
+
+        // API keys only carry permissions, routes that need a user or a role aren't for them
+        if claims.is_none() && let Some(api_key) = api_key {
+            return match &self.policy {
+                AccessPolicy::Public => Ok(()),
+                AccessPolicy::Permission(permission) => check_permission(state, None, Some(api_key), permission).await,
+                _ => Err(Error::ForbiddenError)
+            };
+        }
+
+        match &self.policy {
+            AccessPolicy::Public => Ok(()),
+            AccessPolicy::Authenticated => {
+                claims.map(|_| ()).ok_or(Error::UnauthorizedError)
+            },
+            AccessPolicy::MinRole(min_role) => {
+                let claims = claims.ok_or(Error::UnauthorizedError)?;
+                let role = claims.role.parse::<UserRole>().map_err(|_| Error::ForbiddenError)?;
+
+                if !role.has_at_least(*min_role) {
+                    return Err(Error::ForbiddenError);
+                }
+
+                Ok(())
+            },
+            AccessPolicy::Permission(permission) => {
+                check_permission(state, claims, None, permission).await
+            }
+        }
+    }
+}
+
+async fn check_permission(state: &AppState, claims: Option<&TokenClaims>, api_key: Option<&ApiKey>, permission: &str) -> Result<(), Error> {
+    if claims.is_none() && let Some(api_key) = api_key {
+        if !api_key.has_permission(permission) {
+            return Err(Error::ForbiddenError);
+        }
+
+        return Ok(());
+    }
+
+    let claims = claims.ok_or(Error::UnauthorizedError)?;
+    let role = claims.role.parse::<UserRole>().map_err(|_| Error::ForbiddenError)?;
+
+    let mut conn = state.db_pool.get_connection().await?;
+
+    if !state.permission_service.has_permission(claims.sub, role, permission, &mut conn).await? {
+        return Err(Error::ForbiddenError);
+    }
+
+    Ok(())
+}
+
+/// Same check as `Access::permission`, for handlers that only know which permission
+/// they need once the request has been looked at.
+pub async fn require_permission(req: &web::HttpRequest, state: &AppState, permission: &str) -> Result<(), Error> {
+    let claims = req.extensions().get::<TokenClaims>().cloned();
+    let api_key = req.extensions().get::<ApiKey>().cloned();
+
+    check_permission(state, claims.as_ref(), api_key.as_ref(), permission).await
+}
+
+/// For actions a cashier may only perform with a manager's approval (voids, big
+/// discounts, opening the drawer, ...). Consumes the override token sent in
+/// `X-Override-Token`, which has to be for `action` and issued to the caller's session,
+/// and records who approved it in the audit log.
+pub async fn require_override(req: &web::HttpRequest, state: &AppState, action: &str) -> Result<OverrideClaims, Error> {
+    let claims = req.extensions().get::<TokenClaims>().cloned().ok_or(Error::UnauthorizedError)?;
+
+    let token = req.headers().get("X-Override-Token")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(OverrideError::ApprovalRequired)?;
+
+    let override_claims = state.token_service.verify_override_token(token)
+        .map_err(|_| OverrideError::InvalidApproval)?;
+
+    state.override_service.redeem(&override_claims, &claims, action).await?;
+
+    let event = audit_event(req, audit::OVERRIDE_USE)
+        .target(claims.sub)
+        .details(json!({
+            "action": action,
+            "approved_by": override_claims.sub,
+            "override_id": override_claims.jti,
+            "method": req.method().as_str(),
+            "path": req.path(),
+        }));
+
+    let mut conn = state.db_pool.get_connection().await?;
+    state.audit_service.record(event, &mut conn).await;
+
+    Ok(override_claims)
+}
+
+impl<S, Err> Service<web::WebRequest<Err>> for AccessMiddleware<S>
+where
+    S: Service<web::WebRequest<Err>, Response = web::WebResponse, Error = web::Error>,
+    Err: web::ErrorRenderer,
+{
+    type Response = web::WebResponse;
+    type Error = web::Error;
+
+    ntex::forward_ready!(service);
+
+    async fn call(&self, req: web::WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
+        if self.fallback && req.extensions().contains::<PolicyApplied>() {
+            return ctx.call(&self.service, req).await;
+        }
+
+        let state = req.app_state::<Arc<AppState>>().unwrap().clone();
+        let claims = req.extensions().get::<TokenClaims>().cloned();
+        let api_key = req.extensions().get::<ApiKey>().cloned();
+
+        self.authorize(&state, claims.as_ref(), api_key.as_ref()).await?;
+        req.extensions_mut().insert(PolicyApplied);
+
+        ctx.call(&self.service, req).await
+    }
+}
\ No newline at end of file
@@ -1,11 +1,16 @@
 use std::sync::Arc;
 
-use ntex::http::header;
+use anyhow::anyhow;
+use ntex::http::header::{self, HeaderName, HeaderValue};
 use ntex::service::{Service, ServiceCtx};
 use ntex::web;
+use serde_json::json;
 
 use crate::app::AppState;
-use crate::error::Error;
+use crate::controllers::auth_controller::audit_event;
+use crate::error::{log_error, Error};
+use crate::models::api_key::ApiKey;
+use crate::models::audit_event as audit;
 use crate::services::token_service::TokenClaims;
 
 pub struct Auth;
@@ -32,42 +37,74 @@ where
 
     ntex::forward_ready!(service);
 
+    // only establishes who is calling, whether they are allowed in is decided by the
+    // `Access` policy attached to the route (see `access_middleware`)
     async fn call(&self, req: web::WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
-        let path = req.path();
-        if path.contains("/auth/login") || path.contains("/auth/register") || path.contains("/auth/refresh") {
-            return ctx.call(&self.service, req).await;
-        }
+        let token = req.headers().get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
 
-        let auth_header = req.headers().get(header::AUTHORIZATION);
-        
-        if auth_header.is_none() {
-            return Err(Error::ForbiddenError.into());
-        }
-        
-        let auth_header = auth_header.unwrap().to_str().unwrap_or_default();
-        
-        if !auth_header.starts_with("Bearer ") {
-            return Err(Error::ForbiddenError.into());
-        }
-        
-        let token = auth_header.trim_start_matches("Bearer ").trim();
-        
-        if token.is_empty() {
-            return Err(Error::ForbiddenError.into());
-        }
-        
-        let state = req.app_state::<Arc<AppState>>().unwrap();
-        
-        match state.token_service.verify_access_token(token) {
-            Ok(claims) => {
+        let api_key = req.headers().get("X-Api-Key")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        let mut impersonator_id = None;
+
+        if let Some(token) = token {
+            let state = req.app_state::<Arc<AppState>>().unwrap();
+
+            // an invalid token is treated like no token at all, protected routes answer with 401.
+            // so are tokens of deactivated users and ones minted before a role or status change
+            if let Ok(claims) = state.token_service.verify_access_token(&token)
+                && !state.session_service.is_access_token_revoked(&claims).await?
+                && state.user_state_service.is_current(&claims, &state.db_pool).await? {
+                impersonator_id = claims.impersonator_id();
                 req.extensions_mut().insert(claims);
-                let res = ctx.call(&self.service, req).await?;
-                Ok(res)
             }
-            Err(e) => {
-                Err(e.into())
+        } else if let Some(api_key) = api_key {
+            // integrations authenticate with an API key instead, same rules for invalid ones
+            let state = req.app_state::<Arc<AppState>>().unwrap();
+            let mut conn = state.db_pool.get_connection().await.map_err(Error::from)?;
+
+            match ApiKey::authenticate(&api_key, &mut conn).await {
+                Ok(api_key) => { req.extensions_mut().insert(api_key); },
+                Err(e @ Error::DatabaseError(_)) => return Err(e.into()),
+                Err(_) => {}
             }
         }
+
+        let Some(impersonator_id) = impersonator_id else {
+            return ctx.call(&self.service, req).await;
+        };
+
+        let state = req.app_state::<Arc<AppState>>().unwrap().clone();
+        let mut res = ctx.call(&self.service, req).await?;
+
+        // impersonated requests are flagged to the client, and every one of them ends up
+        // in the audit log under the SuperAdmin that made it
+        res.headers_mut().insert(HeaderName::from_static("x-impersonated-by"), HeaderValue::from(impersonator_id));
+
+        let http_req = res.request();
+        let mut event = audit_event(http_req, audit::IMPERSONATION_REQUEST)
+            .details(json!({
+                "method": http_req.method().as_str(),
+                "path": http_req.path(),
+                "status": res.status().as_u16(),
+            }));
+
+        if let Some(user_id) = http_req.user_id() {
+            event = event.target(user_id);
+        }
+
+        match state.db_pool.get_connection().await {
+            Ok(mut conn) => state.audit_service.record(event, &mut conn).await,
+            Err(e) => log_error(&Error::GeneralError(anyhow!("Failed to record audit event '{}': {}", audit::IMPERSONATION_REQUEST, e)))
+        }
+
+        Ok(res)
     }
 }
 
@@ -79,9 +116,25 @@ pub fn get_user_role(req: &web::HttpRequest) -> Option<String> {
     req.extensions().get::<TokenClaims>().map(|claims| claims.role.clone())
 }
 
+pub fn get_token_claims(req: &web::HttpRequest) -> Option<TokenClaims> {
+    req.extensions().get::<TokenClaims>().cloned()
+}
+
+pub fn get_impersonator_id(req: &web::HttpRequest) -> Option<i64> {
+    req.extensions().get::<TokenClaims>().and_then(|claims| claims.impersonator_id())
+}
+
+pub fn get_api_key(req: &web::HttpRequest) -> Option<ApiKey> {
+    req.extensions().get::<ApiKey>().cloned()
+}
+
 pub trait UserInfo {
     fn user_id(&self) -> Option<i64>;
     fn user_role(&self) -> Option<String>;
+    fn token_claims(&self) -> Option<TokenClaims>;
+    fn impersonator_id(&self) -> Option<i64>; // the SuperAdmin really behind the request, if any
+    fn api_key(&self) -> Option<ApiKey>; // set instead of the claims for integrations
+    fn api_key_store(&self) -> Option<String>; // the only store a store-bound key may see
 }
 
 impl UserInfo for web::HttpRequest {
@@ -92,4 +145,20 @@ impl UserInfo for web::HttpRequest {
     fn user_role(&self) -> Option<String> {
         get_user_role(self)
     }
+
+    fn token_claims(&self) -> Option<TokenClaims> {
+        get_token_claims(self)
+    }
+
+    fn impersonator_id(&self) -> Option<i64> {
+        get_impersonator_id(self)
+    }
+
+    fn api_key(&self) -> Option<ApiKey> {
+        get_api_key(self)
+    }
+
+    fn api_key_store(&self) -> Option<String> {
+        get_api_key(self).and_then(|key| key.store)
+    }
 }
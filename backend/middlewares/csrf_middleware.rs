@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use ntex::http::Method;
+use ntex::http::header::HeaderMap;
+use ntex::service::{Middleware, Service, ServiceCtx};
+use ntex::web;
+
+use crate::app::AppState;
+use crate::error::Error;
+use crate::utils::{cookie, crypto};
+
+/// Double-submit CSRF check for browsers in cookie mode (see `COOKIE_AUTH`).
+///
+/// Requests carrying one of our cookies have to repeat the CSRF cookie in the
+/// `X-CSRF-Token` header to change anything, which a cross-site page can't do since
+/// it can't read the cookie. Clients sending their tokens in headers (devices,
+/// integrations) don't carry the cookies and aren't affected.
+pub struct Csrf;
+
+impl<S> Middleware<S> for Csrf {
+    type Service = CsrfMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        CsrfMiddleware { service }
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: S,
+}
+
+impl<S, Err> Service<web::WebRequest<Err>> for CsrfMiddleware<S>
+where
+    S: Service<web::WebRequest<Err>, Response = web::WebResponse, Error = web::Error>,
+    Err: web::ErrorRenderer,
+{
+    type Response = web::WebResponse;
+    type Error = web::Error;
+
+    ntex::forward_ready!(service);
+
+    async fn call(&self, req: web::WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
+        let state = req.app_state::<Arc<AppState>>().unwrap();
+        let safe_method = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+
+        if !state.config.cookie_auth || safe_method {
+            return ctx.call(&self.service, req).await;
+        }
+
+        if !csrf_passes(req.headers()) {
+            return Err(Error::ForbiddenError.into());
+        }
+
+        ctx.call(&self.service, req).await
+    }
+}
+
+// requests without our cookies pass, the others need the CSRF cookie echoed in the header
+fn csrf_passes(headers: &HeaderMap) -> bool {
+    let csrf_cookie = cookie::get(headers, cookie::CSRF_COOKIE);
+
+    if csrf_cookie.is_none() && cookie::get(headers, cookie::REFRESH_COOKIE).is_none() {
+        return true;
+    }
+
+    let csrf_header = headers.get(cookie::CSRF_HEADER).and_then(|v| v.to_str().ok());
+
+    matches!((csrf_cookie, csrf_header), (Some(expected), Some(actual)) if crypto::constant_time_eq(&expected, actual))
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::header::{self, HeaderName, HeaderValue};
+
+    use super::*;
+
+    fn headers(cookies: Option<&'static str>, csrf: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        if let Some(cookies) = cookies {
+            headers.insert(header::COOKIE, HeaderValue::from_static(cookies));
+        }
+
+        if let Some(csrf) = csrf {
+            headers.insert(HeaderName::from_static("x-csrf-token"), HeaderValue::from_static(csrf));
+        }
+
+        headers
+    }
+
+    #[test]
+    fn header_clients_without_cookies_pass() {
+        assert!(csrf_passes(&headers(None, None)));
+        assert!(csrf_passes(&headers(Some("theme=dark"), None)));
+    }
+
+    #[test]
+    fn a_cookie_without_the_header_is_refused() {
+        assert!(!csrf_passes(&headers(Some("teapos_csrf=abc123"), None)));
+        assert!(!csrf_passes(&headers(Some("teapos_refresh=r3fr3sh"), None)));
+    }
+
+    #[test]
+    fn a_mismatched_token_is_refused() {
+        assert!(!csrf_passes(&headers(Some("teapos_csrf=abc123"), Some("abc124"))));
+        assert!(!csrf_passes(&headers(Some("teapos_csrf=abc123"), Some(""))));
+    }
+
+    #[test]
+    fn a_refresh_cookie_needs_the_csrf_cookie_too() {
+        assert!(!csrf_passes(&headers(Some("teapos_refresh=r3fr3sh"), Some("abc123"))));
+    }
+
+    #[test]
+    fn the_echoed_cookie_passes() {
+        assert!(csrf_passes(&headers(Some("teapos_refresh=r3fr3sh; teapos_csrf=abc123"), Some("abc123"))));
+        assert!(csrf_passes(&headers(Some("teapos_csrf=\"abc123\""), Some("abc123"))));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod response_middleware;
-pub mod auth_middleware;
\ No newline at end of file
+pub mod auth_middleware;
+pub mod access_middleware;
+pub mod csrf_middleware;
\ No newline at end of file
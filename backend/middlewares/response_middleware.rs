@@ -89,6 +89,14 @@ impl<S> ResponseMiddleware<S> {
         }
     }
     
+    // only JSON (or untyped) bodies get the envelope, anything else is passed through as-is
+    fn is_json_response(res: &web::WebResponse) -> bool {
+        match res.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+            Some(content_type) => content_type.starts_with("application/json"),
+            None => true
+        }
+    }
+
     #[inline]
     fn format_response(&self, status: ntex::http::StatusCode, body_str: &str) -> Cow<'static, str> {
         if !status.is_success() {
@@ -100,6 +108,12 @@ impl<S> ResponseMiddleware<S> {
     
     fn format_error_response(&self, body_str: &str) -> Cow<'static, str> {
         static ERROR_FALLBACK: &str = "{\"success\":false,\"message\":\"Oops, seems like our server had a little bit of hickups, Please try again later\"}";
+
+        // structured errors (e.g. validation errors) are built with the envelope already
+        let trimmed = body_str.trim();
+        if trimmed.starts_with('{') && trimmed.contains("\"success\":") {
+            return Cow::Owned(body_str.to_owned());
+        }
         
         let error_response = ErrorResponse::new(body_str);
         
@@ -151,6 +165,10 @@ where
 
     async fn call(&self, req: web::WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
         let mut res = ctx.call(&self.service, req).await?;
+
+        if res.status().is_success() && !Self::is_json_response(&res) {
+            return Ok(res);
+        }
         
         res.headers_mut().insert(
             header::CONTENT_TYPE,
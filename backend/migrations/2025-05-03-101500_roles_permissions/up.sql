@@ -0,0 +1,78 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS roles (
+    id BIGSERIAL PRIMARY KEY CHECK (id >= 0),
+    name VARCHAR(64) NOT NULL UNIQUE,
+    description VARCHAR(255) NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+SELECT diesel_manage_updated_at('roles');
+
+-- the permission catalog is defined by the application (see models/permission.rs),
+-- what admins edit is which role gets which permission
+CREATE TABLE IF NOT EXISTS permissions (
+    id BIGSERIAL PRIMARY KEY CHECK (id >= 0),
+    code VARCHAR(64) NOT NULL UNIQUE,
+    description VARCHAR(255) NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role_id BIGINT NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    permission_id BIGINT NOT NULL REFERENCES permissions(id) ON DELETE CASCADE,
+    PRIMARY KEY (role_id, permission_id)
+);
+
+CREATE TABLE IF NOT EXISTS user_role_assignments (
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role_id BIGINT NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id)
+);
+
+CREATE INDEX IF NOT EXISTS user_role_assignments_role_id_idx ON user_role_assignments (role_id);
+
+INSERT INTO permissions (code, description) VALUES
+    ('order.create', 'Take and edit orders'),
+    ('order.void', 'Void orders and paid items'),
+    ('order.discount', 'Give discounts'),
+    ('order.refund', 'Refund paid orders'),
+    ('drawer.open', 'Open the cash drawer'),
+    ('kitchen.view', 'See and update the kitchen queue'),
+    ('report.view', 'See sales and revenue reports'),
+    ('menu.edit', 'Edit menu items and prices'),
+    ('user.manage', 'Manage user accounts'),
+    ('role.manage', 'Manage roles and their permissions')
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO roles (name, description) VALUES
+    ('owner', 'Store owner, can do everything'),
+    ('shift_manager', 'Runs a shift, approves voids, discounts and refunds'),
+    ('cashier', 'Takes orders and handles payments'),
+    ('barista', 'Prepares drinks'),
+    ('kitchen', 'Prepares food')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id FROM roles r JOIN permissions p ON (
+    r.name = 'owner'
+    OR (r.name = 'shift_manager' AND p.code IN ('order.create', 'order.void', 'order.discount', 'order.refund', 'drawer.open', 'kitchen.view', 'report.view'))
+    OR (r.name = 'cashier' AND p.code IN ('order.create', 'drawer.open'))
+    OR (r.name = 'barista' AND p.code IN ('order.create', 'kitchen.view'))
+    OR (r.name = 'kitchen' AND p.code IN ('kitchen.view'))
+)
+ON CONFLICT DO NOTHING;
+
+-- admins managed users before permissions existed, keep them able to
+INSERT INTO roles (name, description) VALUES
+    ('admin', 'Manages user accounts and roles')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id FROM roles r JOIN permissions p ON p.code IN ('user.manage', 'role.manage')
+WHERE r.name = 'admin'
+ON CONFLICT DO NOTHING;
+
+INSERT INTO user_role_assignments (user_id, role_id)
+SELECT u.id, r.id FROM users u JOIN roles r ON r.name = 'admin'
+WHERE u.role = 'admin'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- argon2 hash of the employee's 4-6 digit terminal PIN
+ALTER TABLE users ADD COLUMN IF NOT EXISTS pin VARCHAR(255) NULL;
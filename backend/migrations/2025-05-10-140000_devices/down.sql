@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS devices CASCADE;
+DROP TYPE IF EXISTS device_type;
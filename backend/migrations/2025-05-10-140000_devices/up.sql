@@ -0,0 +1,22 @@
+-- Your SQL goes here
+DO $$ BEGIN
+    CREATE TYPE device_type AS ENUM ('pos', 'kds', 'kiosk');
+EXCEPTION 
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS devices (
+    id BIGSERIAL PRIMARY KEY CHECK (id >= 0),
+    device_uid VARCHAR(36) NOT NULL UNIQUE,
+    name VARCHAR(255) NOT NULL,
+    store VARCHAR(255) NOT NULL,
+    device_type device_type NOT NULL,
+    secret_hash VARCHAR(64) NOT NULL,
+    enrolled_by BIGINT NULL REFERENCES users(id) ON DELETE SET NULL,
+    last_seen_at TIMESTAMP NULL,
+    revoked_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+SELECT diesel_manage_updated_at('devices');
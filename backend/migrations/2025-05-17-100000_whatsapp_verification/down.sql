@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS users_whatsapp_verified_key;
+ALTER TABLE users DROP COLUMN IF EXISTS whatsapp_verified_at;
+ALTER TABLE users ALTER COLUMN whatsapp TYPE VARCHAR(15);
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+-- numbers are stored in E.164 from now on, which takes up to 16 chars with the '+'
+ALTER TABLE users ALTER COLUMN whatsapp TYPE VARCHAR(16);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS whatsapp_verified_at TIMESTAMP NULL;
+
+-- numbers stored so far are as typed, bring them to E.164 the way `phone::normalize_e164`
+-- does so lookups and the index below match them. National numbers are taken as Indonesian,
+-- the default DEFAULT_COUNTRY_CODE; whatever can't be normalized is left for its owner to fix
+WITH normalized AS (
+    SELECT id, '+' || CASE
+        WHEN btrim(whatsapp) LIKE '+%' THEN digits
+        WHEN digits LIKE '00%' THEN substr(digits, 3)
+        WHEN digits LIKE '0%' THEN '62' || substr(digits, 2)
+        ELSE '62' || digits
+    END AS e164
+    FROM (
+        SELECT id, whatsapp, regexp_replace(whatsapp, '[^0-9]', '', 'g') AS digits
+        FROM users
+        WHERE btrim(whatsapp) ~ '^\+?[0-9 ().-]+$'
+    ) AS numbers
+)
+UPDATE users SET whatsapp = normalized.e164
+FROM normalized
+WHERE users.id = normalized.id
+    AND normalized.e164 ~ '^\+[1-9][0-9]{7,14}$'
+    AND users.whatsapp <> normalized.e164;
+
+-- a number can be registered by several accounts, but verified by only one of them
+CREATE UNIQUE INDEX IF NOT EXISTS users_whatsapp_verified_key ON users (whatsapp) WHERE whatsapp_verified_at IS NOT NULL;
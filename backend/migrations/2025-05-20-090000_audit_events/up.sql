@@ -0,0 +1,25 @@
+-- Your SQL goes here
+-- actor/target ids are kept without a foreign key on purpose, the trail has to survive the user being deleted
+CREATE TABLE IF NOT EXISTS audit_events (
+    id BIGSERIAL PRIMARY KEY CHECK (id >= 0),
+    event_type VARCHAR(64) NOT NULL,
+    actor_id BIGINT NULL,
+    target_id BIGINT NULL,
+    ip_address VARCHAR(45) NOT NULL,
+    user_agent VARCHAR(512) NOT NULL,
+    details JSONB NOT NULL DEFAULT '{}'::jsonb,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX IF NOT EXISTS audit_events_event_type_idx ON audit_events (event_type, created_at);
+CREATE INDEX IF NOT EXISTS audit_events_actor_id_idx ON audit_events (actor_id, created_at);
+CREATE INDEX IF NOT EXISTS audit_events_target_id_idx ON audit_events (target_id, created_at);
+
+INSERT INTO permissions (code, description) VALUES
+    ('audit.view', 'See the security audit log')
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id FROM roles r JOIN permissions p ON (r.name = 'owner' AND p.code = 'audit.view')
+ON CONFLICT DO NOTHING;
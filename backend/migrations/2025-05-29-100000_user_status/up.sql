@@ -0,0 +1,11 @@
+-- Your SQL goes here
+DO $$ BEGIN
+    CREATE TYPE user_status AS ENUM ('active', 'suspended', 'deactivated');
+EXCEPTION 
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- every token carries the version it was minted with, bumping it invalidates them all
+ALTER TABLE users
+    ADD COLUMN status user_status NOT NULL DEFAULT 'active',
+    ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
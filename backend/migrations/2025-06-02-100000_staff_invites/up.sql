@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- only the hash of the invite code is stored, the code itself is sent to the invitee's WhatsApp
+CREATE TABLE IF NOT EXISTS staff_invites (
+    id BIGSERIAL PRIMARY KEY CHECK (id >= 0),
+    whatsapp VARCHAR(16) NOT NULL,
+    fullname VARCHAR(255) NOT NULL,
+    role user_role NOT NULL,
+    store VARCHAR(255) NULL,
+    code_hash VARCHAR(64) NOT NULL UNIQUE,
+    invited_by BIGINT NULL REFERENCES users(id) ON DELETE SET NULL,
+    user_id BIGINT NULL REFERENCES users(id) ON DELETE SET NULL, -- the account created from the invite
+    expires_at TIMESTAMP NOT NULL,
+    accepted_at TIMESTAMP NULL,
+    revoked_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+SELECT diesel_manage_updated_at('staff_invites');
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM permissions WHERE code = 'employee.manage';
+DROP TABLE IF EXISTS employees;
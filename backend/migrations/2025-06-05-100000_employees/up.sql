@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- payroll and scheduling details of staff, kept apart from the login account;
+-- the terminal PIN stays on `users.pin` where PIN login reads it
+CREATE TABLE IF NOT EXISTS employees (
+    id BIGSERIAL PRIMARY KEY CHECK (id >= 0),
+    user_id BIGINT NOT NULL UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+    employee_code VARCHAR(32) NOT NULL UNIQUE,
+    job_title VARCHAR(255) NOT NULL DEFAULT '',
+    hourly_rate BIGINT NULL CHECK (hourly_rate >= 0), -- in the smallest currency unit
+    hire_date DATE NOT NULL,
+    termination_date DATE NULL,
+    stores TEXT[] NOT NULL DEFAULT '{}',
+    emergency_contact_name VARCHAR(255) NULL,
+    emergency_contact_phone VARCHAR(16) NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK (termination_date IS NULL OR termination_date >= hire_date)
+);
+
+SELECT diesel_manage_updated_at('employees');
+
+INSERT INTO permissions (code, description) VALUES
+    ('employee.manage', 'Manage employee profiles, pay rates and store assignments')
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id FROM roles r JOIN permissions p ON (r.name = 'owner' AND p.code = 'employee.manage')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,180 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use diesel_async::AsyncPgConnection;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use diesel::result::Error as DieselError;
+
+use crate::error::{Error as AppError, Result};
+use crate::utils::crypto;
+use thiserror::Error;
+
+use crate::schema::api_keys;
+
+const KEY_PREFIX: &str = "tpk";
+
+// last_used_at is only a hint for admins, no need to write it on every request
+const LAST_USED_RESOLUTION: i64 = 60; // in sec
+
+impl From<ApiKeyError> for AppError {
+    fn from(error: ApiKeyError) -> Self {
+        match error {
+            ApiKeyError::DatabaseError(_) => AppError::DatabaseError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub permissions: Vec<String>,
+    pub store: Option<String>, // `None` means the key isn't tied to a store
+    pub created_by: Option<i64>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub permissions: Vec<String>,
+    pub store: Option<String>,
+    pub created_by: Option<i64>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Error)]
+pub enum ApiKeyError {
+    #[error("Invalid API key")]
+    InvalidKey,
+
+    #[error("API key with ID '{0}' not found")]
+    ApiKeyIDNotFound(i64),
+
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+impl ApiKey {
+    /// Creates a key, returning it together with the full key string. Like device
+    /// secrets, the key is only ever shown here, just its hash is stored.
+    pub async fn create(
+        name: String,
+        permissions: Vec<String>,
+        store: Option<String>,
+        expires_at: Option<NaiveDateTime>,
+        created_by: i64,
+        conn: &mut AsyncPgConnection
+    ) -> Result<(ApiKey, String)> {
+        // hex only, so the prefix can be split off the key again
+        let prefix = format!("{:012x}", rand::rng().random::<u64>() & 0xffff_ffff_ffff);
+        let key = format!("{}_{}_{}", KEY_PREFIX, prefix, crypto::random_token(32));
+
+        let new_key = NewApiKey {
+            name,
+            prefix,
+            key_hash: crypto::sha256_hex(&key),
+            permissions,
+            store,
+            created_by: Some(created_by),
+            expires_at,
+        };
+
+        let api_key = diesel::insert_into(api_keys::table)
+            .values(&new_key)
+            .get_result(conn)
+            .await
+            .map_err(ApiKeyError::DatabaseError)?;
+
+        Ok((api_key, key))
+    }
+
+    pub async fn find_by_id(id: i64, conn: &mut AsyncPgConnection) -> Result<ApiKey> {
+        api_keys::table
+            .find(id)
+            .first(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    ApiKeyError::ApiKeyIDNotFound(id).into()
+                } else {
+                    ApiKeyError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    /// Checks a key sent in `X-Api-Key`, rejecting revoked and expired ones, and
+    /// records that it was used.
+    pub async fn authenticate(key: &str, conn: &mut AsyncPgConnection) -> Result<ApiKey> {
+        let prefix = key.strip_prefix(KEY_PREFIX)
+            .and_then(|rest| rest.strip_prefix('_'))
+            .and_then(|rest| rest.split_once('_'))
+            .map(|(prefix, _)| prefix)
+            .ok_or(ApiKeyError::InvalidKey)?;
+
+        let mut api_key: ApiKey = api_keys::table
+            .filter(api_keys::prefix.eq(prefix))
+            .first(conn)
+            .await
+            .map_err(|e| -> AppError {
+                if let DieselError::NotFound = e {
+                    ApiKeyError::InvalidKey.into()
+                } else {
+                    ApiKeyError::DatabaseError(e).into()
+                }
+            })?;
+
+        let now = Utc::now().naive_utc();
+
+        if api_key.revoked_at.is_some()
+            || api_key.expires_at.is_some_and(|expires_at| expires_at <= now)
+            || !crypto::constant_time_eq(&api_key.key_hash, &crypto::sha256_hex(key)) {
+            return Err(ApiKeyError::InvalidKey.into());
+        }
+
+        if api_key.last_used_at.is_none_or(|last_used_at| now - last_used_at >= Duration::seconds(LAST_USED_RESOLUTION)) {
+            diesel::update(api_keys::table.find(api_key.id))
+                .set(api_keys::last_used_at.eq(now))
+                .execute(conn)
+                .await
+                .map_err(ApiKeyError::DatabaseError)?;
+
+            api_key.last_used_at = Some(now);
+        }
+
+        Ok(api_key)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<ApiKey>> {
+        api_keys::table
+            .order(api_keys::created_at.desc())
+            .load::<ApiKey>(conn)
+            .await
+            .map_err(|e| ApiKeyError::DatabaseError(e).into())
+    }
+
+    pub async fn revoke(&self, conn: &mut AsyncPgConnection) -> Result<ApiKey> {
+        diesel::update(api_keys::table.find(self.id))
+            .set(api_keys::revoked_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)
+            .await
+            .map_err(|e| ApiKeyError::DatabaseError(e).into())
+    }
+}
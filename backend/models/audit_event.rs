@@ -0,0 +1,189 @@
+use diesel::prelude::*;
+use diesel::pg::Pg;
+use diesel_async::RunQueryDsl;
+use diesel_async::AsyncPgConnection;
+use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use diesel::result::Error as DieselError;
+
+use crate::error::{Error as AppError, Result};
+use thiserror::Error;
+
+use crate::schema::audit_events;
+
+pub const LOGIN_SUCCESS: &str = "login.success";
+pub const LOGIN_FAILURE: &str = "login.failure";
+pub const TOKEN_REFRESH: &str = "token.refresh";
+pub const LOGOUT: &str = "logout";
+pub const PASSWORD_RESET: &str = "password.reset";
+pub const PASSWORD_CHANGE: &str = "password.change";
+pub const ROLE_CREATE: &str = "role.create";
+pub const ROLE_UPDATE: &str = "role.update";
+pub const ROLE_DELETE: &str = "role.delete";
+pub const ROLE_PERMISSIONS_CHANGE: &str = "role.permissions_change";
+pub const USER_CREATE: &str = "user.create";
+pub const USER_UPDATE: &str = "user.update";
+pub const USER_ROLES_CHANGE: &str = "user.roles_change";
+pub const USER_STATUS_CHANGE: &str = "user.status_change";
+pub const USER_IMPORT: &str = "user.import";
+pub const USER_EXPORT: &str = "user.export";
+pub const SESSION_REVOKE: &str = "session.revoke";
+pub const SESSION_REVOKE_ALL: &str = "session.revoke_all";
+pub const IMPERSONATION_START: &str = "impersonation.start";
+pub const IMPERSONATION_STOP: &str = "impersonation.stop";
+pub const IMPERSONATION_REQUEST: &str = "impersonation.request";
+pub const API_KEY_CREATE: &str = "api_key.create";
+pub const API_KEY_REVOKE: &str = "api_key.revoke";
+pub const DEVICE_ENROLL: &str = "device.enroll";
+pub const DEVICE_REVOKE: &str = "device.revoke";
+pub const DRAWER_OPEN: &str = "drawer.open";
+pub const IDENTITY_LINK: &str = "identity.link";
+pub const OVERRIDE_APPROVE: &str = "override.approve";
+pub const OVERRIDE_FAILURE: &str = "override.failure";
+pub const OVERRIDE_USE: &str = "override.use";
+pub const INVITE_CREATE: &str = "invite.create";
+pub const INVITE_REVOKE: &str = "invite.revoke";
+pub const INVITE_ACCEPT: &str = "invite.accept";
+pub const EMPLOYEE_CREATE: &str = "employee.create";
+pub const EMPLOYEE_UPDATE: &str = "employee.update";
+pub const EMPLOYEE_DELETE: &str = "employee.delete";
+
+const USER_AGENT_MAX_LENGTH: usize = 512;
+
+impl From<AuditEventError> for AppError {
+    fn from(error: AuditEventError) -> Self {
+        AppError::DatabaseError(error.into())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = audit_events)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub event_type: String,
+    pub actor_id: Option<i64>, // who did it, `None` for anonymous requests (e.g. a failed login)
+    pub target_id: Option<i64>, // the user it was done to
+    pub ip_address: String,
+    pub user_agent: String,
+    pub details: Value,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Insertable)]
+#[diesel(table_name = audit_events)]
+pub struct NewAuditEvent {
+    pub event_type: String,
+    pub actor_id: Option<i64>,
+    pub target_id: Option<i64>,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub details: Value,
+}
+
+impl NewAuditEvent {
+    pub fn new(event_type: &str, ip_address: String, user_agent: String) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            actor_id: None,
+            target_id: None,
+            ip_address,
+            user_agent: user_agent.chars().take(USER_AGENT_MAX_LENGTH).collect(),
+            details: Value::Object(Default::default()),
+        }
+    }
+
+    pub fn actor(mut self, actor_id: i64) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn target(mut self, target_id: i64) -> Self {
+        self.target_id = Some(target_id);
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Narrows an audit log query down, every `None` field matches everything.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub event_type: Option<String>,
+    pub actor_id: Option<i64>,
+    pub target_id: Option<i64>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Error)]
+pub enum AuditEventError {
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+impl AuditEvent {
+    pub async fn create(new_event: NewAuditEvent, conn: &mut AsyncPgConnection) -> Result<AuditEvent> {
+        diesel::insert_into(audit_events::table)
+            .values(&new_event)
+            .get_result(conn)
+            .await
+            .map_err(|e| AuditEventError::DatabaseError(e).into())
+    }
+
+    fn filtered(filter: &AuditFilter) -> audit_events::BoxedQuery<'_, Pg> {
+        let mut query = audit_events::table.into_boxed();
+
+        if let Some(event_type) = &filter.event_type {
+            query = query.filter(audit_events::event_type.eq(event_type));
+        }
+
+        if let Some(actor_id) = filter.actor_id {
+            query = query.filter(audit_events::actor_id.eq(actor_id));
+        }
+
+        if let Some(target_id) = filter.target_id {
+            query = query.filter(audit_events::target_id.eq(target_id));
+        }
+
+        if let Some(from) = filter.from {
+            query = query.filter(audit_events::created_at.ge(from));
+        }
+
+        if let Some(to) = filter.to {
+            query = query.filter(audit_events::created_at.lt(to));
+        }
+
+        query
+    }
+
+    /// Returns one page of the events matching the filter, newest first, together
+    /// with the total number of matching events.
+    pub async fn search(filter: &AuditFilter, limit: i64, offset: i64, conn: &mut AsyncPgConnection) -> Result<(Vec<AuditEvent>, i64)> {
+        let total = Self::filtered(filter)
+            .count()
+            .get_result::<i64>(conn)
+            .await
+            .map_err(AuditEventError::DatabaseError)?;
+
+        let events = Self::filtered(filter)
+            .order((audit_events::created_at.desc(), audit_events::id.desc()))
+            .limit(limit)
+            .offset(offset)
+            .load::<AuditEvent>(conn)
+            .await
+            .map_err(AuditEventError::DatabaseError)?;
+
+        Ok((events, total))
+    }
+
+    pub async fn delete_older_than(cutoff: NaiveDateTime, conn: &mut AsyncPgConnection) -> Result<usize> {
+        diesel::delete(audit_events::table.filter(audit_events::created_at.lt(cutoff)))
+            .execute(conn)
+            .await
+            .map_err(|e| AuditEventError::DatabaseError(e).into())
+    }
+}
@@ -0,0 +1,204 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use diesel_async::AsyncPgConnection;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
+use diesel::{AsExpression, FromSqlRow};
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::deserialize::{self, FromSql};
+use diesel::sql_types::Text;
+use diesel::result::Error as DieselError;
+use uuid::Uuid;
+
+use crate::error::{Error as AppError, Result};
+use crate::utils::crypto;
+use thiserror::Error;
+
+use crate::schema::devices;
+use crate::schema::sql_types::DeviceType as DeviceTypeSqlType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = DeviceTypeSqlType)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceType {
+    Pos,
+    Kds,
+    Kiosk,
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceType::Pos => write!(f, "pos"),
+            DeviceType::Kds => write!(f, "kds"),
+            DeviceType::Kiosk => write!(f, "kiosk"),
+        }
+    }
+}
+
+impl FromStr for DeviceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pos" => Ok(DeviceType::Pos),
+            "kds" => Ok(DeviceType::Kds),
+            "kiosk" => Ok(DeviceType::Kiosk),
+            _ => Err(format!("Unknown device type: {}", s)),
+        }
+    }
+}
+
+impl ToSql<DeviceTypeSqlType, Pg> for DeviceType {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        match *self {
+            DeviceType::Pos => <&str as ToSql<Text, Pg>>::to_sql(&"pos", out),
+            DeviceType::Kds => <&str as ToSql<Text, Pg>>::to_sql(&"kds", out),
+            DeviceType::Kiosk => <&str as ToSql<Text, Pg>>::to_sql(&"kiosk", out)
+        }
+    }
+}
+
+impl FromSql<DeviceTypeSqlType, Pg> for DeviceType {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        let s = std::str::from_utf8(bytes.as_bytes())?;
+        match s {
+            "pos" => Ok(DeviceType::Pos),
+            "kds" => Ok(DeviceType::Kds),
+            "kiosk" => Ok(DeviceType::Kiosk),
+            s => Err(format!("Unrecognized enum variant: {}", s).into()),
+        }
+    }
+}
+
+impl From<DeviceError> for AppError {
+    fn from(error: DeviceError) -> Self {
+        match error {
+            DeviceError::DatabaseError(_) => AppError::DatabaseError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, AsChangeset)]
+#[diesel(table_name = devices)]
+pub struct Device {
+    pub id: i64,
+    pub device_uid: String,
+    pub name: String,
+    pub store: String,
+    pub device_type: DeviceType,
+    #[serde(skip_serializing)]
+    pub secret_hash: String,
+    pub enrolled_by: Option<i64>,
+    pub last_seen_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Insertable)]
+#[diesel(table_name = devices)]
+pub struct NewDevice {
+    pub device_uid: String,
+    pub name: String,
+    pub store: String,
+    pub device_type: DeviceType,
+    pub secret_hash: String,
+    pub enrolled_by: Option<i64>,
+}
+
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    #[error("Invalid device credentials")]
+    InvalidCredentials,
+
+    #[error("Device with ID '{0}' not found")]
+    DeviceIDNotFound(i64),
+
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+impl Device {
+    /// Enrolls a new device, returning it together with its secret. The secret is
+    /// only ever shown here, just its hash is stored.
+    pub async fn enroll(name: String, store: String, device_type: DeviceType, enrolled_by: i64, conn: &mut AsyncPgConnection) -> Result<(Device, String)> {
+        let secret = crypto::random_token(32);
+
+        let new_device = NewDevice {
+            device_uid: Uuid::new_v4().to_string(),
+            name,
+            store,
+            device_type,
+            secret_hash: crypto::sha256_hex(&secret),
+            enrolled_by: Some(enrolled_by),
+        };
+
+        let device = diesel::insert_into(devices::table)
+            .values(&new_device)
+            .get_result(conn)
+            .await
+            .map_err(DeviceError::DatabaseError)?;
+
+        Ok((device, secret))
+    }
+
+    pub async fn find_by_id(id: i64, conn: &mut AsyncPgConnection) -> Result<Device> {
+        devices::table
+            .find(id)
+            .first(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    DeviceError::DeviceIDNotFound(id).into()
+                } else {
+                    DeviceError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    /// Checks a device credential (`X-Device-Id` / `X-Device-Secret`) and marks the device as seen.
+    pub async fn authenticate(device_uid: &str, secret: &str, conn: &mut AsyncPgConnection) -> Result<Device> {
+        let device: Device = devices::table
+            .filter(devices::device_uid.eq(device_uid))
+            .first(conn)
+            .await
+            .map_err(|e| -> AppError {
+                if let DieselError::NotFound = e {
+                    DeviceError::InvalidCredentials.into()
+                } else {
+                    DeviceError::DatabaseError(e).into()
+                }
+            })?;
+
+        if device.revoked_at.is_some() || !crypto::constant_time_eq(&device.secret_hash, &crypto::sha256_hex(secret)) {
+            return Err(DeviceError::InvalidCredentials.into());
+        }
+
+        diesel::update(devices::table.find(device.id))
+            .set(devices::last_seen_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)
+            .await
+            .map_err(|e| DeviceError::DatabaseError(e).into())
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<Device>> {
+        devices::table
+            .order(devices::created_at.desc())
+            .load::<Device>(conn)
+            .await
+            .map_err(|e| DeviceError::DatabaseError(e).into())
+    }
+
+    pub async fn revoke(&self, conn: &mut AsyncPgConnection) -> Result<Device> {
+        diesel::update(devices::table.find(self.id))
+            .set(devices::revoked_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)
+            .await
+            .map_err(|e| DeviceError::DatabaseError(e).into())
+    }
+}
@@ -0,0 +1,251 @@
+use diesel::prelude::*;
+use diesel::pg::Pg;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::AsyncPgConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use serde::{Serialize, Deserialize};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::error::{Error as AppError, Result};
+use thiserror::Error;
+
+use crate::schema::{employees, users};
+
+impl From<EmployeeError> for AppError {
+    fn from(error: EmployeeError) -> Self {
+        match error {
+            EmployeeError::DatabaseError(_) => AppError::DatabaseError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, AsChangeset)]
+#[diesel(table_name = employees, treat_none_as_null = true)]
+pub struct Employee {
+    pub id: i64,
+    pub user_id: i64,
+    pub employee_code: String,
+    pub job_title: String,
+    pub hourly_rate: Option<i64>, // in the smallest currency unit
+    pub hire_date: NaiveDate,
+    pub termination_date: Option<NaiveDate>, // last working day
+    pub stores: Vec<String>,
+    pub emergency_contact_name: Option<String>,
+    pub emergency_contact_phone: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Insertable)]
+#[diesel(table_name = employees)]
+pub struct NewEmployee {
+    pub user_id: i64,
+    pub employee_code: String,
+    pub job_title: String,
+    pub hourly_rate: Option<i64>,
+    pub hire_date: NaiveDate,
+    pub termination_date: Option<NaiveDate>,
+    pub stores: Vec<String>,
+    pub emergency_contact_name: Option<String>,
+    pub emergency_contact_phone: Option<String>,
+}
+
+/// Narrows an employee query down, every `None` field matches everything.
+#[derive(Debug, Default)]
+pub struct EmployeeFilter {
+    pub search: Option<String>, // part of the employee code, job title or the user's full name
+    pub store: Option<String>,
+    pub active: Option<bool>, // `false` for employees past their termination date
+}
+
+#[derive(Debug, Error)]
+pub enum EmployeeError {
+    #[error("Employee with ID '{0}' not found")]
+    EmployeeIDNotFound(i64),
+
+    #[error("No employee profile found for this account")]
+    ProfileNotFound,
+
+    #[error("User with ID '{0}' already has an employee profile")]
+    ProfileAlreadyExists(i64),
+
+    #[error("Employee code '{0}' is already in use")]
+    CodeAlreadyInUse(String),
+
+    #[error("This employee's employment has ended")]
+    Terminated,
+
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+impl EmployeeError {
+    // both unique columns fail the same way, the constraint tells them apart
+    fn from_write(e: DieselError, user_id: i64, employee_code: &str) -> Self {
+        match e {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                if info.constraint_name() == Some("employees_user_id_key") {
+                    EmployeeError::ProfileAlreadyExists(user_id)
+                } else {
+                    EmployeeError::CodeAlreadyInUse(employee_code.to_string())
+                }
+            },
+            e => EmployeeError::DatabaseError(e)
+        }
+    }
+}
+
+impl Employee {
+    /// Creates the profile, in the same transaction setting the employee's terminal PIN
+    /// when `pin_hash` is given.
+    pub async fn create(new_employee: NewEmployee, pin_hash: Option<&str>, conn: &mut AsyncPgConnection) -> Result<Employee> {
+        let user_id = new_employee.user_id;
+        let employee_code = new_employee.employee_code.clone();
+
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            let employee = diesel::insert_into(employees::table)
+                .values(&new_employee)
+                .get_result(conn)
+                .await?;
+
+            Self::set_pin(user_id, pin_hash, conn).await?;
+
+            Ok(employee)
+        }.scope_boxed())
+        .await
+        .map_err(|e| EmployeeError::from_write(e, user_id, &employee_code).into())
+    }
+
+    pub async fn find_by_id(id: i64, conn: &mut AsyncPgConnection) -> Result<Employee> {
+        employees::table
+            .find(id)
+            .first(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    EmployeeError::EmployeeIDNotFound(id).into()
+                } else {
+                    EmployeeError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    pub async fn find_by_user_id(user_id: i64, conn: &mut AsyncPgConnection) -> Result<Employee> {
+        employees::table
+            .filter(employees::user_id.eq(user_id))
+            .first(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    EmployeeError::ProfileNotFound.into()
+                } else {
+                    EmployeeError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    fn filtered(filter: &EmployeeFilter) -> employees::BoxedQuery<'_, Pg> {
+        let mut query = employees::table.into_boxed();
+
+        if let Some(search) = &filter.search {
+            // `\` escapes LIKE wildcards typed by the manager, they're matched literally
+            let pattern = format!("%{}%", search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+
+            query = query.filter(
+                employees::employee_code.ilike(pattern.clone())
+                    .or(employees::job_title.ilike(pattern.clone()))
+                    .or(employees::user_id.eq_any(users::table.select(users::id).filter(users::fullname.ilike(pattern))))
+            );
+        }
+
+        if let Some(store) = &filter.store {
+            query = query.filter(employees::stores.contains(vec![store.clone()]));
+        }
+
+        if let Some(active) = filter.active {
+            let today = Utc::now().date_naive();
+
+            query = if active {
+                query.filter(employees::termination_date.is_null().or(employees::termination_date.ge(today)))
+            } else {
+                query.filter(employees::termination_date.lt(today))
+            };
+        }
+
+        query
+    }
+
+    /// Returns one page of the employees matching the filter, ordered by employee
+    /// code, together with the total number of matching employees.
+    pub async fn search(filter: &EmployeeFilter, limit: i64, offset: i64, conn: &mut AsyncPgConnection) -> Result<(Vec<Employee>, i64)> {
+        let total = Self::filtered(filter)
+            .count()
+            .get_result::<i64>(conn)
+            .await
+            .map_err(EmployeeError::DatabaseError)?;
+
+        let employees = Self::filtered(filter)
+            .order(employees::employee_code.asc())
+            .limit(limit)
+            .offset(offset)
+            .load::<Employee>(conn)
+            .await
+            .map_err(EmployeeError::DatabaseError)?;
+
+        Ok((employees, total))
+    }
+
+    /// Saves the profile, in the same transaction setting the employee's terminal PIN
+    /// when `pin_hash` is given.
+    pub async fn update(&self, pin_hash: Option<&str>, conn: &mut AsyncPgConnection) -> Result<Employee> {
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            let employee = diesel::update(employees::table.find(self.id))
+                .set(self)
+                .get_result(conn)
+                .await?;
+
+            Self::set_pin(self.user_id, pin_hash, conn).await?;
+
+            Ok(employee)
+        }.scope_boxed())
+        .await
+        .map_err(|e| EmployeeError::from_write(e, self.user_id, &self.employee_code).into())
+    }
+
+    async fn set_pin(user_id: i64, pin_hash: Option<&str>, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        if let Some(pin_hash) = pin_hash {
+            diesel::update(users::table.find(user_id))
+                .set(users::pin.eq(pin_hash))
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, conn: &mut AsyncPgConnection) -> Result<usize> {
+        diesel::delete(employees::table.find(self.id))
+            .execute(conn)
+            .await
+            .map_err(|e| EmployeeError::DatabaseError(e).into())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.termination_date.is_none_or(|date| date >= Utc::now().date_naive())
+    }
+
+    /// Whether the account belongs to an employee past their termination date, who
+    /// can't sign in anymore. Accounts without a profile (admins, customers) never are.
+    pub async fn is_terminated(user_id: i64, conn: &mut AsyncPgConnection) -> Result<bool> {
+        let employee: Option<Employee> = employees::table
+            .filter(employees::user_id.eq(user_id))
+            .first(conn)
+            .await
+            .optional()
+            .map_err(EmployeeError::DatabaseError)?;
+
+        Ok(employee.is_some_and(|employee| !employee.is_active()))
+    }
+}
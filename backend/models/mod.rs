@@ -1 +1,10 @@
-pub mod user;
\ No newline at end of file
+pub mod user;
+pub mod role;
+pub mod permission;
+pub mod device;
+pub mod recovery_code;
+pub mod audit_event;
+pub mod api_key;
+pub mod user_identity;
+pub mod staff_invite;
+pub mod employee;
\ No newline at end of file
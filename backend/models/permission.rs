@@ -0,0 +1,56 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use diesel_async::AsyncPgConnection;
+use serde::{Serialize, Deserialize};
+
+use crate::error::Result;
+use crate::models::role::RoleError;
+use crate::schema::permissions;
+
+pub const ORDER_CREATE: &str = "order.create";
+pub const ORDER_VOID: &str = "order.void";
+pub const ORDER_DISCOUNT: &str = "order.discount";
+pub const ORDER_REFUND: &str = "order.refund";
+pub const DRAWER_OPEN: &str = "drawer.open";
+pub const KITCHEN_VIEW: &str = "kitchen.view";
+pub const REPORT_VIEW: &str = "report.view";
+pub const MENU_EDIT: &str = "menu.edit";
+pub const USER_MANAGE: &str = "user.manage";
+pub const ROLE_MANAGE: &str = "role.manage";
+pub const AUDIT_VIEW: &str = "audit.view";
+pub const EMPLOYEE_MANAGE: &str = "employee.manage";
+
+/// Actions a manager can approve for a cashier with an override (see `require_override`).
+pub const OVERRIDABLE: [&str; 4] = [ORDER_VOID, ORDER_DISCOUNT, ORDER_REFUND, DRAWER_OPEN];
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = permissions)]
+pub struct Permission {
+    pub id: i64,
+    pub code: String,
+    pub description: String,
+}
+
+impl Permission {
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<Permission>> {
+        permissions::table
+            .order(permissions::code.asc())
+            .load::<Permission>(conn)
+            .await
+            .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    pub async fn find_by_codes(codes: &[String], conn: &mut AsyncPgConnection) -> Result<Vec<Permission>> {
+        let found = permissions::table
+            .filter(permissions::code.eq_any(codes))
+            .load::<Permission>(conn)
+            .await
+            .map_err(RoleError::DatabaseError)?;
+
+        if let Some(unknown) = codes.iter().find(|code| !found.iter().any(|p| &p.code == *code)) {
+            return Err(RoleError::UnknownPermission(unknown.clone()).into());
+        }
+
+        Ok(found)
+    }
+}
@@ -0,0 +1,110 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::AsyncPgConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use chrono::{NaiveDateTime, Utc};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use diesel::result::Error as DieselError;
+
+use crate::error::{Error as AppError, Result};
+use crate::utils::crypto;
+use thiserror::Error;
+
+use crate::schema::mfa_recovery_codes;
+
+const CODE_COUNT: usize = 10;
+const CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789"; // no look-alikes
+
+impl From<RecoveryCodeError> for AppError {
+    fn from(error: RecoveryCodeError) -> Self {
+        AppError::DatabaseError(error.into())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = mfa_recovery_codes)]
+pub struct RecoveryCode {
+    pub id: i64,
+    pub user_id: i64,
+    pub code_hash: String,
+    pub used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Error)]
+pub enum RecoveryCodeError {
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+impl RecoveryCode {
+    // users type them by hand, so dashes, spaces and casing don't matter
+    fn normalize(code: &str) -> String {
+        code.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+
+    fn generate_code() -> String {
+        let mut rng = rand::rng();
+        let chars: String = (0..10)
+            .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+            .collect();
+
+        format!("{}-{}", &chars[..5], &chars[5..])
+    }
+
+    /// Replaces every recovery code of the user, returning the new codes in plain
+    /// text. Only their hashes are stored, so this is the only time they're visible.
+    pub async fn regenerate(user_id: i64, conn: &mut AsyncPgConnection) -> Result<Vec<String>> {
+        let codes: Vec<String> = (0..CODE_COUNT).map(|_| Self::generate_code()).collect();
+
+        let rows: Vec<_> = codes.iter()
+            .map(|code| (mfa_recovery_codes::user_id.eq(user_id), mfa_recovery_codes::code_hash.eq(crypto::sha256_hex(&Self::normalize(code)))))
+            .collect();
+
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+                .execute(conn)
+                .await?;
+
+            diesel::insert_into(mfa_recovery_codes::table)
+                .values(&rows)
+                .execute(conn)
+                .await?;
+
+            Ok(())
+        }.scope_boxed())
+        .await
+        .map_err(RecoveryCodeError::DatabaseError)?;
+
+        Ok(codes)
+    }
+
+    /// Marks the code as used, returns `false` when it doesn't exist or was already used.
+    pub async fn consume(user_id: i64, code: &str, conn: &mut AsyncPgConnection) -> Result<bool> {
+        let code_hash = crypto::sha256_hex(&Self::normalize(code));
+
+        let updated = diesel::update(
+                mfa_recovery_codes::table
+                    .filter(mfa_recovery_codes::user_id.eq(user_id))
+                    .filter(mfa_recovery_codes::code_hash.eq(code_hash))
+                    .filter(mfa_recovery_codes::used_at.is_null())
+            )
+            .set(mfa_recovery_codes::used_at.eq(Utc::now().naive_utc()))
+            .execute(conn)
+            .await
+            .map_err(RecoveryCodeError::DatabaseError)?;
+
+        Ok(updated > 0)
+    }
+
+    pub async fn delete_for_user(user_id: i64, conn: &mut AsyncPgConnection) -> Result<usize> {
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+            .execute(conn)
+            .await
+            .map_err(|e| RecoveryCodeError::DatabaseError(e).into())
+    }
+}
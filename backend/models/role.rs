@@ -0,0 +1,271 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::AsyncPgConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::error::{Error as AppError, Result};
+use thiserror::Error;
+
+use crate::models::permission::Permission;
+use crate::schema::{permissions, role_permissions, roles, user_role_assignments};
+
+impl From<RoleError> for AppError {
+    fn from(error: RoleError) -> Self {
+        match error {
+            RoleError::DatabaseError(_) => AppError::DatabaseError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, AsChangeset)]
+#[diesel(table_name = roles)]
+pub struct Role {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Insertable)]
+#[diesel(table_name = roles)]
+pub struct NewRole {
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Error)]
+pub enum RoleError {
+    #[error("Role with ID '{0}' not found")]
+    RoleIDNotFound(i64),
+
+    #[error("Role with name '{0}' already exists")]
+    RoleAlreadyExists(String),
+
+    #[error("The '{0}' role comes with TeaPOS and can't be renamed or deleted")]
+    BuiltInRole(String),
+
+    #[error("Unknown permission '{0}'")]
+    UnknownPermission(String),
+
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+/// Roles set up by the migrations, which existing accounts depend on.
+const BUILT_IN_ROLES: [&str; 2] = ["owner", "admin"];
+
+impl Role {
+    /// Creates the role together with its permissions, all or nothing.
+    pub async fn create(new_role: NewRole, codes: &[String], conn: &mut AsyncPgConnection) -> Result<Role> {
+        let existing_role = roles::table
+            .filter(roles::name.eq(&new_role.name))
+            .first::<Role>(conn)
+            .await;
+
+        if existing_role.is_ok() {
+            return Err(RoleError::RoleAlreadyExists(new_role.name).into());
+        }
+
+        let permission_ids = Self::permission_ids(codes, conn).await?;
+        let name = new_role.name.clone();
+
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            let role: Role = diesel::insert_into(roles::table)
+                .values(&new_role)
+                .get_result(conn)
+                .await?;
+
+            Self::replace_permissions(role.id, &permission_ids, conn).await?;
+
+            Ok(role)
+        }.scope_boxed())
+        .await
+        .map_err(|e| Self::write_error(e, name))
+    }
+
+    pub async fn find_by_id(id: i64, conn: &mut AsyncPgConnection) -> Result<Role> {
+        roles::table
+            .find(id)
+            .first(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    RoleError::RoleIDNotFound(id).into()
+                } else {
+                    RoleError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<Role>> {
+        roles::table
+            .order(roles::name.asc())
+            .load::<Role>(conn)
+            .await
+            .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    pub fn is_built_in(&self) -> bool {
+        BUILT_IN_ROLES.contains(&self.name.as_str())
+    }
+
+    /// Saves the name and description together with the new permission set.
+    pub async fn update(&self, codes: &[String], conn: &mut AsyncPgConnection) -> Result<Role> {
+        let permission_ids = Self::permission_ids(codes, conn).await?;
+
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            let role: Role = diesel::update(roles::table.find(self.id))
+                .set(self)
+                .get_result(conn)
+                .await?;
+
+            Self::replace_permissions(role.id, &permission_ids, conn).await?;
+
+            Ok(role)
+        }.scope_boxed())
+        .await
+        .map_err(|e| Self::write_error(e, self.name.clone()))
+    }
+
+    pub async fn delete(&self, conn: &mut AsyncPgConnection) -> Result<usize> {
+        if self.is_built_in() {
+            return Err(RoleError::BuiltInRole(self.name.clone()).into());
+        }
+
+        diesel::delete(roles::table.find(self.id))
+            .execute(conn)
+            .await
+            .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    pub async fn permissions(&self, conn: &mut AsyncPgConnection) -> Result<Vec<String>> {
+        role_permissions::table
+            .inner_join(permissions::table)
+            .filter(role_permissions::role_id.eq(self.id))
+            .select(permissions::code)
+            .order(permissions::code.asc())
+            .load::<String>(conn)
+            .await
+            .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    /// Replaces the whole permission set of the role.
+    pub async fn set_permissions(&self, codes: &[String], conn: &mut AsyncPgConnection) -> Result<()> {
+        let permission_ids = Self::permission_ids(codes, conn).await?;
+        let role_id = self.id;
+
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            Self::replace_permissions(role_id, &permission_ids, conn).await
+        }.scope_boxed())
+        .await
+        .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    async fn permission_ids(codes: &[String], conn: &mut AsyncPgConnection) -> Result<Vec<i64>> {
+        Ok(Permission::find_by_codes(codes, conn).await?
+            .into_iter()
+            .map(|p| p.id)
+            .collect())
+    }
+
+    // meant to run inside a transaction
+    async fn replace_permissions(role_id: i64, permission_ids: &[i64], conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::delete(role_permissions::table.filter(role_permissions::role_id.eq(role_id)))
+            .execute(conn)
+            .await?;
+
+        let rows: Vec<_> = permission_ids.iter()
+            .map(|permission_id| (role_permissions::role_id.eq(role_id), role_permissions::permission_id.eq(*permission_id)))
+            .collect();
+
+        if !rows.is_empty() {
+            diesel::insert_into(role_permissions::table)
+                .values(&rows)
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // another role may have taken the name in the meantime
+    fn write_error(error: DieselError, name: String) -> AppError {
+        match error {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => RoleError::RoleAlreadyExists(name).into(),
+            e => RoleError::DatabaseError(e).into(),
+        }
+    }
+
+    pub async fn user_ids(&self, conn: &mut AsyncPgConnection) -> Result<Vec<i64>> {
+        user_role_assignments::table
+            .filter(user_role_assignments::role_id.eq(self.id))
+            .select(user_role_assignments::user_id)
+            .load::<i64>(conn)
+            .await
+            .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    pub async fn find_by_user(user_id: i64, conn: &mut AsyncPgConnection) -> Result<Vec<Role>> {
+        user_role_assignments::table
+            .inner_join(roles::table)
+            .filter(user_role_assignments::user_id.eq(user_id))
+            .select(roles::all_columns)
+            .order(roles::name.asc())
+            .load::<Role>(conn)
+            .await
+            .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    /// Replaces every role assigned to the user.
+    pub async fn assign_to_user(user_id: i64, role_ids: &[i64], conn: &mut AsyncPgConnection) -> Result<()> {
+        let found: Vec<i64> = roles::table
+            .filter(roles::id.eq_any(role_ids))
+            .select(roles::id)
+            .load::<i64>(conn)
+            .await
+            .map_err(RoleError::DatabaseError)?;
+
+        if let Some(missing) = role_ids.iter().find(|id| !found.contains(*id)) {
+            return Err(RoleError::RoleIDNotFound(*missing).into());
+        }
+
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            diesel::delete(user_role_assignments::table.filter(user_role_assignments::user_id.eq(user_id)))
+                .execute(conn)
+                .await?;
+
+            let rows: Vec<_> = found.iter()
+                .map(|role_id| (user_role_assignments::user_id.eq(user_id), user_role_assignments::role_id.eq(*role_id)))
+                .collect();
+
+            if !rows.is_empty() {
+                diesel::insert_into(user_role_assignments::table)
+                    .values(&rows)
+                    .execute(conn)
+                    .await?;
+            }
+
+            Ok(())
+        }.scope_boxed())
+        .await
+        .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+
+    /// Every permission the user gets through their assigned roles.
+    pub async fn permissions_for_user(user_id: i64, conn: &mut AsyncPgConnection) -> Result<Vec<String>> {
+        user_role_assignments::table
+            .inner_join(role_permissions::table.on(role_permissions::role_id.eq(user_role_assignments::role_id)))
+            .inner_join(permissions::table.on(permissions::id.eq(role_permissions::permission_id)))
+            .filter(user_role_assignments::user_id.eq(user_id))
+            .select(permissions::code)
+            .distinct()
+            .load::<String>(conn)
+            .await
+            .map_err(|e| RoleError::DatabaseError(e).into())
+    }
+}
@@ -0,0 +1,270 @@
+use diesel::prelude::*;
+use diesel::pg::Pg;
+use diesel_async::RunQueryDsl;
+use diesel_async::AsyncPgConnection;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
+use diesel::result::Error as DieselError;
+
+use crate::error::{Error as AppError, Result};
+use crate::models::user::UserRole;
+use crate::utils::crypto;
+use thiserror::Error;
+
+use crate::schema::staff_invites;
+
+impl From<StaffInviteError> for AppError {
+    fn from(error: StaffInviteError) -> Self {
+        match error {
+            StaffInviteError::DatabaseError(_) => AppError::DatabaseError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = staff_invites)]
+pub struct StaffInvite {
+    pub id: i64,
+    pub whatsapp: String,
+    pub fullname: String,
+    pub role: UserRole,
+    pub store: Option<String>,
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+    pub invited_by: Option<i64>,
+    pub user_id: Option<i64>, // the account created when the invite was accepted
+    pub expires_at: NaiveDateTime,
+    pub accepted_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Insertable)]
+#[diesel(table_name = staff_invites)]
+pub struct NewStaffInvite {
+    pub whatsapp: String,
+    pub fullname: String,
+    pub role: UserRole,
+    pub store: Option<String>,
+    pub code_hash: String,
+    pub invited_by: Option<i64>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InviteStatus {
+    Pending,
+    Accepted,
+    Revoked,
+    Expired,
+}
+
+impl fmt::Display for InviteStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InviteStatus::Pending => write!(f, "pending"),
+            InviteStatus::Accepted => write!(f, "accepted"),
+            InviteStatus::Revoked => write!(f, "revoked"),
+            InviteStatus::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+impl FromStr for InviteStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pending" => Ok(InviteStatus::Pending),
+            "accepted" => Ok(InviteStatus::Accepted),
+            "revoked" => Ok(InviteStatus::Revoked),
+            "expired" => Ok(InviteStatus::Expired),
+            _ => Err(format!("Unknown invite status: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StaffInviteError {
+    #[error("Invalid or expired invite code")]
+    InvalidCode,
+
+    #[error("Invite with ID '{0}' not found")]
+    InviteIDNotFound(i64),
+
+    #[error("Invite is already {0}")]
+    NotPending(InviteStatus),
+
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+impl StaffInvite {
+    /// Creates an invite, returning it together with its code. The code is only
+    /// ever sent to the invitee, just its hash is stored.
+    pub async fn create(
+        whatsapp: String,
+        fullname: String,
+        role: UserRole,
+        store: Option<String>,
+        expiry: i64,
+        invited_by: Option<i64>, // `None` for invites made from the command line
+        conn: &mut AsyncPgConnection
+    ) -> Result<(StaffInvite, String)> {
+        let code = crypto::random_token(32);
+
+        let new_invite = NewStaffInvite {
+            whatsapp,
+            fullname,
+            role,
+            store,
+            code_hash: crypto::sha256_hex(&code),
+            invited_by,
+            expires_at: Utc::now().naive_utc() + Duration::seconds(expiry),
+        };
+
+        let invite = diesel::insert_into(staff_invites::table)
+            .values(&new_invite)
+            .get_result(conn)
+            .await
+            .map_err(StaffInviteError::DatabaseError)?;
+
+        Ok((invite, code))
+    }
+
+    pub async fn find_by_id(id: i64, conn: &mut AsyncPgConnection) -> Result<StaffInvite> {
+        staff_invites::table
+            .find(id)
+            .first(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    StaffInviteError::InviteIDNotFound(id).into()
+                } else {
+                    StaffInviteError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    /// Looks up the pending invite a code belongs to, without using it up.
+    pub async fn find_pending_by_code(code: &str, conn: &mut AsyncPgConnection) -> Result<StaffInvite> {
+        Self::pending(Utc::now().naive_utc())
+            .filter(staff_invites::code_hash.eq(crypto::sha256_hex(code)))
+            .first(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    StaffInviteError::InvalidCode.into()
+                } else {
+                    StaffInviteError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    fn pending(now: NaiveDateTime) -> staff_invites::BoxedQuery<'static, Pg> {
+        staff_invites::table
+            .filter(staff_invites::accepted_at.is_null())
+            .filter(staff_invites::revoked_at.is_null())
+            .filter(staff_invites::expires_at.gt(now))
+            .into_boxed()
+    }
+
+    /// Lists invites newest first, optionally only the ones in the given status.
+    pub async fn list(status: Option<InviteStatus>, conn: &mut AsyncPgConnection) -> Result<Vec<StaffInvite>> {
+        let now = Utc::now().naive_utc();
+
+        let query = match status {
+            None => staff_invites::table.into_boxed(),
+            Some(InviteStatus::Pending) => Self::pending(now),
+            Some(InviteStatus::Accepted) => staff_invites::table
+                .filter(staff_invites::accepted_at.is_not_null())
+                .into_boxed(),
+            Some(InviteStatus::Revoked) => staff_invites::table
+                .filter(staff_invites::revoked_at.is_not_null())
+                .into_boxed(),
+            Some(InviteStatus::Expired) => staff_invites::table
+                .filter(staff_invites::accepted_at.is_null())
+                .filter(staff_invites::revoked_at.is_null())
+                .filter(staff_invites::expires_at.le(now))
+                .into_boxed(),
+        };
+
+        query
+            .order((staff_invites::created_at.desc(), staff_invites::id.desc()))
+            .load::<StaffInvite>(conn)
+            .await
+            .map_err(|e| StaffInviteError::DatabaseError(e).into())
+    }
+
+    pub fn status(&self) -> InviteStatus {
+        if self.accepted_at.is_some() {
+            InviteStatus::Accepted
+        } else if self.revoked_at.is_some() {
+            InviteStatus::Revoked
+        } else if self.expires_at <= Utc::now().naive_utc() {
+            InviteStatus::Expired
+        } else {
+            InviteStatus::Pending
+        }
+    }
+
+    pub async fn revoke(&self, conn: &mut AsyncPgConnection) -> Result<StaffInvite> {
+        let status = self.status();
+
+        if status != InviteStatus::Pending {
+            return Err(StaffInviteError::NotPending(status).into());
+        }
+
+        let now = Utc::now().naive_utc();
+
+        diesel::update(staff_invites::table.find(self.id))
+            .filter(staff_invites::accepted_at.is_null())
+            .filter(staff_invites::revoked_at.is_null())
+            .filter(staff_invites::expires_at.gt(now))
+            .set(staff_invites::revoked_at.eq(now))
+            .get_result(conn)
+            .await
+            .map_err(|e| {
+                // accepted in the meantime
+                if let DieselError::NotFound = e {
+                    StaffInviteError::NotPending(InviteStatus::Accepted).into()
+                } else {
+                    StaffInviteError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    /// Marks the invite as accepted, but only if it is still pending, so a code
+    /// can't be used twice even by concurrent requests. Run it in the transaction
+    /// that creates the account, so a failed signup leaves the invite pending.
+    pub async fn claim(&self, conn: &mut AsyncPgConnection) -> Result<StaffInvite> {
+        let now = Utc::now().naive_utc();
+
+        diesel::update(staff_invites::table.find(self.id))
+            .filter(staff_invites::accepted_at.is_null())
+            .filter(staff_invites::revoked_at.is_null())
+            .filter(staff_invites::expires_at.gt(now))
+            .set(staff_invites::accepted_at.eq(now))
+            .get_result(conn)
+            .await
+            .map_err(|e| {
+                if let DieselError::NotFound = e {
+                    StaffInviteError::InvalidCode.into()
+                } else {
+                    StaffInviteError::DatabaseError(e).into()
+                }
+            })
+    }
+
+    pub async fn set_user(&self, user_id: i64, conn: &mut AsyncPgConnection) -> Result<StaffInvite> {
+        diesel::update(staff_invites::table.find(self.id))
+            .set(staff_invites::user_id.eq(user_id))
+            .get_result(conn)
+            .await
+            .map_err(|e| StaffInviteError::DatabaseError(e).into())
+    }
+}
@@ -1,7 +1,8 @@
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, RunQueryDsl};
 use diesel_async::AsyncPgConnection;
-use chrono::NaiveDateTime;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use chrono::{NaiveDateTime, Utc};
 use serde::{Serialize, Deserialize};
 use std::fmt;
 use std::str::FromStr;
@@ -10,14 +11,20 @@ use diesel::pg::{Pg, PgValue};
 use diesel::serialize::{self, Output, ToSql};
 use diesel::deserialize::{self, FromSql};
 use diesel::sql_types::Text;
-use diesel::result::Error as DieselError;
-use argon2::{password_hash::{SaltString, rand_core::OsRng}, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use argon2::{password_hash::{SaltString, rand_core::OsRng}, Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use std::sync::OnceLock;
+use anyhow::anyhow;
 
+use crate::config::config::Config;
 use crate::error::{Error as AppError, Result};
 use thiserror::Error;
 
 use crate::schema::users;
 use crate::schema::sql_types::UserRole as UserRoleSqlType;
+use crate::schema::sql_types::UserStatus as UserStatusSqlType;
+
+static ARGON2_PARAMS: OnceLock<Params> = OnceLock::new();
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
 #[diesel(sql_type = UserRoleSqlType)]
@@ -28,6 +35,22 @@ pub enum UserRole {
     Employee,
 }
 
+impl UserRole {
+    // privilege order: user < employee < admin < superadmin
+    fn rank(&self) -> u8 {
+        match self {
+            UserRole::User => 0,
+            UserRole::Employee => 1,
+            UserRole::Admin => 2,
+            UserRole::SuperAdmin => 3,
+        }
+    }
+
+    pub fn has_at_least(&self, role: UserRole) -> bool {
+        self.rank() >= role.rank()
+    }
+}
+
 impl fmt::Display for UserRole {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -78,23 +101,150 @@ impl FromSql<UserRoleSqlType, Pg> for UserRole {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = UserStatusSqlType)]
+pub enum UserStatus {
+    Active,
+    Suspended, // temporarily blocked, e.g. while something is looked into
+    Deactivated, // left the business
+}
+
+impl fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserStatus::Active => write!(f, "active"),
+            UserStatus::Suspended => write!(f, "suspended"),
+            UserStatus::Deactivated => write!(f, "deactivated"),
+        }
+    }
+}
+
+impl FromStr for UserStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "active" => Ok(UserStatus::Active),
+            "suspended" => Ok(UserStatus::Suspended),
+            "deactivated" => Ok(UserStatus::Deactivated),
+            _ => Err(format!("Unknown user status: {}", s)),
+        }
+    }
+}
+
+impl ToSql<UserStatusSqlType, Pg> for UserStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        match *self {
+            UserStatus::Active => <&str as ToSql<Text, Pg>>::to_sql(&"active", out),
+            UserStatus::Suspended => <&str as ToSql<Text, Pg>>::to_sql(&"suspended", out),
+            UserStatus::Deactivated => <&str as ToSql<Text, Pg>>::to_sql(&"deactivated", out)
+        }
+    }
+}
+
+impl FromSql<UserStatusSqlType, Pg> for UserStatus {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        let s = std::str::from_utf8(bytes.as_bytes())?;
+        match s {
+            "active" => Ok(UserStatus::Active),
+            "suspended" => Ok(UserStatus::Suspended),
+            "deactivated" => Ok(UserStatus::Deactivated),
+            s => Err(format!("Unrecognized enum variant: {}", s).into()),
+        }
+    }
+}
+
+/// Narrows the admin user list down, every `None` field matches everything.
+#[derive(Debug, Default)]
+pub struct UserFilter {
+    pub search: Option<String>, // part of the username, full name or WhatsApp number
+    pub role: Option<UserRole>,
+    pub status: Option<UserStatus>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum UserSort {
+    #[default]
+    CreatedAt,
+    Username,
+    Fullname,
+}
+
+impl FromStr for UserSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "created_at" => Ok(UserSort::CreatedAt),
+            "username" => Ok(UserSort::Username),
+            "fullname" => Ok(UserSort::Fullname),
+            _ => Err(format!("Unknown sort field: {}", s)),
+        }
+    }
+}
+
 impl From<UserError> for AppError {
     fn from(error: UserError) -> Self {
-        AppError::DatabaseError(error.into())
+        match error {
+            UserError::DatabaseError(_) => AppError::DatabaseError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, AsChangeset)]
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
 #[diesel(table_name = users)]
 pub struct User {
     pub id: i64,
     pub username: String,
     pub fullname: String,
+    #[serde(skip_serializing)]
     pub password: String,
     pub whatsapp: String,
     pub role: UserRole,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    pub pin: Option<String>,
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<NaiveDateTime>,
+    pub whatsapp_verified_at: Option<NaiveDateTime>,
+    pub status: UserStatus,
+    #[serde(skip_serializing)]
+    pub token_version: i32, // part of every token, see `revoke_tokens`
+}
+
+// what `update` writes back, `status` and `token_version` are left out so a stale
+// copy of the user can't undo a `set_status` or `revoke_tokens` made meanwhile
+#[derive(AsChangeset)]
+#[diesel(table_name = users)]
+struct UserChangeset<'a> {
+    username: &'a str,
+    fullname: &'a str,
+    password: &'a str,
+    whatsapp: &'a str,
+    role: UserRole,
+    pin: Option<&'a str>,
+    totp_secret: Option<&'a str>,
+    totp_enabled_at: Option<NaiveDateTime>,
+    whatsapp_verified_at: Option<NaiveDateTime>,
+}
+
+impl<'a> From<&'a User> for UserChangeset<'a> {
+    fn from(user: &'a User) -> Self {
+        Self {
+            username: &user.username,
+            fullname: &user.fullname,
+            password: &user.password,
+            whatsapp: &user.whatsapp,
+            role: user.role,
+            pin: user.pin.as_deref(),
+            totp_secret: user.totp_secret.as_deref(),
+            totp_enabled_at: user.totp_enabled_at,
+            whatsapp_verified_at: user.whatsapp_verified_at,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Insertable)]
@@ -105,6 +255,7 @@ pub struct NewUser {
     pub password: String,
     pub whatsapp: String,
     pub role: UserRole,
+    pub whatsapp_verified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Error)]
@@ -121,16 +272,39 @@ pub enum UserError {
     #[error("User with username '{0}' not found")]
     UsernameNotFound(String),
 
+    #[error("WhatsApp number '{0}' is already in use")]
+    WhatsappAlreadyInUse(String),
+
+    #[error("This account is suspended")]
+    AccountSuspended,
+
+    #[error("This account has been deactivated")]
+    AccountDeactivated,
+
     #[error("Unexpected database error: {0}")]
     DatabaseError(#[from] DieselError),
 }
 
 impl User {
+    /// Sets up the Argon2 cost parameters new hashes are made with. Hashing before
+    /// this uses the argon2 crate defaults.
+    pub fn init_password_hashing(config: &Config) -> Result<()> {
+        let params = Params::new(config.argon2_memory_cost, config.argon2_time_cost, config.argon2_parallelism, None)
+            .map_err(|e| AppError::ConfigError(anyhow!("Invalid Argon2 parameters: {}", e)))?;
+
+        let _ = ARGON2_PARAMS.set(params);
+        Ok(())
+    }
+
+    fn argon2() -> Argon2<'static> {
+        let params = ARGON2_PARAMS.get().cloned().unwrap_or_default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    }
+
     pub fn hash_password(password: &str) -> Result<String> {
         let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
         
-        argon2.hash_password(password.as_bytes(), &salt)
+        Self::argon2().hash_password(password.as_bytes(), &salt)
             .map(|hash| hash.to_string())
             .map_err(|_| UserError::InvalidCredentials.into())
     }
@@ -139,7 +313,29 @@ impl User {
         let parsed_hash = PasswordHash::new(hash)
             .map_err(|_| UserError::InvalidCredentials)?;
             
-        Ok(Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok())
+        // the parameters stored in the hash are used here, not the configured ones
+        Ok(Self::argon2().verify_password(password.as_bytes(), &parsed_hash).is_ok())
+    }
+
+    /// Whether the hash was made with another algorithm or other cost parameters
+    /// than the configured ones, and should be replaced once the plain text is known.
+    pub fn needs_rehash(hash: &str) -> bool {
+        let parsed_hash = match PasswordHash::new(hash) {
+            Ok(parsed_hash) => parsed_hash,
+            Err(_) => return false
+        };
+
+        if parsed_hash.algorithm != Algorithm::Argon2id.ident() || parsed_hash.version != Some(Version::V0x13.into()) {
+            return true;
+        }
+
+        let argon2 = Self::argon2();
+        let current = argon2.params();
+
+        match Params::try_from(&parsed_hash) {
+            Ok(params) => params.m_cost() != current.m_cost() || params.t_cost() != current.t_cost() || params.p_cost() != current.p_cost(),
+            Err(_) => true
+        }
     }
     
     pub async fn create(mut new_user: NewUser, conn: &mut AsyncPgConnection) -> Result<User> {
@@ -148,7 +344,7 @@ impl User {
             .first::<User>(conn)
             .await;
             
-        if let Ok(_) = existing_user {
+        if existing_user.is_ok() {
             return Err(UserError::UserAlreadyExists(new_user.username).into());
         }
         
@@ -169,7 +365,7 @@ impl User {
             .first::<User>(conn)
             .await;
 
-        if let Ok(_) = existing_user {
+        if existing_user.is_ok() {
             return Err(UserError::UserAlreadyExists(new_user.username).into());
         }
         
@@ -182,7 +378,7 @@ impl User {
             .values(&new_user)
             .get_result(conn)
             .await
-            .map_err(|e| UserError::DatabaseError(e))?;
+            .map_err(UserError::DatabaseError)?;
 
         Ok(created_user)
     }
@@ -201,6 +397,14 @@ impl User {
             })
     }
 
+    pub async fn find_by_ids(ids: &[i64], conn: &mut AsyncPgConnection) -> Result<Vec<User>> {
+        users::table
+            .filter(users::id.eq_any(ids))
+            .load::<User>(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
     pub async fn find_by_username(username: &str, conn: &mut AsyncPgConnection) -> Result<User> {
         users::table
             .filter(users::username.eq(username))
@@ -215,14 +419,47 @@ impl User {
             })
     }
 
+    /// Saves the user's fields, except for the status and token version, see `set_status`
+    /// and `revoke_tokens` for those.
     pub async fn update(&self, conn: &mut AsyncPgConnection) -> Result<User> {
         diesel::update(users::table.find(self.id))
-            .set(self)
+            .set(UserChangeset::from(self))
             .get_result(conn)
             .await
             .map_err(|e| UserError::DatabaseError(e).into())
     }
 
+    /// Saves the user's fields like `update` and, in the same transaction, moves them to a
+    /// new WhatsApp number (which has to be verified again, `update` can't clear the
+    /// verification since the changeset skips `None` fields) and revokes their tokens when
+    /// asked to. A role change is saved this way, so it can't land without the token bump.
+    pub async fn update_profile(&self, whatsapp: Option<&str>, revoke_tokens: bool, conn: &mut AsyncPgConnection) -> Result<User> {
+        conn.transaction::<_, DieselError, _>(|conn| async move {
+            let mut user: User = diesel::update(users::table.find(self.id))
+                .set(UserChangeset::from(self))
+                .get_result(conn)
+                .await?;
+
+            if let Some(whatsapp) = whatsapp {
+                user = diesel::update(users::table.find(self.id))
+                    .set((users::whatsapp.eq(whatsapp), users::whatsapp_verified_at.eq(None::<NaiveDateTime>)))
+                    .get_result(conn)
+                    .await?;
+            }
+
+            if revoke_tokens {
+                user = diesel::update(users::table.find(self.id))
+                    .set(users::token_version.eq(users::token_version + 1))
+                    .get_result(conn)
+                    .await?;
+            }
+
+            Ok(user)
+        }.scope_boxed())
+        .await
+        .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
     pub async fn delete(&self, conn: &mut AsyncPgConnection) -> Result<usize> {
         diesel::delete(users::table.find(self.id))
             .execute(conn)
@@ -230,6 +467,71 @@ impl User {
             .map_err(|e| UserError::DatabaseError(e).into())
     }
 
+    fn filtered(filter: &UserFilter) -> users::BoxedQuery<'_, Pg> {
+        let mut query = users::table.into_boxed();
+
+        if let Some(search) = &filter.search {
+            // `\` escapes LIKE wildcards typed by the admin, they're matched literally
+            let pattern = format!("%{}%", search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+
+            query = query.filter(
+                users::username.ilike(pattern.clone())
+                    .or(users::fullname.ilike(pattern.clone()))
+                    .or(users::whatsapp.ilike(pattern))
+            );
+        }
+
+        if let Some(role) = filter.role {
+            query = query.filter(users::role.eq(role));
+        }
+
+        if let Some(status) = filter.status {
+            query = query.filter(users::status.eq(status));
+        }
+
+        query
+    }
+
+    /// Returns one page of the users matching the filter together with the total
+    /// number of matching users.
+    pub async fn search(filter: &UserFilter, sort: UserSort, descending: bool, limit: i64, offset: i64, conn: &mut AsyncPgConnection) -> Result<(Vec<User>, i64)> {
+        let total = Self::filtered(filter)
+            .count()
+            .get_result::<i64>(conn)
+            .await
+            .map_err(UserError::DatabaseError)?;
+
+        let query = Self::filtered(filter);
+
+        // the id breaks ties, so pages don't overlap
+        let query = match (sort, descending) {
+            (UserSort::CreatedAt, false) => query.order((users::created_at.asc(), users::id.asc())),
+            (UserSort::CreatedAt, true) => query.order((users::created_at.desc(), users::id.desc())),
+            (UserSort::Username, false) => query.order(users::username.asc()),
+            (UserSort::Username, true) => query.order(users::username.desc()),
+            (UserSort::Fullname, false) => query.order((users::fullname.asc(), users::id.asc())),
+            (UserSort::Fullname, true) => query.order((users::fullname.desc(), users::id.desc())),
+        };
+
+        let users = query
+            .limit(limit)
+            .offset(offset)
+            .load::<User>(conn)
+            .await
+            .map_err(UserError::DatabaseError)?;
+
+        Ok((users, total))
+    }
+
+    /// Every user matching the filter, by username, for exports.
+    pub async fn find_matching(filter: &UserFilter, conn: &mut AsyncPgConnection) -> Result<Vec<User>> {
+        Self::filtered(filter)
+            .order(users::username.asc())
+            .load::<User>(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
     pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<User>> {
         users::table
             .load::<User>(conn)
@@ -237,6 +539,112 @@ impl User {
             .map_err(|e| UserError::DatabaseError(e).into())
     }
     
+    pub fn is_whatsapp_verified(&self) -> bool {
+        self.whatsapp_verified_at.is_some()
+    }
+
+    /// Whether another account already verified this number.
+    pub async fn is_whatsapp_taken(whatsapp: &str, conn: &mut AsyncPgConnection) -> Result<bool> {
+        diesel::select(diesel::dsl::exists(
+                users::table
+                    .filter(users::whatsapp.eq(whatsapp))
+                    .filter(users::whatsapp_verified_at.is_not_null())
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
+    /// Which of the given numbers another account already verified, for checking many at once.
+    pub async fn taken_whatsapps(whatsapps: &[String], conn: &mut AsyncPgConnection) -> Result<Vec<String>> {
+        users::table
+            .filter(users::whatsapp.eq_any(whatsapps))
+            .filter(users::whatsapp_verified_at.is_not_null())
+            .select(users::whatsapp)
+            .load(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
+    /// Which of the given usernames already belong to an account.
+    pub async fn existing_usernames(usernames: &[String], conn: &mut AsyncPgConnection) -> Result<Vec<String>> {
+        users::table
+            .filter(users::username.eq_any(usernames))
+            .select(users::username)
+            .load(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
+    pub async fn mark_whatsapp_verified(&self, conn: &mut AsyncPgConnection) -> Result<User> {
+        diesel::update(users::table.find(self.id))
+            .set(users::whatsapp_verified_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)
+            .await
+            .map_err(|e| match e {
+                // the partial unique index lost a race against another account verifying the number
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => UserError::WhatsappAlreadyInUse(self.whatsapp.clone()).into(),
+                e => UserError::DatabaseError(e).into()
+            })
+    }
+
+    pub fn has_mfa(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    /// Sets (or with `None`, clears) the TOTP secret. `update` can't do this since
+    /// the changeset skips `None` fields.
+    pub async fn set_totp(&self, secret: Option<&str>, enabled_at: Option<NaiveDateTime>, conn: &mut AsyncPgConnection) -> Result<User> {
+        diesel::update(users::table.find(self.id))
+            .set((users::totp_secret.eq(secret), users::totp_enabled_at.eq(enabled_at)))
+            .get_result(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
+    /// Fails for suspended and deactivated accounts, which can't sign in or refresh.
+    pub fn ensure_active(&self) -> Result<()> {
+        match self.status {
+            UserStatus::Active => Ok(()),
+            UserStatus::Suspended => Err(UserError::AccountSuspended.into()),
+            UserStatus::Deactivated => Err(UserError::AccountDeactivated.into()),
+        }
+    }
+
+    /// Just the status and token version, for checking tokens without loading the whole user.
+    pub async fn find_token_state(id: i64, conn: &mut AsyncPgConnection) -> Result<Option<(UserStatus, i32)>> {
+        users::table
+            .find(id)
+            .select((users::status, users::token_version))
+            .first(conn)
+            .await
+            .optional()
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
+    /// Invalidates every token issued to the user so far, by bumping the version
+    /// they have to carry. Done whenever the role or status changes.
+    pub async fn revoke_tokens(&self, conn: &mut AsyncPgConnection) -> Result<User> {
+        diesel::update(users::table.find(self.id))
+            .set(users::token_version.eq(users::token_version + 1))
+            .get_result(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
+    /// Changes the status, revoking the user's tokens at the same time.
+    pub async fn set_status(&self, status: UserStatus, conn: &mut AsyncPgConnection) -> Result<User> {
+        diesel::update(users::table.find(self.id))
+            .set((users::status.eq(status), users::token_version.eq(users::token_version + 1)))
+            .get_result(conn)
+            .await
+            .map_err(|e| UserError::DatabaseError(e).into())
+    }
+
+    pub fn is_valid_pin(pin: &str) -> bool {
+        (4..=6).contains(&pin.len()) && pin.chars().all(|c| c.is_ascii_digit())
+    }
+
     fn is_hashed_password(password: &str) -> bool {
         password.starts_with("$argon2")
     }
@@ -0,0 +1,89 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use diesel_async::AsyncPgConnection;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Serialize, Deserialize};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::error::{Error as AppError, Result};
+use thiserror::Error;
+
+use crate::schema::user_identities;
+
+impl From<UserIdentityError> for AppError {
+    fn from(error: UserIdentityError) -> Self {
+        match error {
+            UserIdentityError::DatabaseError(_) => AppError::DatabaseError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = user_identities)]
+pub struct UserIdentity {
+    pub id: i64,
+    pub user_id: i64,
+    pub provider: String,
+    pub subject: String, // the `sub` claim, stable for the account at the provider
+    pub last_login_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Insertable)]
+#[diesel(table_name = user_identities)]
+pub struct NewUserIdentity {
+    pub user_id: i64,
+    pub provider: String,
+    pub subject: String,
+    pub last_login_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Error)]
+pub enum UserIdentityError {
+    #[error("This account is already linked to a {0} account")]
+    AlreadyLinked(String),
+
+    #[error("Unexpected database error: {0}")]
+    DatabaseError(#[from] DieselError),
+}
+
+impl UserIdentity {
+    pub async fn find(provider: &str, subject: &str, conn: &mut AsyncPgConnection) -> Result<Option<UserIdentity>> {
+        user_identities::table
+            .filter(user_identities::provider.eq(provider))
+            .filter(user_identities::subject.eq(subject))
+            .first(conn)
+            .await
+            .optional()
+            .map_err(|e| UserIdentityError::DatabaseError(e).into())
+    }
+
+    /// Links the provider account to the user. Each side can only be linked once per provider.
+    pub async fn link(user_id: i64, provider: &str, subject: &str, conn: &mut AsyncPgConnection) -> Result<UserIdentity> {
+        let new_identity = NewUserIdentity {
+            user_id,
+            provider: provider.to_string(),
+            subject: subject.to_string(),
+            last_login_at: None,
+        };
+
+        diesel::insert_into(user_identities::table)
+            .values(&new_identity)
+            .get_result(conn)
+            .await
+            .map_err(|e| match e {
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => UserIdentityError::AlreadyLinked(provider.to_string()).into(),
+                e => UserIdentityError::DatabaseError(e).into()
+            })
+    }
+
+    pub async fn touch(&self, conn: &mut AsyncPgConnection) -> Result<UserIdentity> {
+        diesel::update(user_identities::table.find(self.id))
+            .set(user_identities::last_login_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)
+            .await
+            .map_err(|e| UserIdentityError::DatabaseError(e).into())
+    }
+}
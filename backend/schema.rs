@@ -1,14 +1,190 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "device_type"))]
+    pub struct DeviceType;
+
     #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "user_role"))]
     pub struct UserRole;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "user_status"))]
+    pub struct UserStatus;
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> BigSerial,
+        #[max_length = 255]
+        name -> Varchar,
+        #[max_length = 16]
+        prefix -> Varchar,
+        #[max_length = 64]
+        key_hash -> Varchar,
+        permissions -> Array<Text>,
+        #[max_length = 255]
+        store -> Nullable<Varchar>,
+        created_by -> Nullable<Int8>,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> BigSerial,
+        #[max_length = 64]
+        event_type -> Varchar,
+        actor_id -> Nullable<Int8>,
+        target_id -> Nullable<Int8>,
+        #[max_length = 45]
+        ip_address -> Varchar,
+        #[max_length = 512]
+        user_agent -> Varchar,
+        details -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::DeviceType;
+
+    devices (id) {
+        id -> BigSerial,
+        #[max_length = 36]
+        device_uid -> Varchar,
+        #[max_length = 255]
+        name -> Varchar,
+        #[max_length = 255]
+        store -> Varchar,
+        device_type -> DeviceType,
+        #[max_length = 64]
+        secret_hash -> Varchar,
+        enrolled_by -> Nullable<Int8>,
+        last_seen_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    employees (id) {
+        id -> BigSerial,
+        user_id -> Int8,
+        #[max_length = 32]
+        employee_code -> Varchar,
+        #[max_length = 255]
+        job_title -> Varchar,
+        hourly_rate -> Nullable<Int8>,
+        hire_date -> Date,
+        termination_date -> Nullable<Date>,
+        stores -> Array<Text>,
+        #[max_length = 255]
+        emergency_contact_name -> Nullable<Varchar>,
+        #[max_length = 16]
+        emergency_contact_phone -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    mfa_recovery_codes (id) {
+        id -> BigSerial,
+        user_id -> Int8,
+        #[max_length = 64]
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    permissions (id) {
+        id -> BigSerial,
+        #[max_length = 64]
+        code -> Varchar,
+        #[max_length = 255]
+        description -> Varchar,
+    }
+}
+
+diesel::table! {
+    role_permissions (role_id, permission_id) {
+        role_id -> Int8,
+        permission_id -> Int8,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> BigSerial,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 255]
+        description -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::UserRole;
+
+    staff_invites (id) {
+        id -> BigSerial,
+        #[max_length = 16]
+        whatsapp -> Varchar,
+        #[max_length = 255]
+        fullname -> Varchar,
+        role -> UserRole,
+        #[max_length = 255]
+        store -> Nullable<Varchar>,
+        #[max_length = 64]
+        code_hash -> Varchar,
+        invited_by -> Nullable<Int8>,
+        user_id -> Nullable<Int8>,
+        expires_at -> Timestamp,
+        accepted_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_identities (id) {
+        id -> BigSerial,
+        user_id -> Int8,
+        #[max_length = 64]
+        provider -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        last_login_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_role_assignments (user_id, role_id) {
+        user_id -> Int8,
+        role_id -> Int8,
+    }
 }
 
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::UserRole;
+    use super::sql_types::UserStatus;
 
     users (id) {
         id -> BigSerial,
@@ -18,10 +194,44 @@ diesel::table! {
         fullname -> Varchar,
         #[max_length = 255]
         password -> Varchar,
-        #[max_length = 15]
+        #[max_length = 16]
         whatsapp -> Varchar,
         role -> UserRole,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 255]
+        pin -> Nullable<Varchar>,
+        #[max_length = 64]
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled_at -> Nullable<Timestamp>,
+        whatsapp_verified_at -> Nullable<Timestamp>,
+        status -> UserStatus,
+        token_version -> Int4,
     }
 }
+
+diesel::joinable!(api_keys -> users (created_by));
+diesel::joinable!(devices -> users (enrolled_by));
+diesel::joinable!(employees -> users (user_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(role_permissions -> permissions (permission_id));
+diesel::joinable!(role_permissions -> roles (role_id));
+diesel::joinable!(staff_invites -> users (invited_by));
+diesel::joinable!(user_identities -> users (user_id));
+diesel::joinable!(user_role_assignments -> roles (role_id));
+diesel::joinable!(user_role_assignments -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_events,
+    devices,
+    employees,
+    mfa_recovery_codes,
+    permissions,
+    role_permissions,
+    roles,
+    staff_invites,
+    user_identities,
+    user_role_assignments,
+    users,
+);
@@ -1,3 +1,4 @@
+use chrono::Utc;
 use diesel_async::AsyncPgConnection;
 use crate::error::Result;
 use crate::models::user::{NewUser, User, UserRole};
@@ -11,15 +12,17 @@ pub async fn seed(conn: &mut AsyncPgConnection) -> Result<()> {
             username: "admin".to_string(),
             fullname: "System Administrator".to_string(),
             password: "admin123".to_string(), // Will be hashed automatically
-            whatsapp: "123456789".to_string(),
+            whatsapp: "+6281200000001".to_string(),
             role: UserRole::SuperAdmin,
+            whatsapp_verified_at: Some(Utc::now().naive_utc()),
         },
         NewUser {
             username: "cashier".to_string(),
             fullname: "Default Cashier".to_string(),
             password: "cashier123".to_string(), // Will be hashed automatically
-            whatsapp: "987654321".to_string(),
+            whatsapp: "+6281200000002".to_string(),
             role: UserRole::Employee,
+            whatsapp_verified_at: Some(Utc::now().naive_utc()),
         },
         // Add more users here as needed
     ];
@@ -0,0 +1,41 @@
+use anyhow::anyhow;
+use chrono::{Duration, Utc};
+use diesel_async::AsyncPgConnection;
+
+use crate::config::config::Config;
+use crate::error::{log_error, Error, Result};
+use crate::models::audit_event::{AuditEvent, NewAuditEvent};
+
+/// Writes the security audit trail to Postgres and enforces its retention.
+pub struct AuditService {
+    retention_days: u32, // 0 keeps events forever
+}
+
+impl AuditService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            retention_days: config.audit_retention_days,
+        }
+    }
+
+    /// Records an event. A failed write is logged but never fails the request that
+    /// caused it, the action itself already happened.
+    pub async fn record(&self, event: NewAuditEvent, conn: &mut AsyncPgConnection) {
+        let event_type = event.event_type.clone();
+
+        if let Err(e) = AuditEvent::create(event, conn).await {
+            log_error(&Error::GeneralError(anyhow!("Failed to record audit event '{}': {}", event_type, e)));
+        }
+    }
+
+    /// Deletes the events that are past the retention period, returns how many were removed.
+    pub async fn purge_expired(&self, conn: &mut AsyncPgConnection) -> Result<usize> {
+        if self.retention_days == 0 {
+            return Ok(0);
+        }
+
+        let cutoff = Utc::now().naive_utc() - Duration::days(self.retention_days as i64);
+
+        AuditEvent::delete_older_than(cutoff, conn).await
+    }
+}
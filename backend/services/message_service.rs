@@ -0,0 +1,80 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+
+use crate::config::config::Config;
+use crate::error::{Error, Result};
+use crate::models::staff_invite::StaffInvite;
+
+/// Delivers short text messages (OTP codes, notices, ...) to a user's WhatsApp
+/// number. Implementations are picked with `MESSAGE_SENDER`.
+#[async_trait]
+pub trait MessageSender: Send + Sync {
+    async fn send(&self, whatsapp: &str, message: &str) -> Result<()>;
+}
+
+/// Only logs that a message would have been sent, for development and tests. The text
+/// itself is left out, messages carry codes and links nobody but the recipient may see.
+pub struct LogMessageSender;
+
+#[async_trait]
+impl MessageSender for LogMessageSender {
+    async fn send(&self, whatsapp: &str, message: &str) -> Result<()> {
+        println!("[INFO] Not sending a message of {} characters to {}, MESSAGE_SENDER is 'log'", message.chars().count(), mask_number(whatsapp));
+        Ok(())
+    }
+}
+
+/// Keeps messages instead of sending them, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryMessageSender {
+    sent: std::sync::Mutex<Vec<(String, String)>>,
+}
+
+#[cfg(test)]
+impl MemoryMessageSender {
+    /// Every message so far as (number, text), oldest first.
+    pub(crate) fn sent(&self) -> Vec<(String, String)> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MessageSender for MemoryMessageSender {
+    async fn send(&self, whatsapp: &str, message: &str) -> Result<()> {
+        self.sent.lock().unwrap().push((whatsapp.to_string(), message.to_string()));
+        Ok(())
+    }
+}
+
+// e.g. "+62*******6789", enough to tell recipients apart
+fn mask_number(whatsapp: &str) -> String {
+    let chars: Vec<char> = whatsapp.chars().collect();
+    let shown = chars.len().saturating_sub(4);
+
+    chars.iter()
+        .enumerate()
+        .map(|(i, c)| if i < 3.min(shown) || i >= shown { *c } else { '*' })
+        .collect()
+}
+
+pub fn message_sender_from_config(config: &Config) -> Result<Box<dyn MessageSender>> {
+    match config.message_sender.as_str() {
+        "log" => Ok(Box::new(LogMessageSender)),
+        other => Err(Error::ConfigError(anyhow!("Unknown message sender '{}'", other)))
+    }
+}
+
+/// Sends the invitee their link, which is returned as well.
+pub async fn send_invite(sender: &dyn MessageSender, config: &Config, invite: &StaffInvite, code: &str) -> Result<String> {
+    let link = format!("{}?code={}", config.invite_url, code);
+    let message = format!(
+        "You've been invited to join TeaPOS as {}. Set up your account here: {} (the link expires in {} hours).",
+        invite.role, link, config.invite_expiry / 3600
+    );
+
+    sender.send(&invite.whatsapp, &message).await?;
+
+    Ok(link)
+}
\ No newline at end of file
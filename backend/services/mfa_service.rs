@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use diesel_async::AsyncPgConnection;
+use thiserror::Error;
+
+use crate::config::config::Config;
+use crate::error::{Error as AppError, Result};
+use crate::models::recovery_code::RecoveryCode;
+use crate::models::user::{User, UserRole};
+use crate::services::redis_service::RedisService;
+use crate::services::token_service::TokenClaims;
+use crate::utils::totp;
+
+pub struct MfaService {
+    redis_service: Arc<RedisService>,
+    issuer: String,
+    required_roles: Vec<UserRole>,
+}
+
+#[derive(Debug, Error)]
+pub enum MfaError {
+    #[error("Two-factor authentication is not set up for this account")]
+    NotSetUp,
+    #[error("This sign-in attempt was already used, sign in again")]
+    ChallengeUsed,
+    #[error("Redis error: {0}")]
+    RedisError(String),
+}
+
+impl From<MfaError> for AppError {
+    fn from(error: MfaError) -> Self {
+        match error {
+            MfaError::NotSetUp | MfaError::ChallengeUsed => AppError::ApiError(error.into()),
+            MfaError::RedisError(_) => AppError::ServiceError(error.into()),
+        }
+    }
+}
+
+impl MfaService {
+    pub fn new(redis_service: Arc<RedisService>, config: &Config) -> Self {
+        let required_roles = config.mfa_required_roles.split(',')
+            .map(str::trim)
+            .filter(|role| !role.is_empty())
+            .filter_map(|role| match role.parse::<UserRole>() {
+                Ok(role) => Some(role),
+                Err(e) => {
+                    eprintln!("Config Error: Ignoring MFA_REQUIRED_ROLES entry: {}", e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            redis_service,
+            issuer: config.mfa_issuer.clone(),
+            required_roles,
+        }
+    }
+
+    pub fn is_required(&self, role: UserRole) -> bool {
+        self.required_roles.contains(&role)
+    }
+
+    /// Whether a login of this user has to go through the second step.
+    pub fn is_challenged(&self, user: &User) -> bool {
+        user.has_mfa() || self.is_required(user.role)
+    }
+
+    pub fn provisioning_uri(&self, secret: &str, user: &User) -> String {
+        totp::provisioning_uri(secret, &self.issuer, &user.username)
+    }
+
+    /// Checks a TOTP code against the user's secret (enabled or still pending). A
+    /// code is accepted only once, replaying it inside its validity window fails.
+    pub async fn verify_totp(&self, user: &User, code: &str) -> Result<bool> {
+        let secret = user.totp_secret.as_deref().ok_or(MfaError::NotSetUp)?;
+
+        let step = match totp::verify(secret, code, Utc::now().timestamp()) {
+            Some(step) => step,
+            None => return Ok(false)
+        };
+
+        let mut conn = self.redis_service.get_connection();
+
+        // only lives as long as the code could still be accepted
+        let first_use: bool = redis::cmd("SET")
+            .arg(format!("mfa:{}:used:{}", user.id, step))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(120)
+            .query_async::<Option<String>>(&mut conn)
+            .await
+            .map(|reply| reply.is_some())
+            .map_err(|e| MfaError::RedisError(e.to_string()))?;
+
+        Ok(first_use)
+    }
+
+    /// Marks the MFA token of a login as used, failing if it already was. Used jtis are
+    /// remembered until the token expires.
+    pub async fn consume_challenge(&self, claims: &TokenClaims) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+        let ttl = (claims.exp - Utc::now().timestamp()).max(1);
+
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(format!("mfa:challenge:used:{}", claims.jti))
+            .arg(claims.sub)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| MfaError::RedisError(e.to_string()))?;
+
+        if stored.is_none() {
+            return Err(MfaError::ChallengeUsed.into());
+        }
+
+        Ok(())
+    }
+
+    /// Accepts either a TOTP code or one of the user's unused recovery codes.
+    pub async fn verify_code(&self, user: &User, code: &str, conn: &mut AsyncPgConnection) -> Result<bool> {
+        if self.verify_totp(user, code).await? {
+            return Ok(true);
+        }
+
+        if !user.has_mfa() {
+            return Ok(false);
+        }
+
+        RecoveryCode::consume(user.id, code, conn).await
+    }
+}
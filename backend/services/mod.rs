@@ -1,3 +1,14 @@
 pub mod redis_service;
 pub mod token_service;
-pub mod session_service;
\ No newline at end of file
+pub mod session_service;
+pub mod permission_service;
+pub mod pin_service;
+pub mod rate_limit_service;
+pub mod mfa_service;
+pub mod message_service;
+pub mod otp_service;
+pub mod audit_service;
+pub mod oidc_service;
+pub mod override_service;
+pub mod user_state_service;
+pub mod user_csv_service;
\ No newline at end of file
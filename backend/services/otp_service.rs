@@ -0,0 +1,221 @@
+use std::sync::Arc;
+
+use rand::Rng;
+use redis::{AsyncCommands, RedisResult, Script};
+use thiserror::Error;
+
+use crate::config::config::Config;
+use crate::error::{Error as AppError, Result};
+use crate::services::message_service::MessageSender;
+use crate::services::rate_limit_service::COUNT_SCRIPT;
+use crate::services::redis_service::RedisService;
+use crate::utils::crypto;
+
+/// One-time codes sent to the user out of band (WhatsApp), scoped by purpose so a
+/// code issued for one flow can't be redeemed in another. Only hashes are stored.
+pub struct OtpService {
+    redis_service: Arc<RedisService>,
+    count_script: Script,
+    expiry: u64, // in sec
+    max_attempts: u32,
+    resend_cooldown: u64, // in sec
+}
+
+#[derive(Debug, Error)]
+pub enum OtpError {
+    #[error("Too many wrong codes, request a new one")]
+    TooManyAttempts,
+    #[error("Redis error: {0}")]
+    RedisError(String),
+}
+
+impl From<OtpError> for AppError {
+    fn from(error: OtpError) -> Self {
+        match error {
+            OtpError::TooManyAttempts => AppError::ApiError(error.into()),
+            OtpError::RedisError(_) => AppError::ServiceError(error.into()),
+        }
+    }
+}
+
+impl OtpService {
+    pub fn new(redis_service: Arc<RedisService>, config: &Config) -> Self {
+        Self {
+            redis_service,
+            count_script: Script::new(COUNT_SCRIPT),
+            expiry: config.otp_expiry,
+            max_attempts: config.otp_max_attempts,
+            resend_cooldown: config.otp_resend_cooldown,
+        }
+    }
+
+    fn expiry_minutes(&self) -> u64 {
+        self.expiry.div_ceil(60)
+    }
+
+    /// Issues a code and sends it to `whatsapp`, worded by `message` from the code and
+    /// the minutes it stays valid. Nothing is sent inside the resend cooldown.
+    pub async fn send(
+        &self,
+        sender: &dyn MessageSender,
+        purpose: &str,
+        user_id: i64,
+        whatsapp: &str,
+        message: impl Fn(&str, u64) -> String
+    ) -> Result<()> {
+        if let Some(code) = self.issue(purpose, user_id).await? {
+            sender.send(whatsapp, &message(&code, self.expiry_minutes())).await?;
+        }
+
+        Ok(())
+    }
+
+    // bound to the user, so equal codes of different users don't share a hash
+    fn hash(user_id: i64, code: &str) -> String {
+        crypto::sha256_hex(&format!("{}:{}", user_id, code))
+    }
+
+    /// Issues a new code, replacing any previous one. Returns `None` while the last
+    /// code is still inside its resend cooldown.
+    pub async fn issue(&self, purpose: &str, user_id: i64) -> Result<Option<String>> {
+        let mut conn = self.redis_service.get_connection();
+        let code_key = format!("otp:{}:{}", purpose, user_id);
+
+        let cooling_down: bool = conn.exists(format!("{}:cooldown", code_key)).await
+            .map_err(|e| OtpError::RedisError(e.to_string()))?;
+
+        if cooling_down {
+            return Ok(None);
+        }
+
+        let code = format!("{:06}", rand::rng().random_range(0..1_000_000));
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set_ex(&code_key, Self::hash(user_id, &code), self.expiry)
+            .del(format!("{}:attempts", code_key))
+            .set_ex(format!("{}:cooldown", code_key), 1, self.resend_cooldown);
+
+        let _: () = pipe.query_async(&mut conn).await
+            .map_err(|e| OtpError::RedisError(e.to_string()))?;
+
+        Ok(Some(code))
+    }
+
+    /// Redeems a code. It's single use, and burned after `max_attempts` wrong guesses.
+    pub async fn verify(&self, purpose: &str, user_id: i64, code: &str) -> Result<bool> {
+        let mut conn = self.redis_service.get_connection();
+        let code_key = format!("otp:{}:{}", purpose, user_id);
+        let attempts_key = format!("{}:attempts", code_key);
+
+        let stored_hash: Option<String> = conn.get(&code_key).await
+            .map_err(|e| OtpError::RedisError(e.to_string()))?;
+
+        let stored_hash = match stored_hash {
+            Some(hash) => hash,
+            None => return Ok(false)
+        };
+
+        let (attempts, _): (u32, i64) = self.count_script.key(&attempts_key).arg(self.expiry).invoke_async(&mut conn).await
+            .map_err(|e| OtpError::RedisError(e.to_string()))?;
+
+        if attempts > self.max_attempts {
+            let _: RedisResult<()> = conn.del(&[&code_key, &attempts_key]).await;
+            return Err(OtpError::TooManyAttempts.into());
+        }
+
+        if !crypto::constant_time_eq(&stored_hash, &Self::hash(user_id, code.trim())) {
+            return Ok(false);
+        }
+
+        // whoever deletes the code redeemed it, a concurrent request with the same code loses
+        let deleted: u32 = conn.del(&code_key).await
+            .map_err(|e| OtpError::RedisError(e.to_string()))?;
+        let _: RedisResult<()> = conn.del(&attempts_key).await;
+
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::message_service::MemoryMessageSender;
+    use crate::services::redis_service::stub;
+
+    const WHATSAPP: &str = "+6281234567890";
+
+    async fn otp_service() -> OtpService {
+        let config = Config { otp_max_attempts: 3, otp_resend_cooldown: 60, ..Config::default() };
+        OtpService::new(stub::redis_service().await, &config)
+    }
+
+    // the code is the only run of six digits in the message
+    async fn send_code(otp_service: &OtpService, sender: &MemoryMessageSender, purpose: &str, user_id: i64) -> String {
+        otp_service.send(sender, purpose, user_id, WHATSAPP, |code, minutes| format!("Code {}, valid for {} minutes", code, minutes)).await.unwrap();
+
+        let (whatsapp, message) = sender.sent().pop().unwrap();
+        assert_eq!(whatsapp, WHATSAPP);
+
+        message.split(|c: char| !c.is_ascii_digit()).find(|part| part.len() == 6).unwrap().to_string()
+    }
+
+    #[ntex::test]
+    async fn a_code_can_only_be_used_once() {
+        let (otp_service, sender) = (otp_service().await, MemoryMessageSender::default());
+        let code = send_code(&otp_service, &sender, "password_reset", 1).await;
+
+        assert!(otp_service.verify("password_reset", 1, &code).await.unwrap());
+        assert!(!otp_service.verify("password_reset", 1, &code).await.unwrap());
+    }
+
+    #[ntex::test]
+    async fn codes_are_bound_to_their_purpose_and_user() {
+        let (otp_service, sender) = (otp_service().await, MemoryMessageSender::default());
+        let code = send_code(&otp_service, &sender, "password_reset", 1).await;
+
+        assert!(!otp_service.verify("whatsapp_verify", 1, &code).await.unwrap());
+        assert!(!otp_service.verify("password_reset", 2, &code).await.unwrap());
+        assert!(otp_service.verify("password_reset", 1, &code).await.unwrap());
+    }
+
+    fn wrong_code(code: &str) -> &'static str {
+        if code == "000000" { "111111" } else { "000000" }
+    }
+
+    #[ntex::test]
+    async fn wrong_codes_count_up_to_the_limit() {
+        let (otp_service, sender) = (otp_service().await, MemoryMessageSender::default());
+        let code = send_code(&otp_service, &sender, "password_reset", 1).await;
+
+        for _ in 0..2 {
+            assert!(!otp_service.verify("password_reset", 1, wrong_code(&code)).await.unwrap());
+        }
+
+        assert!(otp_service.verify("password_reset", 1, &code).await.unwrap());
+    }
+
+    #[ntex::test]
+    async fn too_many_wrong_codes_burn_the_code() {
+        let (otp_service, sender) = (otp_service().await, MemoryMessageSender::default());
+        let code = send_code(&otp_service, &sender, "password_reset", 1).await;
+
+        for _ in 0..3 {
+            assert!(!otp_service.verify("password_reset", 1, wrong_code(&code)).await.unwrap());
+        }
+
+        assert!(otp_service.verify("password_reset", 1, &code).await.is_err());
+        assert!(!otp_service.verify("password_reset", 1, &code).await.unwrap());
+    }
+
+    #[ntex::test]
+    async fn nothing_is_sent_during_the_resend_cooldown() {
+        let (otp_service, sender) = (otp_service().await, MemoryMessageSender::default());
+        let code = send_code(&otp_service, &sender, "whatsapp_verify", 1).await;
+
+        otp_service.send(&sender, "whatsapp_verify", 1, WHATSAPP, |code, _| code.to_string()).await.unwrap();
+
+        assert_eq!(sender.sent().len(), 1);
+        assert!(otp_service.verify("whatsapp_verify", 1, &code).await.unwrap());
+    }
+}
\ No newline at end of file
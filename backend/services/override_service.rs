@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use thiserror::Error;
+
+use crate::error::{Error as AppError, Result};
+use crate::services::redis_service::RedisService;
+use crate::services::token_service::{OverrideClaims, TokenClaims};
+
+/// Makes sure every manager override token (see `TokenService::generate_override_token`)
+/// is only redeemed once. Used jtis are remembered in Redis until the token expires.
+pub struct OverrideService {
+    redis_service: Arc<RedisService>,
+}
+
+#[derive(Debug, Error)]
+pub enum OverrideError {
+    #[error("Manager approval is required for this action")]
+    ApprovalRequired,
+    #[error("Invalid or expired manager approval")]
+    InvalidApproval,
+    #[error("This manager approval has already been used")]
+    AlreadyUsed,
+    #[error("Redis error: {0}")]
+    RedisError(String),
+}
+
+impl From<OverrideError> for AppError {
+    fn from(error: OverrideError) -> Self {
+        match error {
+            OverrideError::RedisError(_) => AppError::ServiceError(error.into()),
+            _ => AppError::ApiError(error.into()),
+        }
+    }
+}
+
+impl OverrideService {
+    pub fn new(redis_service: Arc<RedisService>) -> Self {
+        Self { redis_service }
+    }
+
+    /// Redeems an approval for `action` on the cashier's session it was issued to.
+    pub async fn redeem(&self, approval: &OverrideClaims, claims: &TokenClaims, action: &str) -> Result<()> {
+        if approval.action != action || approval.cashier != claims.sub || approval.sid != claims.sid {
+            return Err(OverrideError::InvalidApproval.into());
+        }
+
+        self.consume(approval).await
+    }
+
+    /// Marks the override as used, failing if it already was.
+    pub async fn consume(&self, claims: &OverrideClaims) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+        let ttl = (claims.exp - Utc::now().timestamp()).max(1);
+
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(format!("override:used:{}", claims.jti))
+            .arg(claims.sub)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| OverrideError::RedisError(e.to_string()))?;
+
+        if stored.is_none() {
+            return Err(OverrideError::AlreadyUsed.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::permission;
+    use crate::services::redis_service::stub;
+
+    use super::*;
+
+    fn cashier(sid: &str) -> TokenClaims {
+        let now = Utc::now().timestamp();
+
+        TokenClaims {
+            sub: 7,
+            role: "employee".to_string(),
+            exp: now + 300,
+            iat: now,
+            jti: "acc-1".to_string(),
+            sid: sid.to_string(),
+            token_type: "acc".to_string(),
+            ver: 0,
+            act: None,
+        }
+    }
+
+    fn approval(jti: &str, action: &str) -> OverrideClaims {
+        let now = Utc::now().timestamp();
+
+        OverrideClaims {
+            sub: 2,
+            cashier: 7,
+            action: action.to_string(),
+            exp: now + 60,
+            iat: now,
+            jti: jti.to_string(),
+            sid: "session-1".to_string(),
+            token_type: "ovr".to_string(),
+        }
+    }
+
+    fn is_error(result: Result<()>, expected: OverrideError) -> bool {
+        matches!(result, Err(AppError::ApiError(e)) if e.to_string() == expected.to_string())
+    }
+
+    #[ntex::test]
+    async fn an_approval_can_only_be_used_once() {
+        let service = OverrideService::new(stub::redis_service().await);
+        let approval = approval("ovr-1", permission::DRAWER_OPEN);
+
+        service.redeem(&approval, &cashier("session-1"), permission::DRAWER_OPEN).await.unwrap();
+
+        let replayed = service.redeem(&approval, &cashier("session-1"), permission::DRAWER_OPEN).await;
+        assert!(is_error(replayed, OverrideError::AlreadyUsed));
+    }
+
+    #[ntex::test]
+    async fn an_approval_only_covers_its_action_and_session() {
+        let service = OverrideService::new(stub::redis_service().await);
+        let approval = approval("ovr-2", permission::ORDER_VOID);
+
+        let wrong_action = service.redeem(&approval, &cashier("session-1"), permission::DRAWER_OPEN).await;
+        assert!(is_error(wrong_action, OverrideError::InvalidApproval));
+
+        let other_session = service.redeem(&approval, &cashier("session-2"), permission::ORDER_VOID).await;
+        assert!(is_error(other_session, OverrideError::InvalidApproval));
+
+        // refused attempts don't burn the approval
+        service.redeem(&approval, &cashier("session-1"), permission::ORDER_VOID).await.unwrap();
+    }
+}
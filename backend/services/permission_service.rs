@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use diesel_async::AsyncPgConnection;
+use redis::{AsyncCommands, RedisResult};
+
+use crate::config::config::Config;
+use crate::error::{Error, Result};
+use crate::models::role::Role;
+use crate::models::user::UserRole;
+use crate::services::redis_service::RedisService;
+
+/// Resolves what a user is allowed to do from their assigned roles.
+///
+/// Lookups are cached in Redis (`user:{id}:permissions`) for a short while, the cache
+/// is dropped whenever a role mapping or a user's roles change.
+pub struct PermissionService {
+    redis_service: Arc<RedisService>,
+    cache_ttl: u64, // in sec
+}
+
+impl PermissionService {
+    pub fn new(redis_service: Arc<RedisService>, config: &Config) -> Self {
+        Self {
+            redis_service,
+            cache_ttl: config.permission_cache_ttl,
+        }
+    }
+
+    pub async fn get_user_permissions(&self, user_id: i64, conn: &mut AsyncPgConnection) -> Result<HashSet<String>> {
+        let mut redis_conn = self.redis_service.get_connection();
+        let cache_key = format!("user:{}:permissions", user_id);
+
+        let cached: Option<String> = redis_conn.get(&cache_key).await
+            .map_err(|e| Error::RedisError(anyhow!("Failed to read permission cache: {}", e)))?;
+
+        if let Some(permissions) = cached.and_then(|json| serde_json::from_str::<HashSet<String>>(&json).ok()) {
+            return Ok(permissions);
+        }
+
+        let permissions: HashSet<String> = Role::permissions_for_user(user_id, conn).await?
+            .into_iter()
+            .collect();
+
+        if let Ok(json) = serde_json::to_string(&permissions) {
+            let _: RedisResult<()> = redis_conn.set_ex(&cache_key, json, self.cache_ttl).await;
+        }
+
+        Ok(permissions)
+    }
+
+    pub async fn has_permission(&self, user_id: i64, role: UserRole, permission: &str, conn: &mut AsyncPgConnection) -> Result<bool> {
+        // superadmins are never locked out by a misconfigured role mapping
+        if role == UserRole::SuperAdmin {
+            return Ok(true);
+        }
+
+        Ok(self.get_user_permissions(user_id, conn).await?.contains(permission))
+    }
+
+    pub async fn invalidate_users(&self, user_ids: &[i64]) -> Result<()> {
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut redis_conn = self.redis_service.get_connection();
+
+        let cache_keys: Vec<String> = user_ids.iter()
+            .map(|user_id| format!("user:{}:permissions", user_id))
+            .collect();
+
+        let _: () = redis_conn.del(cache_keys).await
+            .map_err(|e| Error::RedisError(anyhow!("Failed to clear permission cache: {}", e)))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use redis::{AsyncCommands, RedisResult, Script};
+use thiserror::Error;
+
+use crate::config::config::Config;
+use crate::error::{Error as AppError, Result};
+use crate::services::rate_limit_service::COUNT_SCRIPT;
+use crate::services::redis_service::RedisService;
+
+/// Tracks failed PIN attempts per user, locking the PIN for a while once too many
+/// wrong ones have been entered. Counters live in Redis so every backend instance
+/// sees the same lockout.
+pub struct PinService {
+    redis_service: Arc<RedisService>,
+    count_script: Script,
+    max_attempts: u32,
+    lockout_duration: u64, // in sec
+}
+
+#[derive(Debug, Error)]
+pub enum PinError {
+    #[error("Too many wrong PIN attempts, try again later")]
+    PinLocked,
+    #[error("Redis error: {0}")]
+    RedisError(String),
+}
+
+impl From<PinError> for AppError {
+    fn from(error: PinError) -> Self {
+        match error {
+            PinError::PinLocked => AppError::ApiError(error.into()),
+            PinError::RedisError(_) => AppError::ServiceError(error.into()),
+        }
+    }
+}
+
+impl PinService {
+    pub fn new(redis_service: Arc<RedisService>, config: &Config) -> Self {
+        Self {
+            redis_service,
+            count_script: Script::new(COUNT_SCRIPT),
+            max_attempts: config.pin_max_attempts,
+            lockout_duration: config.pin_lockout_duration,
+        }
+    }
+
+    pub async fn ensure_not_locked(&self, user_id: i64) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+
+        let failures: Option<u32> = conn.get(format!("pin:{}:failures", user_id)).await
+            .map_err(|e| PinError::RedisError(e.to_string()))?;
+
+        if failures.unwrap_or(0) >= self.max_attempts {
+            return Err(PinError::PinLocked.into());
+        }
+
+        Ok(())
+    }
+
+    pub async fn register_failure(&self, user_id: i64) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+
+        // the window starts at the first failure, so the lockout ends `lockout_duration` after it
+        let _: (u32, i64) = self.count_script.key(format!("pin:{}:failures", user_id)).arg(self.lockout_duration).invoke_async(&mut conn).await
+            .map_err(|e| PinError::RedisError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    pub async fn reset(&self, user_id: i64) {
+        let mut conn = self.redis_service.get_connection();
+        let _: RedisResult<()> = conn.del(format!("pin:{}:failures", user_id)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::redis_service::stub;
+
+    async fn pin_service() -> (PinService, Arc<RedisService>) {
+        let redis_service = stub::redis_service().await;
+        let config = Config { pin_max_attempts: 3, pin_lockout_duration: 600, ..Config::default() };
+
+        (PinService::new(redis_service.clone(), &config), redis_service)
+    }
+
+    #[ntex::test]
+    async fn locks_the_pin_after_too_many_failures() {
+        let (pin_service, _) = pin_service().await;
+
+        for _ in 0..2 {
+            pin_service.register_failure(1).await.unwrap();
+            assert!(pin_service.ensure_not_locked(1).await.is_ok());
+        }
+
+        pin_service.register_failure(1).await.unwrap();
+        assert!(pin_service.ensure_not_locked(1).await.is_err());
+        assert!(pin_service.ensure_not_locked(2).await.is_ok());
+
+        pin_service.reset(1).await;
+        assert!(pin_service.ensure_not_locked(1).await.is_ok());
+    }
+
+    #[ntex::test]
+    async fn the_failure_counter_always_expires() {
+        let (pin_service, redis_service) = pin_service().await;
+
+        pin_service.register_failure(1).await.unwrap();
+        pin_service.register_failure(1).await.unwrap();
+
+        let ttl: i64 = redis_service.get_connection().ttl("pin:1:failures").await.unwrap();
+        assert!((1..=600).contains(&ttl), "ttl {}", ttl);
+    }
+}
\ No newline at end of file
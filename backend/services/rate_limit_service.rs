@@ -0,0 +1,167 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use governor::clock::{Clock, DefaultClock};
+use redis::{AsyncCommands, RedisResult, Script};
+use thiserror::Error;
+
+use crate::config::config::Config;
+use crate::error::{Error as AppError, Result};
+use crate::services::redis_service::RedisService;
+
+// upper bound of the delay forced between two failed logins, before the lockout kicks in
+const MAX_LOGIN_DELAY: u64 = 30; // in sec
+
+// counts a hit and starts the window of a new counter in one step, so a counter can never
+// be left without an expiry. Returns the count and the seconds left in the window
+pub(crate) const COUNT_SCRIPT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if redis.call('TTL', KEYS[1]) < 0 then
+    redis.call('EXPIRE', KEYS[1], ARGV[1])
+end
+return {count, redis.call('TTL', KEYS[1])}
+";
+
+/// Throttles the unauthenticated auth endpoints. An in-memory limiter per instance
+/// absorbs bursts cheaply, the actual limits are fixed window counters in Redis so
+/// they hold no matter which instance a request lands on.
+pub struct RateLimitService {
+    redis_service: Arc<RedisService>,
+    burst_limiter: DefaultKeyedRateLimiter<String>,
+    clock: DefaultClock,
+    count_script: Script,
+    window: u64, // in sec
+    ip_max: u32,
+    username_max: u32,
+    login_max_failures: u32,
+    login_lockout_duration: u64, // in sec
+}
+
+#[derive(Debug, Error)]
+pub enum RateLimitError {
+    #[error("Rate limited, retry in {0} seconds")]
+    RateLimited(u64),
+    #[error("Redis error: {0}")]
+    RedisError(String),
+}
+
+impl From<RateLimitError> for AppError {
+    fn from(error: RateLimitError) -> Self {
+        match error {
+            RateLimitError::RateLimited(retry_after) => AppError::TooManyRequestsError(retry_after),
+            RateLimitError::RedisError(_) => AppError::ServiceError(error.into()),
+        }
+    }
+}
+
+impl RateLimitService {
+    pub fn new(redis_service: Arc<RedisService>, config: &Config) -> Self {
+        let burst = NonZeroU32::new(config.rate_limit_burst).unwrap_or(NonZeroU32::MIN);
+
+        Self {
+            redis_service,
+            burst_limiter: RateLimiter::keyed(Quota::per_second(burst)),
+            clock: DefaultClock::default(),
+            count_script: Script::new(COUNT_SCRIPT),
+            window: config.rate_limit_window,
+            ip_max: config.rate_limit_ip_max,
+            username_max: config.rate_limit_username_max,
+            login_max_failures: config.login_max_failures,
+            login_lockout_duration: config.login_lockout_duration,
+        }
+    }
+
+    /// Counts a request to `action` coming from `ip`.
+    pub async fn limit_ip(&self, action: &str, ip: &str) -> Result<()> {
+        let key = format!("{}:{}", action, ip);
+
+        if let Err(not_until) = self.burst_limiter.check_key(&key) {
+            let wait = not_until.wait_time_from(self.clock.now());
+            return Err(RateLimitError::RateLimited(wait.as_secs().max(1)).into());
+        }
+
+        // the keyed limiter never forgets a key on its own
+        if self.burst_limiter.len() > 10_000 {
+            self.burst_limiter.retain_recent();
+        }
+
+        self.hit(&format!("ratelimit:{}:ip:{}", action, ip), self.ip_max).await
+    }
+
+    /// Counts a request to `action` targeting `username` (or any other account identifier).
+    pub async fn limit_username(&self, action: &str, username: &str) -> Result<()> {
+        self.hit(&format!("ratelimit:{}:user:{}", action, username.to_lowercase()), self.username_max).await
+    }
+
+    async fn hit(&self, key: &str, max: u32) -> Result<()> {
+        let (count, ttl) = self.count(key, self.window).await?;
+
+        if count > max {
+            return Err(RateLimitError::RateLimited(ttl.max(1) as u64).into());
+        }
+
+        Ok(())
+    }
+
+    async fn count(&self, key: &str, window: u64) -> Result<(u32, i64)> {
+        let mut conn = self.redis_service.get_connection();
+
+        let counted: (u32, i64) = self.count_script.key(key).arg(window).invoke_async(&mut conn).await
+            .map_err(|e| RateLimitError::RedisError(e.to_string()))?;
+
+        Ok(counted)
+    }
+
+    /// Rejects the login while the account is locked out or still inside the delay
+    /// forced by its previous failure.
+    pub async fn ensure_login_allowed(&self, username: &str) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+
+        let ttl: i64 = conn.ttl(format!("login:{}:blocked", username.to_lowercase())).await
+            .map_err(|e| RateLimitError::RedisError(e.to_string()))?;
+
+        // -2 / -1 mean there's no block (or one without expiry, which we never set)
+        if ttl > 0 {
+            return Err(RateLimitError::RateLimited(ttl as u64).into());
+        }
+
+        Ok(())
+    }
+
+    /// Every failure doubles the wait before the next attempt, reaching
+    /// `login_max_failures` locks the account for `login_lockout_duration`.
+    pub async fn register_login_failure(&self, username: &str) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+        let username = username.to_lowercase();
+        let failures_key = format!("login:{}:failures", username);
+        let blocked_key = format!("login:{}:blocked", username);
+
+        let (failures, _) = self.count(&failures_key, self.login_lockout_duration).await?;
+
+        let mut pipe = redis::pipe();
+
+        if failures >= self.login_max_failures {
+            // the lockout starts a fresh window once it's over
+            pipe.set_ex(&blocked_key, 1, self.login_lockout_duration)
+                .del(&failures_key);
+        } else {
+            let delay = 2u64.saturating_pow(failures - 1).min(MAX_LOGIN_DELAY);
+
+            pipe.set_ex(&blocked_key, 1, delay)
+                .expire(&failures_key, self.login_lockout_duration as i64);
+        }
+
+        let _: () = pipe.query_async(&mut conn).await
+            .map_err(|e| RateLimitError::RedisError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    pub async fn reset_login_failures(&self, username: &str) {
+        let mut conn = self.redis_service.get_connection();
+        let username = username.to_lowercase();
+
+        let _: RedisResult<()> = conn.del(&[format!("login:{}:failures", username), format!("login:{}:blocked", username)]).await;
+    }
+}
@@ -32,4 +32,176 @@ impl RedisService {
     pub fn get_connection(&self) -> MultiplexedConnection {
         self.connection.clone()
     }
-}
\ No newline at end of file
+}
+
+/// A stand-in Redis server for tests, keeping keys in memory. It speaks just enough
+/// RESP for the counters, codes and markers the services keep, and runs every script
+/// as `rate_limit_service::COUNT_SCRIPT`, the only one they use.
+#[cfg(test)]
+pub(crate) mod stub {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::RedisService;
+    use crate::config::config::Config;
+
+    type Store = Arc<Mutex<HashMap<String, (String, Option<Instant>)>>>;
+
+    enum Reply {
+        Status(&'static str),
+        Int(i64),
+        Bulk(Option<String>),
+        Array(Vec<Reply>),
+        Error(String),
+    }
+
+    impl Reply {
+        fn write(&self, out: &mut Vec<u8>) {
+            match self {
+                Reply::Status(status) => out.extend_from_slice(format!("+{}\r\n", status).as_bytes()),
+                Reply::Int(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+                Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+                Reply::Bulk(Some(value)) => out.extend_from_slice(format!("${}\r\n{}\r\n", value.len(), value).as_bytes()),
+                Reply::Error(message) => out.extend_from_slice(format!("-{}\r\n", message).as_bytes()),
+                Reply::Array(items) => {
+                    out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                    items.iter().for_each(|item| item.write(out));
+                },
+            }
+        }
+    }
+
+    /// Starts a stub server and connects a `RedisService` to it.
+    pub(crate) async fn redis_service() -> Arc<RedisService> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let store = Store::default();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let store = store.clone();
+                thread::spawn(move || serve(stream, store));
+            }
+        });
+
+        let config = Config { redis_url: format!("redis://{}", addr), ..Config::default() };
+        Arc::new(RedisService::new(&config).await.unwrap())
+    }
+
+    fn serve(stream: TcpStream, store: Store) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut queued: Option<Vec<Vec<String>>> = None; // inside MULTI
+
+        while let Some(command) = read_command(&mut reader) {
+            let name = command[0].to_uppercase();
+
+            let reply = match (name.as_str(), &mut queued) {
+                ("MULTI", _) => {
+                    queued = Some(Vec::new());
+                    Reply::Status("OK")
+                },
+                ("EXEC", Some(_)) => {
+                    let commands = queued.take().unwrap_or_default();
+                    Reply::Array(commands.iter().map(|command| run(&store, command)).collect())
+                },
+                (_, Some(commands)) => {
+                    commands.push(command);
+                    Reply::Status("QUEUED")
+                },
+                (_, None) => run(&store, &command),
+            };
+
+            let mut out = Vec::new();
+            reply.write(&mut out);
+
+            if writer.write_all(&out).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok().filter(|read| *read > 0)?;
+        let count: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+
+        (0..count).map(|_| {
+            let mut line = String::new();
+            reader.read_line(&mut line).ok()?;
+            let length: usize = line.trim().strip_prefix('$')?.parse().ok()?;
+
+            let mut value = vec![0; length + 2]; // with its CRLF
+            reader.read_exact(&mut value).ok()?;
+            value.truncate(length);
+
+            String::from_utf8(value).ok()
+        }).collect()
+    }
+
+    fn run(store: &Store, command: &[String]) -> Reply {
+        let mut store = store.lock().unwrap();
+        let now = Instant::now();
+        store.retain(|_, (_, expires_at)| expires_at.is_none_or(|expires_at| expires_at > now));
+
+        let arg = |i: usize| command.get(i).cloned().unwrap_or_default();
+        let seconds = |i: usize| Some(now + Duration::from_secs(arg(i).parse().unwrap_or(0)));
+        let ttl = |store: &HashMap<String, (String, Option<Instant>)>, key: &str| match store.get(key) {
+            None => -2,
+            Some((_, None)) => -1,
+            Some((_, Some(expires_at))) => expires_at.duration_since(now).as_secs_f64().ceil() as i64,
+        };
+
+        match command[0].to_uppercase().as_str() {
+            "PING" => Reply::Status("PONG"),
+            "CLIENT" | "SELECT" => Reply::Status("OK"),
+            "GET" => Reply::Bulk(store.get(&arg(1)).map(|(value, _)| value.clone())),
+            "EXISTS" => Reply::Int(command[1..].iter().filter(|key| store.contains_key(*key)).count() as i64),
+            "DEL" => Reply::Int(command[1..].iter().filter(|key| store.remove(*key).is_some()).count() as i64),
+            "TTL" => Reply::Int(ttl(&store, &arg(1))),
+            "SETEX" => {
+                store.insert(arg(1), (arg(3), seconds(2)));
+                Reply::Status("OK")
+            },
+            "SET" => {
+                let options: Vec<String> = command[3..].iter().map(|option| option.to_uppercase()).collect();
+
+                if options.contains(&"NX".to_string()) && store.contains_key(&arg(1)) {
+                    return Reply::Bulk(None);
+                }
+
+                let expires_at = options.iter().position(|option| option == "EX").and_then(|i| seconds(i + 4));
+                store.insert(arg(1), (arg(2), expires_at));
+                Reply::Status("OK")
+            },
+            "INCR" => {
+                let entry = store.entry(arg(1)).or_insert(("0".to_string(), None));
+                let count = entry.0.parse::<i64>().unwrap_or(0) + 1;
+                entry.0 = count.to_string();
+                Reply::Int(count)
+            },
+            "EXPIRE" => match store.get_mut(&arg(1)) {
+                Some(entry) => {
+                    entry.1 = seconds(2);
+                    Reply::Int(1)
+                },
+                None => Reply::Int(0),
+            },
+            "EVALSHA" | "EVAL" => {
+                // KEYS[1] is the counter, ARGV[1] its window
+                let (key, window) = (arg(3), seconds(4));
+                let entry = store.entry(key.clone()).or_insert(("0".to_string(), None));
+                let count = entry.0.parse::<i64>().unwrap_or(0) + 1;
+                entry.0 = count.to_string();
+                entry.1 = entry.1.or(window);
+
+                Reply::Array(vec![Reply::Int(count), Reply::Int(ttl(&store, &key))])
+            },
+            other => Reply::Error(format!("ERR unknown command '{}'", other)),
+        }
+    }
+}
@@ -7,27 +7,57 @@ use serde::{Serialize, Deserialize};
 use crate::config::config::Config;
 use crate::error::{Error as AppError, Result};
 use crate::services::redis_service::RedisService;
+use crate::services::token_service::TokenClaims;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct DeviceInfo {
     pub user_agent: String,
     pub ip_address: String,
     pub device_id: String,
+    pub enrolled_device: Option<EnrolledDevice>, // set when the client authenticated as an enrolled device
     pub last_active: DateTime<Utc>
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EnrolledDevice {
+    pub id: i64,
+    pub name: String,
+    pub store: String,
+    pub device_type: String,
+}
+
+// sessions stored before rotation existed lack `family_id` and `replaced_by`, see `SessionData::parse`
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SessionData {
     pub user_id: i64,
+    #[serde(default)]
+    pub family_id: String, // shared by every refresh token rotated from the same login
     pub device_info: DeviceInfo,
     pub is_valid: bool,
+    #[serde(default)]
+    pub replaced_by: Option<String>, // jti of the refresh token this one was rotated into
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>
 }
 
+impl SessionData {
+    /// Reads the session stored under `session:{token_id}`. An older session without
+    /// a family becomes the start of its own, so it can be rotated like any other.
+    fn parse(token_id: &str, json: &str) -> serde_json::Result<SessionData> {
+        let mut session_data: SessionData = serde_json::from_str(json)?;
+
+        if session_data.family_id.is_empty() {
+            session_data.family_id = token_id.to_string();
+        }
+
+        Ok(session_data)
+    }
+}
+
 pub struct SessionService {
     redis_service: Arc<RedisService>,
     session_expiry: u64, // in sec
+    access_token_expiry: u64, // in sec, the longest an access token of any kind stays valid
     invalid_session_retention: u64, // in sec
 }
 
@@ -39,6 +69,10 @@ pub enum SessionError {
     SessionExpired,
     #[error("Device mismatch")]
     DeviceMismatch,
+    #[error("Refresh token reuse detected")]
+    TokenReuseDetected,
+    #[error("Session '{0}' not found")]
+    SessionNotFound(String),
     #[error("Redis error: {0}")]
     RedisError(String),
     #[error("Serialization error: {0}")]
@@ -47,7 +81,10 @@ pub enum SessionError {
 
 impl From<SessionError> for AppError {
     fn from(error: SessionError) -> Self {
-        AppError::ServiceError(error.into())
+        match error {
+            SessionError::SessionNotFound(_) => AppError::ApiError(error.into()),
+            _ => AppError::ServiceError(error.into()),
+        }
     }
 }
 
@@ -56,16 +93,25 @@ impl SessionService {
         Self {
             redis_service,
             session_expiry: config.ref_token_expiry as u64,
-            invalid_session_retention: 86400
+            // revocation markers must outlive every access token they could apply to
+            access_token_expiry: [
+                config.acc_token_expiry,
+                config.cookie_acc_token_expiry,
+                config.pin_token_expiry,
+                config.impersonation_token_expiry
+            ].into_iter().max().unwrap_or_default() as u64,
+            // a retired refresh token has to be recognised for as long as it could be replayed
+            invalid_session_retention: (config.ref_token_expiry as u64).max(86400)
         }
     }
 
     async fn is_same_device(&self, stored: &DeviceInfo, current: &DeviceInfo) -> bool {
         stored.device_id == current.device_id && 
-        stored.user_agent == current.user_agent
+        stored.user_agent == current.user_agent &&
+        stored.enrolled_device.as_ref().map(|d| d.id) == current.enrolled_device.as_ref().map(|d| d.id)
     }
 
-    pub async fn create_session(&self, user_id: i64, device_info: DeviceInfo, token_id: &str) -> Result<()> {
+    pub async fn create_session(&self, user_id: i64, device_info: DeviceInfo, token_id: &str, session_id: &str) -> Result<()> {
         let mut conn = self.redis_service.get_connection();
 
         let now = Utc::now();
@@ -73,14 +119,17 @@ impl SessionService {
 
         let session_data = SessionData {
             user_id,
+            family_id: session_id.to_string(),
             device_info,
             is_valid: true,
+            replaced_by: None,
             created_at: now,
             expires_at: exp,
         };
         
         let session_key = format!("session:{}", token_id);
         let user_sessions_key = format!("user:{}:sessions", user_id);
+        let family_key = format!("session_family:{}", session_id);
 
         let session_json = serde_json::to_string(&session_data)
             .map_err(|e| SessionError::SerializationError(e.to_string()))?;
@@ -88,8 +137,15 @@ impl SessionService {
         let mut pipe = redis::pipe();
         pipe.atomic()
             .set_ex(&session_key, session_json, self.session_expiry)
+            .set_ex(&family_key, token_id, self.session_expiry)
             .sadd(&user_sessions_key, token_id)
             .expire(&user_sessions_key, self.session_expiry as i64);
+
+        if let Some(device) = &session_data.device_info.enrolled_device {
+            let device_sessions_key = format!("device:{}:sessions", device.id);
+            pipe.sadd(&device_sessions_key, token_id)
+                .expire(&device_sessions_key, self.session_expiry as i64);
+        }
         
         let _: () = pipe.query_async(&mut conn).await
             .map_err(|e| SessionError::RedisError(e.to_string()))?;
@@ -97,6 +153,29 @@ impl SessionService {
         Ok(())
     }
 
+    /// Looks a session up by its id (the family id carried in `TokenClaims.sid`),
+    /// returning the jti of its current refresh token alongside the session.
+    pub async fn get_session(&self, session_id: &str) -> Result<(String, SessionData)> {
+        let mut conn = self.redis_service.get_connection();
+
+        let family_key = format!("session_family:{}", session_id);
+
+        let token_id: String = conn.get(&family_key).await
+            .map_err(|_| SessionError::InvalidSessionToken)?;
+
+        let session_json: String = conn.get(format!("session:{}", token_id)).await
+            .map_err(|_| SessionError::InvalidSessionToken)?;
+
+        let session_data = SessionData::parse(&token_id, &session_json)
+            .map_err(|e| SessionError::SerializationError(e.to_string()))?;
+
+        if !session_data.is_valid || session_data.expires_at < Utc::now() {
+            return Err(SessionError::InvalidSessionToken.into());
+        }
+
+        Ok((token_id, session_data))
+    }
+
     pub async fn validate_session(&self, token_id: &str, current_device_info: DeviceInfo) -> Result<SessionData> {
         let mut conn = self.redis_service.get_connection();
         
@@ -105,7 +184,7 @@ impl SessionService {
         let session_json: String = conn.get(&session_key).await
             .map_err(|_| SessionError::InvalidSessionToken)?;
         
-        let mut session_data: SessionData = serde_json::from_str(&session_json)
+        let mut session_data = SessionData::parse(token_id, &session_json)
             .map_err(|e| SessionError::SerializationError(e.to_string()))?;
         
         if !session_data.is_valid {
@@ -136,6 +215,160 @@ impl SessionService {
         Ok(session_data)
     }
 
+    pub async fn rotate_session(&self, token_id: &str, new_token_id: &str, current_device_info: DeviceInfo) -> Result<SessionData> {
+        let mut conn = self.redis_service.get_connection();
+
+        let session_key = format!("session:{}", token_id);
+
+        let session_json: String = conn.get(&session_key).await
+            .map_err(|_| SessionError::InvalidSessionToken)?;
+
+        let mut session_data = SessionData::parse(token_id, &session_json)
+            .map_err(|e| SessionError::SerializationError(e.to_string()))?;
+
+        if !session_data.is_valid {
+            // an already rotated refresh token showing up again means it has been stolen,
+            // so everything that descends from the same login gets revoked
+            if session_data.replaced_by.is_some() {
+                self.revoke_session_family(session_data.user_id, &session_data.family_id).await?;
+                return Err(SessionError::TokenReuseDetected.into());
+            }
+
+            return Err(SessionError::InvalidSessionToken.into());
+        }
+
+        let now = Utc::now();
+        if session_data.expires_at < now {
+            return Err(SessionError::SessionExpired.into());
+        }
+
+        if !self.is_same_device(&session_data.device_info, &current_device_info).await {
+            return Err(SessionError::DeviceMismatch.into());
+        }
+
+        // claim the rotation first, so two requests racing with the same token can't both get a new one
+        let rotation_key = format!("session:{}:rotated", token_id);
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&rotation_key)
+            .arg(new_token_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(self.invalid_session_retention)
+            .query_async(&mut conn).await
+            .map_err(|e| SessionError::RedisError(e.to_string()))?;
+
+        if claimed.is_none() {
+            self.revoke_session_family(session_data.user_id, &session_data.family_id).await?;
+            return Err(SessionError::TokenReuseDetected.into());
+        }
+
+        let new_session_data = SessionData {
+            user_id: session_data.user_id,
+            family_id: session_data.family_id.clone(),
+            device_info: DeviceInfo {
+                ip_address: current_device_info.ip_address,
+                last_active: now,
+                ..session_data.device_info.clone()
+            },
+            is_valid: true,
+            replaced_by: None,
+            created_at: session_data.created_at,
+            expires_at: now + Duration::seconds(self.session_expiry as i64),
+        };
+
+        session_data.is_valid = false;
+        session_data.replaced_by = Some(new_token_id.to_string());
+
+        let retired_json = serde_json::to_string(&session_data)
+            .map_err(|e| SessionError::SerializationError(e.to_string()))?;
+        let new_session_json = serde_json::to_string(&new_session_data)
+            .map_err(|e| SessionError::SerializationError(e.to_string()))?;
+
+        let new_session_key = format!("session:{}", new_token_id);
+        let user_sessions_key = format!("user:{}:sessions", session_data.user_id);
+        let family_key = format!("session_family:{}", session_data.family_id);
+
+        // the retired session is kept around so a replay of it can still be recognized
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set_ex(&session_key, retired_json, self.invalid_session_retention)
+            .srem(&user_sessions_key, token_id)
+            .set_ex(&new_session_key, new_session_json, self.session_expiry)
+            .set_ex(&family_key, new_token_id, self.session_expiry)
+            .sadd(&user_sessions_key, new_token_id)
+            .expire(&user_sessions_key, self.session_expiry as i64);
+
+        if let Some(device) = &new_session_data.device_info.enrolled_device {
+            let device_sessions_key = format!("device:{}:sessions", device.id);
+            pipe.srem(&device_sessions_key, token_id)
+                .sadd(&device_sessions_key, new_token_id)
+                .expire(&device_sessions_key, self.session_expiry as i64);
+        }
+
+        let _: () = pipe.query_async(&mut conn).await
+            .map_err(|e| SessionError::RedisError(e.to_string()))?;
+
+        Ok(new_session_data)
+    }
+
+    pub async fn revoke_session_family(&self, user_id: i64, family_id: &str) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+
+        let user_sessions_key = format!("user:{}:sessions", user_id);
+
+        let session_ids: Vec<String> = conn.smembers(&user_sessions_key).await
+            .map_err(|e| SessionError::RedisError(e.to_string()))?;
+
+        for token_id in session_ids {
+            let session_key = format!("session:{}", token_id);
+
+            let session_json = match conn.get::<_, String>(&session_key).await {
+                Ok(json) => json,
+                Err(_) => continue
+            };
+
+            match SessionData::parse(&token_id, &session_json) {
+                Ok(session_data) if session_data.family_id == family_id => {
+                    self.invalidate_session(&token_id).await?;
+                },
+                _ => continue
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ends every session opened on an enrolled device, for when the device is revoked.
+    /// Returns how many there were.
+    pub async fn revoke_device_sessions(&self, device_id: i64) -> Result<usize> {
+        let mut conn = self.redis_service.get_connection();
+        let device_sessions_key = format!("device:{}:sessions", device_id);
+
+        let token_ids: Vec<String> = conn.smembers(&device_sessions_key).await
+            .map_err(|e| SessionError::RedisError(e.to_string()))?;
+
+        for token_id in &token_ids {
+            self.invalidate_session(token_id).await?;
+        }
+
+        let _: RedisResult<()> = conn.del(&device_sessions_key).await;
+
+        Ok(token_ids.len())
+    }
+
+    /// Revokes a single session of the user by its id. Sessions belonging to
+    /// someone else are reported as not found.
+    pub async fn revoke_user_session(&self, user_id: i64, session_id: &str) -> Result<()> {
+        let (token_id, session_data) = self.get_session(session_id).await
+            .map_err(|_| SessionError::SessionNotFound(session_id.to_string()))?;
+
+        if session_data.user_id != user_id {
+            return Err(SessionError::SessionNotFound(session_id.to_string()).into());
+        }
+
+        self.invalidate_session(&token_id).await
+    }
+
     pub async fn invalidate_session(&self, token_id: &str) -> Result<()> {
         let mut conn = self.redis_service.get_connection();
         
@@ -144,7 +377,7 @@ impl SessionService {
         let session_json: RedisResult<String> = conn.get(&session_key).await;
 
         if let Ok(json) = session_json {
-            match serde_json::from_str::<SessionData>(&json) {
+            match SessionData::parse(token_id, &json) {
                 Ok(mut session_data) => {
                     session_data.is_valid = false;
 
@@ -159,6 +392,10 @@ impl SessionService {
                     
                     let user_sessions_key = format!("user:{}:sessions", session_data.user_id);
                     pipe.srem(&user_sessions_key, token_id);
+
+                    // access tokens minted for this session must stop working right away too
+                    let revoked_session_key = format!("revoked:session:{}", session_data.family_id);
+                    pipe.set_ex(&revoked_session_key, 1, self.access_token_expiry);
                     
                     let _: () = pipe.query_async(&mut conn).await
                         .map_err(|e| SessionError::RedisError(e.to_string()))?;
@@ -194,7 +431,7 @@ impl SessionService {
                 continue;
             }
             
-            let parse_result = serde_json::from_str::<SessionData>(&json_result.unwrap());
+            let parse_result = SessionData::parse(&token_id, &json_result.unwrap());
             
             if parse_result.is_err() {
                 invalid_sessions.push(token_id);
@@ -236,17 +473,24 @@ impl SessionService {
             
             match conn.get::<_, String>(&session_key).await {
                 Ok(session_json) => {
-                    if let Ok(mut session_data) = serde_json::from_str::<SessionData>(&session_json) {
+                    if let Ok(mut session_data) = SessionData::parse(token_id, &session_json) {
                         session_data.is_valid = false;
                         if let Ok(updated_json) = serde_json::to_string(&session_data) {
                             pipe.set_ex(&session_key, updated_json, self.invalid_session_retention);
                         }
+
+                        let revoked_session_key = format!("revoked:session:{}", session_data.family_id);
+                        pipe.set_ex(&revoked_session_key, 1, self.access_token_expiry);
                     }
                 },
                 Err(_) => continue
             }
         }
 
+        // anything issued before this moment is rejected, even tokens not bound to a tracked session
+        let not_before_key = format!("user:{}:not_before", user_id);
+        pipe.set_ex(&not_before_key, Utc::now().timestamp(), self.access_token_expiry);
+
         let _: RedisResult<()> = pipe.query_async(&mut conn).await;
         
         let _: RedisResult<()> = conn.del(&user_sessions_key).await;
@@ -254,6 +498,61 @@ impl SessionService {
         Ok(())
     }
 
+    /// Ends every session of the user except `current_session_id`, e.g. after a
+    /// password change made from that session.
+    pub async fn logout_other_sessions(&self, user_id: i64, current_session_id: &str) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+
+        let token_ids: Vec<String> = conn.smembers(format!("user:{}:sessions", user_id)).await
+            .map_err(|e| SessionError::RedisError(e.to_string()))?;
+
+        for token_id in token_ids {
+            let session_json: RedisResult<String> = conn.get(format!("session:{}", token_id)).await;
+
+            let is_current = session_json.ok()
+                .and_then(|json| SessionData::parse(&token_id, &json).ok())
+                .is_some_and(|session_data| session_data.family_id == current_session_id);
+
+            if !is_current {
+                self.invalidate_session(&token_id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn revoke_access_token(&self, claims: &TokenClaims) -> Result<()> {
+        let mut conn = self.redis_service.get_connection();
+
+        // only needs to outlive the token itself
+        let remaining = (claims.exp - Utc::now().timestamp()).max(1) as u64;
+        let revoked_token_key = format!("revoked:access:{}", claims.jti);
+
+        let _: () = conn.set_ex(&revoked_token_key, 1, remaining).await
+            .map_err(|e| SessionError::RedisError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    pub async fn is_access_token_revoked(&self, claims: &TokenClaims) -> Result<bool> {
+        let mut conn = self.redis_service.get_connection();
+
+        let keys = [
+            format!("revoked:access:{}", claims.jti),
+            format!("revoked:session:{}", claims.sid),
+            format!("user:{}:not_before", claims.sub),
+        ];
+
+        let values: Vec<Option<i64>> = redis::cmd("MGET").arg(&keys[..]).query_async(&mut conn).await
+            .map_err(|e| SessionError::RedisError(e.to_string()))?;
+
+        if values[0].is_some() || values[1].is_some() {
+            return Ok(true);
+        }
+
+        Ok(values[2].is_some_and(|not_before| claims.iat < not_before))
+    }
+
     pub async fn update_session_activity(&self, token_id: &str, current_device_info: DeviceInfo) -> Result<()> {
         let mut conn = self.redis_service.get_connection();
         
@@ -262,7 +561,7 @@ impl SessionService {
         let session_json: String = conn.get(&session_key).await
             .map_err(|_| SessionError::InvalidSessionToken)?;
         
-        let mut session_data: SessionData = serde_json::from_str(&session_json)
+        let mut session_data = SessionData::parse(token_id, &session_json)
             .map_err(|e| SessionError::SerializationError(e.to_string()))?;
         
         if !session_data.is_valid {
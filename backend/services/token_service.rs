@@ -1,6 +1,14 @@
+use std::collections::HashMap;
+use std::fs;
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Serialize, Deserialize};
+use serde_json::json;
+use sha2::Sha256;
 use uuid::Uuid;
 use anyhow::anyhow;
 
@@ -8,51 +16,196 @@ use crate::config::config::Config;
 use crate::error::{Error, Result};
 use crate::models::user::User;
 
+// DER prefix of an Ed25519 SubjectPublicKeyInfo, the raw 32 byte key follows it
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: i64,
     pub role: String,
     pub exp: i64,
     pub iat: i64,
     pub jti: String,
-    pub token_type: String // "acc" / "ref"
+    pub sid: String, // id of the session (refresh token family) the token was minted for
+    pub token_type: String, // "acc" / "ref" / "mfa"
+    #[serde(default)]
+    pub ver: i32, // `User.token_version` at mint time, outdated tokens are rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor> // set when a SuperAdmin is acting as `sub`
+}
+
+/// The user really behind an impersonation token (RFC 8693 "act" claim).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: i64,
+}
+
+impl TokenClaims {
+    pub fn impersonator_id(&self) -> Option<i64> {
+        self.act.as_ref().map(|actor| actor.sub)
+    }
+}
+
+/// A manager's one-time approval for a cashier to perform a sensitive action (e.g. a
+/// void), bound to the cashier's session. Consumed by `access_middleware::require_override`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverrideClaims {
+    pub sub: i64, // the approving manager
+    pub cashier: i64,
+    pub action: String, // permission code of the approved action
+    pub exp: i64,
+    pub iat: i64,
+    pub jti: String,
+    pub sid: String,
+    pub token_type: String, // "ovr"
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub kid: String,
+    pub x: String,
+}
+
+/// HS256 key for one kind of internal token. Each kind gets its own key derived from
+/// `REF_TOKEN_SECRET`, so a token of one kind is never accepted as another.
+struct SecretKey {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl SecretKey {
+    fn derive(secret: &str, token_type: &str) -> Self {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+        mac.update(format!("teapos-{}-token", token_type).as_bytes());
+        let key = mac.finalize().into_bytes();
+
+        Self {
+            encoding: EncodingKey::from_secret(&key),
+            decoding: DecodingKey::from_secret(&key),
+        }
+    }
 }
 
 pub struct TokenService {
-    access_secret: String,
-    refresh_secret: String,
+    // access tokens are verified by other services too, so they're signed with an Ed25519 key
+    signing_key: EncodingKey,
+    signing_key_id: String,
+    verification_keys: HashMap<String, DecodingKey>,
+    jwks: Vec<Jwk>,
+
+    // refresh, MFA and override tokens never leave TeaPOS, a shared secret is enough for them
+    refresh_key: SecretKey,
+    mfa_key: SecretKey,
+    override_key: SecretKey,
     access_expiry: i64, // in sec
-    refresh_expiry: i64 // in sec
+    cookie_access_expiry: i64, // in sec
+    pin_access_expiry: i64, // in sec
+    refresh_expiry: i64, // in sec
+    mfa_expiry: i64, // in sec
+    impersonation_expiry: i64, // in sec
+    override_expiry: i64 // in sec
 }
 
 impl TokenService {
-    pub fn new(config: &Config) -> Self {
-        Self {
-            access_secret: config.acc_token_secret.clone(),
-            refresh_secret: config.ref_token_secret.clone(),
+    pub fn new(config: &Config) -> Result<Self> {
+        let private_pem = fs::read(&config.jwt_private_key_path)
+            .map_err(|e| Error::ConfigError(anyhow!("Failed to read JWT private key '{}': {}", config.jwt_private_key_path, e)))?;
+
+        let signing_key = EncodingKey::from_ed_pem(&private_pem)
+            .map_err(|e| Error::ConfigError(anyhow!("Invalid JWT private key '{}': {}", config.jwt_private_key_path, e)))?;
+
+        let mut verification_keys = HashMap::new();
+        let mut jwks = Vec::new();
+
+        // every configured public key stays valid for verification, so a rotated out key
+        // keeps working until the tokens signed with it have expired
+        for entry in config.jwt_public_keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (kid, path) = entry.split_once('=')
+                .ok_or_else(|| Error::ConfigError(anyhow!("Invalid JWT public key entry '{}', expected 'kid=path'", entry)))?;
+
+            let public_pem = fs::read_to_string(path)
+                .map_err(|e| Error::ConfigError(anyhow!("Failed to read JWT public key '{}': {}", path, e)))?;
+
+            let decoding_key = DecodingKey::from_ed_pem(public_pem.as_bytes())
+                .map_err(|e| Error::ConfigError(anyhow!("Invalid JWT public key '{}': {}", path, e)))?;
+
+            let raw_key = Self::ed25519_public_key(&public_pem)
+                .ok_or_else(|| Error::ConfigError(anyhow!("JWT public key '{}' is not an Ed25519 key", path)))?;
+
+            verification_keys.insert(kid.to_string(), decoding_key);
+            jwks.push(Jwk {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                alg: "EdDSA".to_string(),
+                key_use: "sig".to_string(),
+                kid: kid.to_string(),
+                x: URL_SAFE_NO_PAD.encode(raw_key),
+            });
+        }
+
+        if !verification_keys.contains_key(&config.jwt_key_id) {
+            return Err(Error::ConfigError(anyhow!("No JWT public key configured for the signing key '{}'", config.jwt_key_id)));
+        }
+
+        Ok(Self {
+            signing_key,
+            signing_key_id: config.jwt_key_id.clone(),
+            verification_keys,
+            jwks,
+            refresh_key: SecretKey::derive(&config.ref_token_secret, "ref"),
+            mfa_key: SecretKey::derive(&config.ref_token_secret, "mfa"),
+            override_key: SecretKey::derive(&config.ref_token_secret, "ovr"),
             access_expiry: config.acc_token_expiry,
-            refresh_expiry: config.ref_token_expiry
+            cookie_access_expiry: config.cookie_acc_token_expiry,
+            pin_access_expiry: config.pin_token_expiry,
+            refresh_expiry: config.ref_token_expiry,
+            mfa_expiry: config.mfa_token_expiry,
+            impersonation_expiry: config.impersonation_token_expiry,
+            override_expiry: config.override_token_expiry
+        })
+    }
+
+    fn ed25519_public_key(pem: &str) -> Option<Vec<u8>> {
+        let body: String = pem.lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+
+        let der = STANDARD.decode(body.trim()).ok()?;
+
+        if der.len() != ED25519_SPKI_PREFIX.len() + 32 || der[..ED25519_SPKI_PREFIX.len()] != ED25519_SPKI_PREFIX {
+            return None;
         }
+
+        Some(der[ED25519_SPKI_PREFIX.len()..].to_vec())
     }
 
-    pub fn generate_tokens(&self, user: &User) -> Result<(String, String)> {
-        let now = Utc::now();
+    fn sign_access_token(&self, claims: &TokenClaims) -> Result<String> {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.signing_key_id.clone());
 
-        let acc_claims = TokenClaims {
-            sub: user.id,
-            role: user.role.to_string(),
-            exp: (now + Duration::seconds(self.access_expiry)).timestamp(),
-            iat: now.timestamp(),
-            jti: Uuid::new_v4().to_string(),
-            token_type: "acc".to_string()
-        };
+        encode(&header, claims, &self.signing_key)
+            .map_err(|_| Error::ServiceError(anyhow!("Failed to generate access token")))
+    }
 
-        let acc_token = encode(
-            &Header::new(Algorithm::HS256),
-            &acc_claims,
-            &EncodingKey::from_secret(self.access_secret.as_bytes())
-        ).map_err(|_| Error::ServiceError(anyhow!("Failed to generate access token")))?;
+    pub fn generate_tokens(&self, user: &User, session_id: &str) -> Result<(String, String)> {
+        self.generate_tokens_with_access_expiry(user, session_id, self.access_expiry)
+    }
+
+    /// Same as `generate_tokens`, for browsers keeping the refresh token in a cookie. Their
+    /// access token only lives in memory and is replaced often, so it's shorter-lived.
+    pub fn generate_cookie_tokens(&self, user: &User, session_id: &str) -> Result<(String, String)> {
+        self.generate_tokens_with_access_expiry(user, session_id, self.cookie_access_expiry)
+    }
+
+    fn generate_tokens_with_access_expiry(&self, user: &User, session_id: &str, access_expiry: i64) -> Result<(String, String)> {
+        let now = Utc::now();
+
+        let acc_token = self.generate_access_token_with_expiry(user, session_id, access_expiry, None)?;
 
         let ref_claims = TokenClaims {
             sub: user.id,
@@ -60,24 +213,34 @@ impl TokenService {
             exp: (now + Duration::seconds(self.refresh_expiry)).timestamp(),
             iat: now.timestamp(),
             jti: Uuid::new_v4().to_string(),
-            token_type: "ref".to_string()
+            sid: session_id.to_string(),
+            token_type: "ref".to_string(),
+            act: None,
+            ver: user.token_version
         };
 
         let ref_token = encode(
             &Header::new(Algorithm::HS256),
             &ref_claims,
-            &EncodingKey::from_secret(self.refresh_secret.as_bytes())
+            &self.refresh_key.encoding
         ).map_err(|_| Error::ServiceError(anyhow!("Failed to generate refresh token")))?;
 
         Ok((acc_token, ref_token))
     }
 
     pub fn verify_access_token(&self, token: &str) -> Result<TokenClaims> {
-        let validation = Validation::new(Algorithm::HS256);
+        let header = decode_header(token)
+            .map_err(|_| Error::ServiceError(anyhow!("Invalid token")))?;
+
+        let decoding_key = header.kid
+            .and_then(|kid| self.verification_keys.get(&kid))
+            .ok_or_else(|| Error::ServiceError(anyhow!("Invalid token")))?;
+
+        let validation = Validation::new(Algorithm::EdDSA);
 
         let token_data = decode::<TokenClaims>(
             token,
-            &DecodingKey::from_secret(self.access_secret.as_bytes()),
+            decoding_key,
             &validation
         ).map_err(|e| {
             match e.kind() {
@@ -94,11 +257,44 @@ impl TokenService {
     }
 
     pub fn verify_refresh_token(&self, token: &str) -> Result<TokenClaims> {
+        self.verify_internal_token(token, "ref", &self.refresh_key)
+    }
+
+    /// Short-lived proof that the password step of a login succeeded, exchanged for
+    /// real tokens at `/auth/login/mfa` once the second factor checks out.
+    pub fn generate_mfa_token(&self, user: &User) -> Result<String> {
+        let now = Utc::now();
+
+        let mfa_claims = TokenClaims {
+            sub: user.id,
+            role: user.role.to_string(),
+            exp: (now + Duration::seconds(self.mfa_expiry)).timestamp(),
+            iat: now.timestamp(),
+            jti: Uuid::new_v4().to_string(),
+            sid: String::new(), // there's no session until the challenge is completed
+            token_type: "mfa".to_string(),
+            act: None,
+            ver: user.token_version
+        };
+
+        encode(
+            &Header::new(Algorithm::HS256),
+            &mfa_claims,
+            &self.mfa_key.encoding
+        ).map_err(|_| Error::ServiceError(anyhow!("Failed to generate MFA token")))
+    }
+
+    pub fn verify_mfa_token(&self, token: &str) -> Result<TokenClaims> {
+        self.verify_internal_token(token, "mfa", &self.mfa_key)
+    }
+
+    // refresh and MFA tokens are only ever read by TeaPOS itself
+    fn verify_internal_token(&self, token: &str, token_type: &str, key: &SecretKey) -> Result<TokenClaims> {
         let validation = Validation::new(Algorithm::HS256);
 
         let token_data = decode::<TokenClaims>(
             token,
-            &DecodingKey::from_secret(self.refresh_secret.as_bytes()),
+            &key.decoding,
             &validation
         ).map_err(|e| {
             match e.kind() {
@@ -107,31 +303,92 @@ impl TokenService {
             }
         })?;
 
-        if token_data.claims.token_type != "ref" {
+        if token_data.claims.token_type != token_type {
             return Err(Error::ServiceError(anyhow!("Invalid token type")));
         }
 
         Ok(token_data.claims)
     }
 
-    pub fn generate_access_token(&self, user: &User) -> Result<String> {
+    pub fn generate_access_token(&self, user: &User, session_id: &str) -> Result<String> {
+        self.generate_access_token_with_expiry(user, session_id, self.access_expiry, None)
+    }
+
+    /// Short-lived, non-refreshable access token for a staff member who switched onto
+    /// a shared terminal with their PIN. It's bound to the terminal's session, so
+    /// revoking that session logs everyone on the terminal out.
+    pub fn generate_pin_access_token(&self, user: &User, terminal_session_id: &str) -> Result<String> {
+        self.generate_access_token_with_expiry(user, terminal_session_id, self.pin_access_expiry, None)
+    }
+
+    /// Short-lived, non-refreshable access token that lets `actor_id` act as `user`.
+    /// It's bound to the actor's own session, so ending that session ends it too.
+    pub fn generate_impersonation_token(&self, user: &User, actor_id: i64, actor_session_id: &str) -> Result<(String, TokenClaims)> {
+        let token = self.generate_access_token_with_expiry(user, actor_session_id, self.impersonation_expiry, Some(Actor { sub: actor_id }))?;
+        let claims = self.verify_access_token(&token)?;
+
+        Ok((token, claims))
+    }
+
+    fn generate_access_token_with_expiry(&self, user: &User, session_id: &str, expiry: i64, act: Option<Actor>) -> Result<String> {
         let now = Utc::now();
 
         let acc_claims = TokenClaims {
             sub: user.id,
             role: user.role.to_string(),
-            exp: (now + Duration::seconds(self.access_expiry)).timestamp(),
+            exp: (now + Duration::seconds(expiry)).timestamp(),
+            iat: now.timestamp(),
+            jti: Uuid::new_v4().to_string(),
+            sid: session_id.to_string(),
+            token_type: "acc".to_string(),
+            act,
+            ver: user.token_version
+        };
+
+        self.sign_access_token(&acc_claims)
+    }
+
+    pub fn generate_override_token(&self, manager: &User, cashier_claims: &TokenClaims, action: &str) -> Result<(String, OverrideClaims)> {
+        let now = Utc::now();
+
+        let ovr_claims = OverrideClaims {
+            sub: manager.id,
+            cashier: cashier_claims.sub,
+            action: action.to_string(),
+            exp: (now + Duration::seconds(self.override_expiry)).timestamp(),
             iat: now.timestamp(),
             jti: Uuid::new_v4().to_string(),
-            token_type: "acc".to_string()
+            sid: cashier_claims.sid.clone(),
+            token_type: "ovr".to_string()
         };
 
-        let acc_token = encode(
+        let token = encode(
             &Header::new(Algorithm::HS256),
-            &acc_claims,
-            &EncodingKey::from_secret(self.access_secret.as_bytes())
-        ).map_err(|_| Error::ServiceError(anyhow!("Failed to generate access token")))?;
+            &ovr_claims,
+            &self.override_key.encoding
+        ).map_err(|_| Error::ServiceError(anyhow!("Failed to generate override token")))?;
+
+        Ok((token, ovr_claims))
+    }
+
+    pub fn verify_override_token(&self, token: &str) -> Result<OverrideClaims> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0; // the token is only meant to live for seconds
+
+        let token_data = decode::<OverrideClaims>(
+            token,
+            &self.override_key.decoding,
+            &validation
+        ).map_err(|_| Error::ServiceError(anyhow!("Invalid token")))?;
+
+        if token_data.claims.token_type != "ovr" {
+            return Err(Error::ServiceError(anyhow!("Invalid token type")));
+        }
+
+        Ok(token_data.claims)
+    }
 
-        Ok(acc_token)
+    pub fn jwks(&self) -> serde_json::Value {
+        json!({ "keys": self.jwks })
     }
 }
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::app::AppState;
+use crate::error::{Error, Result};
+use crate::models::audit_event::{self as audit, NewAuditEvent};
+use crate::models::staff_invite::StaffInvite;
+use crate::models::user::{NewUser, User, UserRole};
+use crate::services::message_service::send_invite;
+use crate::utils::{csv, phone, validation};
+
+const REQUIRED_COLUMNS: [&str; 4] = ["username", "fullname", "whatsapp", "role"];
+const EXPORT_COLUMNS: [&str; 7] = ["username", "fullname", "whatsapp", "role", "status", "whatsapp_verified", "created_at"];
+
+#[derive(Serialize, Debug)]
+pub struct RowError {
+    pub line: usize,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: usize,
+    pub created: usize,
+    pub invited: usize,
+    pub errors: Vec<RowError>,
+}
+
+impl ImportReport {
+    fn error(&mut self, line: usize, field: Option<&str>, message: impl Into<String>) {
+        self.errors.push(RowError { line, field: field.map(str::to_string), message: message.into() });
+    }
+}
+
+enum ImportAction {
+    Create(NewUser),
+    Invite { whatsapp: String, fullname: String, role: UserRole, store: Option<String> },
+}
+
+/// The records of an import file, with its columns looked up by name.
+struct Sheet {
+    columns: HashMap<String, usize>,
+    records: Vec<(usize, Vec<String>)>,
+}
+
+impl Sheet {
+    fn parse(input: &str) -> Result<Sheet> {
+        let mut records = csv::parse(input).map_err(|e| Error::ApiError(anyhow!(e)))?.into_iter();
+
+        let (_, names) = records.next().ok_or_else(|| Error::ApiError(anyhow!("The file is empty")))?;
+        let columns: HashMap<String, usize> = names.iter()
+            .enumerate()
+            .map(|(i, name)| (name.trim().to_lowercase(), i))
+            .collect();
+
+        if let Some(missing) = REQUIRED_COLUMNS.iter().find(|column| !columns.contains_key(**column)) {
+            return Err(Error::ApiError(anyhow!("Missing the '{}' column", missing)));
+        }
+
+        Ok(Sheet { columns, records: records.collect() })
+    }
+
+    fn field<'a>(&self, record: &'a [String], name: &str) -> &'a str {
+        self.columns.get(name)
+            .and_then(|&i| record.get(i))
+            .map(|field| csv::unguard(field)) // for files that came from an export
+            .unwrap_or("")
+    }
+}
+
+/// Usernames and WhatsApp numbers of the file that already belong to an account.
+#[derive(Default)]
+struct Taken {
+    usernames: HashSet<String>,
+    whatsapps: HashSet<String>,
+}
+
+impl Taken {
+    /// Looks them all up at once rather than once per row.
+    async fn lookup(sheet: &Sheet, default_country_code: &str, conn: &mut AsyncPgConnection) -> Result<Taken> {
+        let usernames: Vec<String> = sheet.records.iter()
+            .map(|(_, record)| sheet.field(record, "username").trim().to_string())
+            .filter(|username| !username.is_empty())
+            .collect();
+
+        let whatsapps: Vec<String> = sheet.records.iter()
+            .filter_map(|(_, record)| phone::normalize_e164(sheet.field(record, "whatsapp").trim(), default_country_code))
+            .collect();
+
+        Ok(Taken {
+            usernames: User::existing_usernames(&usernames, conn).await?.into_iter().collect(),
+            whatsapps: User::taken_whatsapps(&whatsapps, conn).await?.into_iter().collect(),
+        })
+    }
+}
+
+/// Checks every row of the file, collecting what is wrong with each of them into
+/// the report. Rows with a password become accounts, rows without one an invite.
+fn check_rows(sheet: &Sheet, taken: &Taken, caller_role: UserRole, default_country_code: &str, report: &mut ImportReport) -> Vec<(usize, ImportAction)> {
+    let mut usernames = HashSet::new();
+    let mut numbers = HashSet::new();
+    let mut actions = Vec::new();
+
+    for (line, record) in &sheet.records {
+        let line = *line;
+        report.rows += 1;
+        let errors_before = report.errors.len();
+
+        let field = |name: &str| sheet.field(record, name);
+
+        let username = field("username").trim();
+        let fullname = field("fullname").trim();
+        let password = field("password"); // taken as is, spaces are allowed in passwords
+        let store = field("store").trim();
+
+        if fullname.is_empty() || fullname.chars().count() > 255 {
+            report.error(line, Some("fullname"), "must be 1 to 255 characters");
+        }
+
+        let role = match field("role").trim().parse::<UserRole>() {
+            Ok(role) if !caller_role.has_at_least(role) => {
+                report.error(line, Some("role"), "can't be above your own role");
+                None
+            },
+            Ok(role) => Some(role),
+            Err(_) => {
+                report.error(line, Some("role"), "must be superadmin, admin, employee or user");
+                None
+            }
+        };
+
+        let whatsapp = match phone::normalize_e164(field("whatsapp").trim(), default_country_code) {
+            Some(whatsapp) if !numbers.insert(whatsapp.clone()) => {
+                report.error(line, Some("whatsapp"), format!("'{}' appears more than once in the file", whatsapp));
+                None
+            },
+            Some(whatsapp) if taken.whatsapps.contains(&whatsapp) => {
+                report.error(line, Some("whatsapp"), format!("'{}' is already in use", whatsapp));
+                None
+            },
+            Some(whatsapp) => Some(whatsapp),
+            None => {
+                report.error(line, Some("whatsapp"), "is not a valid phone number");
+                None
+            }
+        };
+
+        if password.is_empty() {
+            // the invitee picks their own username when accepting
+            if !username.is_empty() {
+                report.error(line, Some("username"), "must be left empty for an invite, the invitee picks it");
+            }
+
+            if role.is_some_and(|role| !role.has_at_least(UserRole::Employee)) {
+                report.error(line, Some("role"), "invites are for staff, give customers a password instead");
+            }
+        } else {
+            if let Err(e) = validation::validate_password(password) {
+                report.error(line, Some("password"), e.message.map(|m| m.to_string()).unwrap_or_else(|| "is invalid".to_string()));
+            }
+
+            if !(3..=32).contains(&username.chars().count()) {
+                report.error(line, Some("username"), "must be 3 to 32 characters");
+            } else if let Err(e) = validation::validate_username(username) {
+                report.error(line, Some("username"), e.message.map(|m| m.to_string()).unwrap_or_else(|| "is invalid".to_string()));
+            } else if !usernames.insert(username.to_string()) {
+                report.error(line, Some("username"), format!("'{}' appears more than once in the file", username));
+            } else if taken.usernames.contains(username) {
+                report.error(line, Some("username"), format!("'{}' already exists", username));
+            }
+        }
+
+        if report.errors.len() > errors_before {
+            continue;
+        }
+
+        let (Some(role), Some(whatsapp)) = (role, whatsapp) else { continue };
+
+        let action = if password.is_empty() {
+            ImportAction::Invite {
+                whatsapp,
+                fullname: fullname.to_string(),
+                role,
+                store: Some(store.to_string()).filter(|store| !store.is_empty()),
+            }
+        } else {
+            ImportAction::Create(NewUser {
+                username: username.to_string(),
+                fullname: fullname.to_string(),
+                password: password.to_string(),
+                whatsapp,
+                role,
+                whatsapp_verified_at: None
+            })
+        };
+
+        actions.push((line, action));
+    }
+
+    actions
+}
+
+/// Imports users from a CSV file, used by both the admin endpoint and the `--import-users`
+/// command. Rows are written in one transaction, so nothing is imported unless every row
+/// is valid and makes it in, and nothing at all on a dry run.
+pub async fn import_csv(
+    state: &AppState,
+    input: &str,
+    caller_role: UserRole,
+    actor_id: Option<i64>,
+    dry_run: bool,
+    event: &dyn Fn(&str) -> NewAuditEvent,
+    conn: &mut AsyncPgConnection
+) -> Result<ImportReport> {
+    let default_country_code = &state.config.default_country_code;
+
+    let sheet = Sheet::parse(input)?;
+    let taken = Taken::lookup(&sheet, default_country_code, conn).await?;
+
+    let mut report = ImportReport { dry_run, ..Default::default() };
+    let mut actions = check_rows(&sheet, &taken, caller_role, default_country_code, &mut report);
+
+    if dry_run || !report.errors.is_empty() {
+        return Ok(report);
+    }
+
+    // hashed up front, so the transaction isn't held open for every row's hash
+    for (_, action) in &mut actions {
+        if let ImportAction::Create(new_user) = action {
+            new_user.password = User::hash_password(&new_user.password)?;
+        }
+    }
+
+    // every row is written or none is, a row failing now (e.g. a username taken since
+    // the check) rolls the whole file back
+    let mut failed_line = None;
+    let failed = &mut failed_line;
+
+    let written = conn.transaction::<_, Error, _>(|conn| async move {
+        let mut users = Vec::new();
+        let mut invites = Vec::new();
+
+        for (line, action) in actions {
+            *failed = Some(line);
+
+            match action {
+                ImportAction::Create(new_user) => {
+                    users.push(User::create_and_return(new_user, conn).await?);
+                },
+                ImportAction::Invite { whatsapp, fullname, role, store } => {
+                    let (invite, code) = StaffInvite::create(whatsapp, fullname, role, store, state.config.invite_expiry, actor_id, conn).await?;
+                    invites.push((line, invite, code));
+                }
+            }
+        }
+
+        Ok((users, invites))
+    }.scope_boxed())
+    .await;
+
+    let (users, invites) = match (written, failed_line) {
+        (Ok(written), _) => written,
+        (Err(Error::DatabaseError(e)), _) => return Err(Error::DatabaseError(e)),
+        (Err(e), Some(line)) => {
+            report.error(line, None, format!("{}, nothing was imported", e));
+            return Ok(report);
+        },
+        (Err(e), None) => return Err(e),
+    };
+
+    for user in users {
+        report.created += 1;
+
+        let created = event(audit::USER_CREATE)
+            .target(user.id)
+            .details(json!({ "username": user.username, "role": user.role.to_string(), "source": "import" }));
+        state.audit_service.record(created, conn).await;
+    }
+
+    // invites only go out once the import is committed
+    for (line, invite, code) in invites {
+        if let Err(e) = send_invite(state.message_sender.as_ref(), &state.config, &invite, &code).await {
+            report.error(line, Some("whatsapp"), format!("Invite created but couldn't be sent ({}), revoke it and invite them again", e));
+        }
+
+        report.invited += 1;
+
+        let invited = event(audit::INVITE_CREATE)
+            .details(json!({ "invite_id": invite.id, "whatsapp": invite.whatsapp, "role": invite.role.to_string(), "store": invite.store, "source": "import" }));
+        state.audit_service.record(invited, conn).await;
+    }
+
+    let imported = event(audit::USER_IMPORT)
+        .details(json!({ "rows": report.rows, "created": report.created, "invited": report.invited, "errors": report.errors.len() }));
+    state.audit_service.record(imported, conn).await;
+
+    Ok(report)
+}
+
+/// Writes users in the import format, plus a few read-only columns the import ignores.
+pub fn export_csv(users: &[User]) -> String {
+    let mut out = String::new();
+    csv::write_record(&mut out, &EXPORT_COLUMNS);
+
+    for user in users {
+        csv::write_record(&mut out, &[
+            user.username.clone(),
+            user.fullname.clone(),
+            user.whatsapp.clone(),
+            user.role.to_string(),
+            user.status.to_string(),
+            user.is_whatsapp_verified().to_string(),
+            user.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        ]);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "username,fullname,whatsapp,role,password,store\n";
+    const PASSWORD: &str = "steeped-oolong-42";
+
+    fn dry_run(rows: &str, taken: &Taken, caller_role: UserRole) -> (ImportReport, Vec<(usize, ImportAction)>) {
+        let sheet = Sheet::parse(&format!("{}{}", HEADER, rows)).unwrap();
+        let mut report = ImportReport { dry_run: true, ..Default::default() };
+        let actions = check_rows(&sheet, taken, caller_role, "62", &mut report);
+        (report, actions)
+    }
+
+    fn errors(report: &ImportReport) -> Vec<(usize, Option<&str>)> {
+        report.errors.iter().map(|e| (e.line, e.field.as_deref())).collect()
+    }
+
+    #[test]
+    fn rows_with_a_password_become_accounts_and_the_rest_invites() {
+        let rows = format!("budi,Budi Santoso,0812 3456 7890,user,{PASSWORD},\n,Siti Aminah,+6281298765432,employee,,Bandung\n");
+        let (report, actions) = dry_run(&rows, &Taken::default(), UserRole::Admin);
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.rows, 2);
+
+        match &actions[..] {
+            [(2, ImportAction::Create(user)), (3, ImportAction::Invite { whatsapp, role, store, .. })] => {
+                assert_eq!(user.username, "budi");
+                assert_eq!(user.whatsapp, "+6281234567890");
+                assert_eq!(user.role, UserRole::User);
+                assert_eq!(whatsapp, "+6281298765432");
+                assert_eq!(*role, UserRole::Employee);
+                assert_eq!(store.as_deref(), Some("Bandung"));
+            },
+            _ => panic!("unexpected actions"),
+        }
+    }
+
+    #[test]
+    fn reports_each_problem_on_its_line() {
+        let rows = ",Nobody,12,cook,,\nx,Short Name,081234567891,user,short,\n";
+        let (report, actions) = dry_run(rows, &Taken::default(), UserRole::Admin);
+
+        assert!(actions.is_empty());
+        assert_eq!(errors(&report), vec![
+            (2, Some("role")),
+            (2, Some("whatsapp")),
+            (3, Some("password")),
+            (3, Some("username")),
+        ]);
+    }
+
+    #[test]
+    fn refuses_roles_above_the_caller() {
+        let rows = ",Boss,081234567890,superadmin,,\n";
+        let (report, actions) = dry_run(rows, &Taken::default(), UserRole::Admin);
+
+        assert!(actions.is_empty());
+        assert_eq!(report.errors[0].message, "can't be above your own role");
+    }
+
+    #[test]
+    fn invites_are_only_for_staff_and_leave_the_username_empty() {
+        let rows = ",Customer,081234567890,user,,\nsiti,Siti,081234567891,employee,,\n";
+        let (report, _) = dry_run(rows, &Taken::default(), UserRole::Admin);
+
+        assert_eq!(errors(&report), vec![(2, Some("role")), (3, Some("username"))]);
+    }
+
+    #[test]
+    fn catches_duplicates_within_the_file() {
+        let rows = format!("budi,Budi,081234567890,user,{PASSWORD},\nbudi,Budi Two,+6281234567890,user,{PASSWORD},\n");
+        let (report, actions) = dry_run(&rows, &Taken::default(), UserRole::Admin);
+
+        assert_eq!(actions.len(), 1);
+        assert_eq!(errors(&report), vec![(3, Some("whatsapp")), (3, Some("username"))]);
+    }
+
+    #[test]
+    fn catches_usernames_and_numbers_already_in_use() {
+        let taken = Taken {
+            usernames: HashSet::from(["budi".to_string()]),
+            whatsapps: HashSet::from(["+6281298765432".to_string()]),
+        };
+        let rows = format!("budi,Budi,081234567890,user,{PASSWORD},\n,Siti,081298765432,employee,,\n");
+        let (report, actions) = dry_run(&rows, &taken, UserRole::Admin);
+
+        assert!(actions.is_empty());
+        assert_eq!(report.errors[0].message, "'budi' already exists");
+        assert_eq!(report.errors[1].message, "'+6281298765432' is already in use");
+    }
+
+    #[test]
+    fn reads_back_an_export() {
+        let mut input = String::new();
+        csv::write_record(&mut input, &["username", "fullname", "whatsapp", "role", "password"]);
+        csv::write_record(&mut input, &["budi", "-Budi-", "+6281234567890", "user", PASSWORD]);
+
+        let sheet = Sheet::parse(&input).unwrap();
+        let mut report = ImportReport::default();
+        let actions = check_rows(&sheet, &Taken::default(), UserRole::Admin, "62", &mut report);
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        match &actions[..] {
+            [(2, ImportAction::Create(user))] => {
+                assert_eq!(user.fullname, "-Budi-");
+                assert_eq!(user.whatsapp, "+6281234567890");
+            },
+            _ => panic!("unexpected actions"),
+        }
+    }
+
+    #[test]
+    fn rejects_empty_files_and_missing_columns() {
+        let empty = Sheet::parse("\u{feff}\r\n").err().unwrap();
+        assert_eq!(empty.to_string(), "The file is empty");
+
+        let missing = Sheet::parse("username,fullname,role\n").err().unwrap();
+        assert_eq!(missing.to_string(), "Missing the 'whatsapp' column");
+    }
+}
\ No newline at end of file
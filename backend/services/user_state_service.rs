@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+
+use crate::config::config::Config;
+use crate::database::DbPool;
+use crate::error::{Error, Result};
+use crate::models::user::{User, UserStatus};
+use crate::services::redis_service::RedisService;
+use crate::services::token_service::TokenClaims;
+
+/// Checks that the user behind a token is still active and that the token wasn't
+/// minted before their last role or status change (`User.token_version`).
+///
+/// This runs on every authenticated request, so the state is cached in Redis
+/// (`user:{id}:state`) and only loaded from the database on a miss. The cache is
+/// dropped whenever the status or token version changes.
+pub struct UserStateService {
+    redis_service: Arc<RedisService>,
+    cache_ttl: u64, // in sec
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct UserState {
+    pub status: UserStatus,
+    pub token_version: i32,
+}
+
+impl UserStateService {
+    pub fn new(redis_service: Arc<RedisService>, config: &Config) -> Self {
+        Self {
+            redis_service,
+            cache_ttl: config.user_state_cache_ttl,
+        }
+    }
+
+    /// `None` once the user has been deleted.
+    pub async fn get(&self, user_id: i64, db_pool: &DbPool) -> Result<Option<UserState>> {
+        let mut redis_conn = self.redis_service.get_connection();
+        let cache_key = format!("user:{}:state", user_id);
+
+        let cached: Option<String> = redis_conn.get(&cache_key).await
+            .map_err(|e| Error::RedisError(anyhow!("Failed to read user state cache: {}", e)))?;
+
+        if let Some(state) = cached.and_then(|json| serde_json::from_str::<UserState>(&json).ok()) {
+            return Ok(Some(state));
+        }
+
+        // only take a connection from the pool when the cache can't answer
+        let mut conn = db_pool.get_connection().await?;
+
+        let Some((status, token_version)) = User::find_token_state(user_id, &mut conn).await? else {
+            return Ok(None);
+        };
+
+        let state = UserState { status, token_version };
+
+        if let Ok(json) = serde_json::to_string(&state) {
+            let _: RedisResult<()> = redis_conn.set_ex(&cache_key, json, self.cache_ttl).await;
+        }
+
+        Ok(Some(state))
+    }
+
+    pub async fn is_current(&self, claims: &TokenClaims, db_pool: &DbPool) -> Result<bool> {
+        let state = self.get(claims.sub, db_pool).await?;
+
+        Ok(state.is_some_and(|state| state.status == UserStatus::Active && state.token_version == claims.ver))
+    }
+
+    pub async fn invalidate(&self, user_id: i64) -> Result<()> {
+        let mut redis_conn = self.redis_service.get_connection();
+
+        let _: () = redis_conn.del(format!("user:{}:state", user_id)).await
+            .map_err(|e| Error::RedisError(anyhow!("Failed to clear user state cache: {}", e)))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use ntex::http::header::{self, HeaderMap};
+
+/// Refresh token of browsers in cookie mode, only ever sent to the refresh endpoint.
+pub const REFRESH_COOKIE: &str = "teapos_refresh";
+pub const REFRESH_COOKIE_PATH: &str = "/auth/refresh";
+
+/// CSRF token of browsers in cookie mode. Readable by the frontend, which has to echo
+/// it in `CSRF_HEADER` on every state-changing request (double-submit).
+pub const CSRF_COOKIE: &str = "teapos_csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Reads a cookie sent by the client.
+pub fn get(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get_all(header::COOKIE)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// `Set-Cookie` value for one of our cookies. They're always `SameSite=Strict`, a
+/// `max_age` of 0 removes the cookie.
+pub fn build(name: &str, value: &str, path: &str, max_age: i64, http_only: bool, secure: bool) -> String {
+    let mut cookie = format!("{}={}; Path={}; Max-Age={}; SameSite=Strict", name, value, path, max_age);
+
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+
+    if secure {
+        cookie.push_str("; Secure");
+    }
+
+    cookie
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(cookies: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for cookie in cookies {
+            headers.append(header::COOKIE, HeaderValue::from_static(cookie));
+        }
+
+        headers
+    }
+
+    #[test]
+    fn finds_a_cookie_among_others() {
+        let headers = headers(&["theme=dark; teapos_csrf=abc123 ; lang=id"]);
+
+        assert_eq!(get(&headers, CSRF_COOKIE).as_deref(), Some("abc123"));
+        assert_eq!(get(&headers, "lang").as_deref(), Some("id"));
+    }
+
+    #[test]
+    fn reads_every_cookie_header() {
+        let headers = headers(&["theme=dark", "teapos_refresh=r3fr3sh"]);
+
+        assert_eq!(get(&headers, REFRESH_COOKIE).as_deref(), Some("r3fr3sh"));
+    }
+
+    #[test]
+    fn missing_cookies_and_names_that_only_share_a_prefix_are_none() {
+        assert_eq!(get(&HeaderMap::new(), CSRF_COOKIE), None);
+        assert_eq!(get(&headers(&["teapos_csrf_old=abc123"]), CSRF_COOKIE), None);
+    }
+
+    #[test]
+    fn quotes_are_stripped() {
+        assert_eq!(get(&headers(&["teapos_csrf=\"abc123\""]), CSRF_COOKIE).as_deref(), Some("abc123"));
+    }
+
+    #[test]
+    fn empty_values_count_as_missing() {
+        assert_eq!(get(&headers(&["teapos_csrf="]), CSRF_COOKIE), None);
+        assert_eq!(get(&headers(&["teapos_csrf=\"\""]), CSRF_COOKIE), None);
+    }
+}
@@ -0,0 +1,211 @@
+/// First characters that make a spreadsheet read a field as a formula, see the
+/// OWASP CSV injection guidance.
+const FORMULA_PREFIXES: [char; 6] = ['=', '@', '+', '-', '\t', '\r'];
+
+/// Minimal RFC 4180 reader: comma separated, fields optionally wrapped in double
+/// quotes (`""` inside them is a literal quote), CRLF or LF line endings. Returns
+/// every record with the line it starts on, blank lines are skipped.
+pub fn parse(input: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input); // BOM written by spreadsheet apps
+
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                },
+                _ => field.push(c),
+            }
+
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+
+                if record.iter().any(|f| !f.is_empty()) {
+                    records.push((record_line, std::mem::take(&mut record)));
+                } else {
+                    record.clear();
+                }
+
+                line += 1;
+                record_line = line;
+            },
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Unterminated quoted field starting on line {}", record_line));
+    }
+
+    record.push(field);
+
+    if record.iter().any(|f| !f.is_empty()) {
+        records.push((record_line, record));
+    }
+
+    Ok(records)
+}
+
+/// Formats one record, quoting fields only where needed. Fields that a spreadsheet
+/// would run as a formula get a leading `'`, which `unguard` takes off again.
+pub fn write_record<S: AsRef<str>>(out: &mut String, fields: &[S]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+
+        let field = field.as_ref();
+        let field = if field.starts_with(FORMULA_PREFIXES) { format!("'{}", field) } else { field.to_string() };
+
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&field);
+        }
+    }
+
+    out.push_str("\r\n");
+}
+
+
+/// Takes off the `'` that `write_record` puts in front of formula-like fields, so
+/// exported files (WhatsApp numbers start with `+`) can be imported again.
+pub fn unguard(field: &str) -> &str {
+    match field.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(FORMULA_PREFIXES) => rest,
+        _ => field,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_plain_records() {
+        let records = parse("username,fullname\nbudi,Budi Santoso\n").unwrap();
+
+        assert_eq!(records, vec![
+            (1, record(&["username", "fullname"])),
+            (2, record(&["budi", "Budi Santoso"])),
+        ]);
+    }
+
+    #[test]
+    fn handles_crlf_line_endings_and_a_missing_final_newline() {
+        let records = parse("a,b\r\nc,d").unwrap();
+
+        assert_eq!(records, vec![(1, record(&["a", "b"])), (2, record(&["c", "d"]))]);
+    }
+
+    #[test]
+    fn strips_the_byte_order_mark() {
+        let records = parse("\u{feff}username,role\r\n").unwrap();
+
+        assert_eq!(records, vec![(1, record(&["username", "role"]))]);
+    }
+
+    #[test]
+    fn unescapes_doubled_quotes() {
+        let records = parse("\"say \"\"hi\"\"\",\"\"\"\"\n").unwrap();
+
+        assert_eq!(records, vec![(1, record(&["say \"hi\"", "\""]))]);
+    }
+
+    #[test]
+    fn keeps_commas_and_newlines_inside_quotes() {
+        let records = parse("\"Santoso, Budi\",\"line one\r\nline two\"\nnext,row\n").unwrap();
+
+        assert_eq!(records, vec![
+            (1, record(&["Santoso, Budi", "line one\r\nline two"])),
+            (3, record(&["next", "row"])), // numbered after the quoted newline
+        ]);
+    }
+
+    #[test]
+    fn skips_blank_lines_but_keeps_counting_them() {
+        let records = parse("a,b\n\n,\nc,d\n").unwrap();
+
+        assert_eq!(records, vec![(1, record(&["a", "b"])), (4, record(&["c", "d"]))]);
+    }
+
+    #[test]
+    fn keeps_empty_fields() {
+        let records = parse("a,,c,\n").unwrap();
+
+        assert_eq!(records, vec![(1, record(&["a", "", "c", ""]))]);
+    }
+
+    #[test]
+    fn rejects_an_unterminated_quote() {
+        let error = parse("a,b\n\"open,c\nd\n").unwrap_err();
+
+        assert_eq!(error, "Unterminated quoted field starting on line 2");
+    }
+
+    #[test]
+    fn quotes_only_where_needed() {
+        let mut out = String::new();
+        write_record(&mut out, &["plain", "with,comma", "with \"quote\"", "two\nlines", ""]);
+
+        assert_eq!(out, "plain,\"with,comma\",\"with \"\"quote\"\"\",\"two\nlines\",\r\n");
+    }
+
+    #[test]
+    fn guards_fields_a_spreadsheet_would_run() {
+        let mut out = String::new();
+        write_record(&mut out, &["=1+1", "@SUM(A1)", "+62812", "-2", "\tx", "\rx", "a=b"]);
+
+        assert_eq!(out, "'=1+1,'@SUM(A1),'+62812,'-2,'\tx,\"'\rx\",a=b\r\n");
+    }
+
+    #[test]
+    fn unguard_only_removes_the_guard() {
+        assert_eq!(unguard("'+62812"), "+62812");
+        assert_eq!(unguard("'=1+1"), "=1+1");
+        assert_eq!(unguard("'quoted'"), "'quoted'");
+        assert_eq!(unguard("+62812"), "+62812");
+    }
+
+    #[test]
+    fn written_records_read_back_the_same() {
+        let fields = ["Santoso, \"Budi\"", "+6281234567890", "multi\nline", "-", ""];
+
+        let mut out = String::new();
+        write_record(&mut out, &fields);
+        write_record(&mut out, &["last", "row", "", "", "x"]);
+
+        let records = parse(&out).unwrap();
+        let read: Vec<&str> = records[0].1.iter().map(|f| unguard(f)).collect();
+
+        assert_eq!(read, fields);
+        assert_eq!(records[1].0, 3);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod crypto;
+pub mod totp;
+pub mod phone;
+pub mod validation;
+pub mod cookie;
+pub mod csv;
\ No newline at end of file
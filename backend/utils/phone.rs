@@ -0,0 +1,72 @@
+/// Normalizes a phone number to E.164 (`+<country code><number>`). Numbers without
+/// an international prefix (`+` or `00`) are taken as national numbers of
+/// `default_country_code`, with their trunk `0` dropped, even when they happen to
+/// start with the same digits as the country code. Returns `None` for anything that
+/// can't be a number.
+pub fn normalize_e164(input: &str, default_country_code: &str) -> Option<String> {
+    let trimmed = input.trim();
+
+    // only the usual separators are allowed between the digits
+    if !trimmed.chars().all(|c| c.is_ascii_digit() || matches!(c, '+' | ' ' | '-' | '.' | '(' | ')')) {
+        return None;
+    }
+
+    if trimmed.chars().skip(1).any(|c| c == '+') {
+        return None;
+    }
+
+    let digits: String = trimmed.chars().filter(|c| c.is_ascii_digit()).collect();
+
+    let international = if trimmed.starts_with('+') {
+        digits
+    } else if let Some(rest) = digits.strip_prefix("00") {
+        rest.to_string()
+    } else if let Some(rest) = digits.strip_prefix('0') {
+        format!("{}{}", default_country_code, rest)
+    } else {
+        format!("{}{}", default_country_code, digits)
+    };
+
+    // E.164 allows at most 15 digits, and country codes never start with 0
+    if !(8..=15).contains(&international.len()) || international.starts_with('0') {
+        return None;
+    }
+
+    Some(format!("+{}", international))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_international_numbers() {
+        assert_eq!(normalize_e164("+62 812-3456-7890", "62").as_deref(), Some("+6281234567890"));
+        assert_eq!(normalize_e164("+1 (415) 555.0132", "62").as_deref(), Some("+14155550132"));
+        assert_eq!(normalize_e164("0062 812 3456 7890", "62").as_deref(), Some("+6281234567890"));
+    }
+
+    #[test]
+    fn prefixes_national_numbers_with_the_default_country_code() {
+        assert_eq!(normalize_e164("0812 3456 7890", "62").as_deref(), Some("+6281234567890"));
+        assert_eq!(normalize_e164("812 3456 7890", "62").as_deref(), Some("+6281234567890"));
+        assert_eq!(normalize_e164(" 020 7946 0958 ", "44").as_deref(), Some("+442079460958"));
+    }
+
+    #[test]
+    fn national_numbers_starting_with_the_country_code_stay_national() {
+        assert_eq!(normalize_e164("6201 2345 678", "62").as_deref(), Some("+6262012345678"));
+        assert_eq!(normalize_e164("4420 7946 0958", "44").as_deref(), Some("+44442079460958"));
+    }
+
+    #[test]
+    fn rejects_what_cant_be_a_number() {
+        assert_eq!(normalize_e164("", "62"), None);
+        assert_eq!(normalize_e164("not a number", "62"), None);
+        assert_eq!(normalize_e164("+62+812345678", "62"), None);
+        assert_eq!(normalize_e164("0812/3456/7890", "62"), None);
+        assert_eq!(normalize_e164("+0812345678", "62"), None);
+        assert_eq!(normalize_e164("+123456", "62"), None);
+        assert_eq!(normalize_e164("+1234567890123456", "62"), None);
+    }
+}
@@ -0,0 +1,95 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use validator::{ValidationError, ValidationErrors};
+
+use crate::config::config::Config;
+use crate::models::user::User;
+
+// anything longer only makes hashing slower, no real password needs it
+const PASSWORD_MAX_LENGTH: usize = 128;
+
+static COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+static PASSWORD_POLICY: OnceLock<PasswordPolicy> = OnceLock::new();
+
+pub struct PasswordPolicy {
+    min_length: usize,
+    block_common: bool,
+    common_passwords: HashSet<&'static str>,
+}
+
+impl PasswordPolicy {
+    fn new(min_length: usize, block_common: bool) -> Self {
+        Self {
+            min_length,
+            block_common,
+            common_passwords: COMMON_PASSWORDS.lines().map(str::trim).filter(|l| !l.is_empty()).collect(),
+        }
+    }
+}
+
+/// Sets the password policy up from the config. Validators used before this get the
+/// default policy.
+pub fn init_password_policy(config: &Config) {
+    let _ = PASSWORD_POLICY.set(PasswordPolicy::new(config.password_min_length, config.password_block_common));
+}
+
+fn password_policy() -> &'static PasswordPolicy {
+    PASSWORD_POLICY.get_or_init(|| PasswordPolicy::new(8, true))
+}
+
+fn error(code: &'static str, message: impl Into<Cow<'static, str>>) -> ValidationError {
+    ValidationError::new(code).with_message(message.into())
+}
+
+/// Errors for a single field, for checks that can only run inside the handler.
+pub fn field_error(field: &'static str, code: &'static str, message: &'static str) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add(field, error(code, message));
+    errors
+}
+
+pub fn validate_password(password: &str) -> Result<(), ValidationError> {
+    let policy = password_policy();
+    let length = password.chars().count();
+
+    if length < policy.min_length {
+        return Err(error("password_too_short", format!("must be at least {} characters", policy.min_length)));
+    }
+
+    if length > PASSWORD_MAX_LENGTH {
+        return Err(error("password_too_long", format!("must be at most {} characters", PASSWORD_MAX_LENGTH)));
+    }
+
+    if policy.block_common && policy.common_passwords.contains(password.to_lowercase().as_str()) {
+        return Err(error("password_too_common", "is too common, pick something harder to guess"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')) {
+        return Err(error("username_invalid", "may only contain letters, digits, '_', '.' and '-'"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_role_name(name: &str) -> Result<(), ValidationError> {
+    if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+        return Err(error("role_name_invalid", "may only contain lowercase letters, digits and '_'"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_pin(pin: &str) -> Result<(), ValidationError> {
+    if !User::is_valid_pin(pin) {
+        return Err(error("pin_invalid", "must be 4 to 6 digits"));
+    }
+
+    Ok(())
+}